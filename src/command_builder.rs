@@ -1,11 +1,6 @@
 use serenity::builder::CreateCommand;
 use serenity::builder::CreateCommandOption;
 use serenity::model::application::CommandOptionType;
-use serenity::model::prelude::User;
-
-pub mod name {
-    const MY_GLOBAL_STRING: &str = "My global constant string";
-}
 
 // -----------------
 // Base command set
@@ -16,8 +11,8 @@ pub mod name {
 pub mod base {
     use serenity::builder::{CreateCommand, CreateCommandOption};
     use serenity::model::application::CommandOptionType;
-
-    use crate::db::model::GameMode;
+    use serenity::model::channel::ChannelType;
+    use serenity::model::permissions::Permissions;
 
     pub fn build_help() -> CreateCommand {
         CreateCommand::new("help").description("Show the manual for this bot")
@@ -66,136 +61,484 @@ pub mod base {
             .add_option(player_count_option)
     }
 
-    pub fn build_delmod(game_modes: &Vec<GameMode>) -> CreateCommand {
-        let mut game_mode_option = CreateCommandOption::new(
+    pub fn build_delmod() -> CreateCommand {
+        let game_mode_option = CreateCommandOption::new(
             CommandOptionType::String,
             "game_mode",
             "The label of the game mode you want to delete",
         )
+        .set_autocomplete(true)
         .required(true);
 
-        // load existing game modes
-        for existing_game_mode in game_modes {
-            game_mode_option = game_mode_option
-                .add_string_choice(&existing_game_mode.label, &existing_game_mode.label);
-        }
-
         CreateCommand::new("delmod")
             .description("Delete an existing game mode")
             .add_option(game_mode_option)
     }
 
+    /// Options which are left out are not changed. Text options can be cleared with `-`.
+    pub fn build_editmod() -> CreateCommand {
+        let game_mode_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "game_mode",
+            "The label of the game mode you want to edit",
+        )
+        .set_autocomplete(true)
+        .required(true);
+
+        let label_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "label",
+            "New name of the game mode",
+        );
+
+        let player_count_option = CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "player_count",
+            "Number of players required to fill the game mode. Must be even, minimum 2, maximum 24",
+        )
+        .min_int_value(2)
+        .max_int_value(24);
+
+        let description_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "description",
+            "Shown in /list. Use - to clear",
+        )
+        .max_length(100);
+
+        let aliases_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "aliases",
+            "Other names for the game mode, separated by commas. Use - to clear",
+        );
+
+        let emoji_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "emoji",
+            "Shown before the label. Use - to clear",
+        );
+
+        let max_queue_hours_option = CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "max_queue_hours",
+            "Hours before queued players are removed. Use 0 for the bot's default",
+        )
+        .min_int_value(0)
+        .max_int_value(168);
+
+        let max_party_size_option = CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "max_party_size",
+            "Largest party which can join. Use 0 for the bot's default",
+        )
+        .min_int_value(0)
+        .max_int_value(12);
+
+        let disabled_option = CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "disabled",
+            "Disabled game modes cannot be joined, and are left out of /list",
+        );
+
+        CreateCommand::new("editmod")
+            .description("Edit an existing game mode")
+            .add_option(game_mode_option)
+            .add_option(label_option)
+            .add_option(player_count_option)
+            .add_option(description_option)
+            .add_option(aliases_option)
+            .add_option(emoji_option)
+            .add_option(max_queue_hours_option)
+            .add_option(max_party_size_option)
+            .add_option(disabled_option)
+    }
+
     pub fn build_list() -> CreateCommand {
         CreateCommand::new("list").description("Show available game modes and queued players")
     }
 
-    pub fn build_last(game_modes: &Vec<GameMode>) -> CreateCommand {
+    pub fn build_last() -> CreateCommand {
         let history_count_option = CreateCommandOption::new(CommandOptionType::Integer, "match_age", "How many steps/matches to traverse into match history when searching for a match to display");
 
-        let game_mode_option = generate_command_option_game_mode(&game_modes, false);
+        let game_mode_option = generate_command_option_game_mode(false);
 
         CreateCommand::new("last")
             .description("Display info about a previous pug. You can filter results by game mode.")
             .add_option(history_count_option)
             .add_option(game_mode_option)
+            .add_option(generate_command_option_season())
     }
 
-    /// The join command only has one option, which is required.
-    ///
-    /// The choices are game mode labels that are obtained from the [`Vec<GameMode>`] provided
-    /// to this function. No choices are added to the option if the [`Vec`] is empty.
-    pub fn build_join(game_modes: &Vec<GameMode>) -> CreateCommand {
+    /// The join command only has one option, a game mode, which is suggested by autocomplete.
+    pub fn build_join() -> CreateCommand {
         // !FIXME: The planned behavior for join is for the game mode option to be optional,
         // so that when it is ommited, the user is added to all available game modes
         // Therefore, join handler should be updated accordingly. and `false` passed to the following function
-        let game_mode_option = generate_command_option_game_mode(&game_modes, false);
+        let game_mode_option = generate_command_option_game_mode(false);
         CreateCommand::new("join")
             .description("Add yourself to all game mode queues, or one you specify")
             .add_option(game_mode_option)
     }
 
-    /// The leave command only has one option, which is required.
-    ///
-    /// The choices are game mode labels that are obtained from the [`Vec<GameMode>`] provided
-    /// to this function. No choices are added to the option if the [`Vec`] is empty.
-    pub fn build_leave(game_modes: &Vec<GameMode>) -> CreateCommand {
-        let game_mode_option = generate_command_option_game_mode(&game_modes, false);
+    /// The leave command only has one option, a game mode, which is suggested by autocomplete.
+    pub fn build_leave() -> CreateCommand {
+        let game_mode_option = generate_command_option_game_mode(false);
         CreateCommand::new("leave")
             .description("Remove yourself from all game mode queues, or one you specify")
             .add_option(game_mode_option)
     }
 
-    pub fn build_addplayer(game_modes: &Vec<GameMode>) -> CreateCommand {
+    pub fn build_addplayer() -> CreateCommand {
         let user_option =
             CreateCommandOption::new(CommandOptionType::User, "user", "Which user to add")
                 .required(true);
-        let mut game_mode_option = CreateCommandOption::new(
+        let game_mode_option = CreateCommandOption::new(
             CommandOptionType::String,
             "game_mode",
             "Which game mode queue you want to add the user to",
         )
+        .set_autocomplete(true)
         .required(true);
 
-        // load existing game modes
-        for existing_game_mode in game_modes {
-            game_mode_option = game_mode_option
-                .add_string_choice(&existing_game_mode.label, &existing_game_mode.label);
-        }
-
         CreateCommand::new("addplayer")
             .description("Add a user to the queue for a game mode")
             .add_option(user_option)
             .add_option(game_mode_option)
     }
 
-    pub fn build_delplayer(game_modes: &Vec<GameMode>) -> CreateCommand {
+    pub fn build_delplayer() -> CreateCommand {
         let user_option =
             CreateCommandOption::new(CommandOptionType::User, "user", "Which user to remove")
                 .required(true);
-        let mut game_mode_option = CreateCommandOption::new(
+        let game_mode_option = CreateCommandOption::new(
             CommandOptionType::String,
             "game_mode",
             "Which game mode queue you want to remove the user from",
         )
+        .set_autocomplete(true)
         .required(true);
 
-        // load existing game modes
-        for existing_game_mode in game_modes {
-            game_mode_option = game_mode_option
-                .add_string_choice(&existing_game_mode.label, &existing_game_mode.label);
-        }
-
         CreateCommand::new("delplayer")
             .description("Remove a user from the queue of a game mode")
             .add_option(user_option)
             .add_option(game_mode_option)
     }
 
-    /// The join command only has one option, which is required.
-    /// This helper builds that option.
-    ///
-    /// The choices are game mode labels that are obtained from the [`Vec<GameMode>`] provided
-    /// to this function. No choices are added to the option if the [`Vec`] is empty.
+    pub fn build_notify() -> CreateCommand {
+        let game_mode_option = generate_command_option_game_mode(true);
+
+        let threshold_option = CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "threshold",
+            "Alert me when this many players are queued. Leave out to stop alerts for this game mode",
+        )
+        .min_int_value(1);
+
+        let delivery_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "delivery",
+            "How you want to be alerted (default: direct message)",
+        )
+        .add_string_choice("Direct message", "dm")
+        .add_string_choice("Mention in pug channel", "mention");
+
+        let quiet_start_option = CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "quiet_start",
+            "Hour of day (UTC) from which you do not want alerts",
+        )
+        .min_int_value(0)
+        .max_int_value(23);
+
+        let quiet_end_option = CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "quiet_end",
+            "Hour of day (UTC) at which alerts resume",
+        )
+        .min_int_value(0)
+        .max_int_value(23);
+
+        CreateCommand::new("notify")
+            .description("Get alerted when a game mode's queue is about to fill")
+            .add_option(game_mode_option)
+            .add_option(threshold_option)
+            .add_option(delivery_option)
+            .add_option(quiet_start_option)
+            .add_option(quiet_end_option)
+    }
+
+    pub fn build_stats() -> CreateCommand {
+        let user_option = CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "Whose statistics to show (default: yourself)",
+        );
+        let game_mode_option = generate_command_option_game_mode(false);
+
+        CreateCommand::new("stats")
+            .description("Show pug statistics for a player. You can filter results by game mode.")
+            .add_option(user_option)
+            .add_option(game_mode_option)
+            .add_option(generate_command_option_season())
+    }
+
+    pub fn build_leaderboard() -> CreateCommand {
+        let game_mode_option = generate_command_option_game_mode(true);
+
+        let metric_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "metric",
+            "What to rank players by (default: games played)",
+        )
+        .add_string_choice("Games played", "games")
+        .add_string_choice("Wins", "wins")
+        .add_string_choice("Win rate", "winrate")
+        .add_string_choice("Times captained", "captained");
+
+        let page_option =
+            CreateCommandOption::new(CommandOptionType::Integer, "page", "Page to start on")
+                .min_int_value(1);
+
+        let from_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "from",
+            "Only count pugs played on or after this date (YYYY-MM-DD)",
+        );
+
+        let to_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "to",
+            "Only count pugs played on or before this date (YYYY-MM-DD)",
+        );
+
+        CreateCommand::new("leaderboard")
+            .description("Show the top players of a game mode")
+            .add_option(game_mode_option)
+            .add_option(metric_option)
+            .add_option(page_option)
+            .add_option(generate_command_option_season())
+            .add_option(from_option)
+            .add_option(to_option)
+    }
+
+    pub fn build_season() -> CreateCommand {
+        let name_option =
+            CreateCommandOption::new(CommandOptionType::String, "name", "Name of the new season")
+                .max_length(50)
+                .required(true);
+
+        let start_subcommand =
+            CreateCommandOption::new(CommandOptionType::SubCommand, "start", "Start a new season")
+                .add_sub_option(name_option);
+
+        let end_subcommand = CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "end",
+            "End the current season and archive its standings",
+        );
+
+        CreateCommand::new("season")
+            .description("Manage competitive seasons")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .add_option(start_subcommand)
+            .add_option(end_subcommand)
+    }
+
+    pub fn build_map() -> CreateCommand {
+        let game_mode_option = || {
+            CreateCommandOption::new(CommandOptionType::String, "game_mode", "The game mode")
+                .set_autocomplete(true)
+                .required(true)
+        };
+        let map_option = || {
+            CreateCommandOption::new(CommandOptionType::String, "map", "Name of the map")
+                .max_length(50)
+                .required(true)
+        };
+
+        let add_subcommand = CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "add",
+            "Add a map to a game mode's map pool",
+        )
+        .add_sub_option(game_mode_option())
+        .add_sub_option(map_option());
+
+        let remove_subcommand = CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "remove",
+            "Remove a map from a game mode's map pool",
+        )
+        .add_sub_option(game_mode_option())
+        .add_sub_option(map_option());
+
+        let list_subcommand = CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "list",
+            "Show the map pools of all game modes, or one you specify",
+        )
+        .add_sub_option(
+            CreateCommandOption::new(CommandOptionType::String, "game_mode", "The game mode")
+                .set_autocomplete(true),
+        );
+
+        CreateCommand::new("map")
+            .description("Manage the maps voted on once teams are picked")
+            .add_option(add_subcommand)
+            .add_option(remove_subcommand)
+            .add_option(list_subcommand)
+    }
+
+    pub fn build_party() -> CreateCommand {
+        let invite_subcommand = CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "invite",
+            "Invite a player to queue with you",
+        )
+        .add_sub_option(
+            CreateCommandOption::new(CommandOptionType::User, "user", "Player to invite")
+                .required(true),
+        );
+
+        let accept_subcommand = CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "accept",
+            "Join a party you were invited to",
+        )
+        .add_sub_option(CreateCommandOption::new(
+            CommandOptionType::User,
+            "from",
+            "Who invited you (needed if you have several invites)",
+        ));
+
+        let leave_subcommand =
+            CreateCommandOption::new(CommandOptionType::SubCommand, "leave", "Leave your party");
+
+        let disband_subcommand = CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "disband",
+            "Break up the party you lead",
+        );
+
+        CreateCommand::new("party")
+            .description("Queue with friends, and play on the same team")
+            .add_option(invite_subcommand)
+            .add_option(accept_subcommand)
+            .add_option(leave_subcommand)
+            .add_option(disband_subcommand)
+    }
+
+    pub fn build_report() -> CreateCommand {
+        let result_option =
+            CreateCommandOption::new(CommandOptionType::String, "result", "How your team did")
+                .add_string_choice("Win", "win")
+                .add_string_choice("Loss", "loss")
+                .add_string_choice("Draw", "draw")
+                .required(true);
+
+        let score_option =
+            CreateCommandOption::new(CommandOptionType::String, "score", "Final score, e.g. 5-3")
+                .max_length(20);
+
+        CreateCommand::new("report")
+            .description("Report the result of your pug, for the other captain to confirm")
+            .add_option(result_option)
+            .add_option(score_option)
+    }
+
+    pub fn build_setresult() -> CreateCommand {
+        let result_option =
+            CreateCommandOption::new(CommandOptionType::String, "result", "Which team won")
+                .add_string_choice("Red team won", "red")
+                .add_string_choice("Blue team won", "blue")
+                .add_string_choice("Draw", "draw")
+                .required(true);
+
+        let score_option =
+            CreateCommandOption::new(CommandOptionType::String, "score", "Final score, e.g. 5-3")
+                .max_length(20);
+
+        let thread_option = CreateCommandOption::new(
+            CommandOptionType::Channel,
+            "thread",
+            "Thread of the pug (default: this thread)",
+        )
+        .channel_types(vec![ChannelType::PublicThread]);
+
+        CreateCommand::new("setresult")
+            .description("Set or change the result of a pug")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .add_option(result_option)
+            .add_option(score_option)
+            .add_option(thread_option)
+    }
+
+    pub fn build_incident() -> CreateCommand {
+        let id_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "id",
+            "Incident ID, as given in an error reply",
+        )
+        .required(true);
+
+        CreateCommand::new("incident")
+            .description("View the details of an incident (superusers only)")
+            .default_member_permissions(Permissions::ADMINISTRATOR)
+            .add_option(id_option)
+    }
+
+    pub fn build_export() -> CreateCommand {
+        let game_mode_option = generate_command_option_game_mode(false);
+
+        let from_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "from",
+            "Export pugs played on or after this date (YYYY-MM-DD)",
+        )
+        .required(true);
+
+        let to_option = CreateCommandOption::new(
+            CommandOptionType::String,
+            "to",
+            "Export pugs played on or before this date (YYYY-MM-DD)",
+        )
+        .required(true);
+
+        let format_option =
+            CreateCommandOption::new(CommandOptionType::String, "format", "File format")
+                .add_string_choice("CSV", "csv")
+                .add_string_choice("JSON", "json")
+                .required(true);
+
+        CreateCommand::new("export")
+            .description("Export match history of one or all game modes as a file")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .add_option(from_option)
+            .add_option(to_option)
+            .add_option(format_option)
+            .add_option(game_mode_option)
+    }
+
+    /// Builds an optional option for filtering results to those of a single season.
+    pub fn generate_command_option_season() -> CreateCommandOption {
+        CreateCommandOption::new(
+            CommandOptionType::String,
+            "season",
+            "Only count pugs played during this season",
+        )
+    }
+
+    /// Builds a game mode option, which suggests game mode labels as the user types.
     ///
-    /// This functionality lives in a separate helper function so it might be reusable.
-    // !TODO: since this is apparently no longer useful outside this module. should it be deleted?
-    pub fn generate_command_option_game_mode(
-        game_modes: &Vec<GameMode>,
-        is_value_required: bool,
-    ) -> CreateCommandOption {
-        let mut game_mode_option = CreateCommandOption::new(
+    /// Since suggestions are not enforced, handlers must still check that the game mode exists.
+    pub fn generate_command_option_game_mode(is_value_required: bool) -> CreateCommandOption {
+        CreateCommandOption::new(
             CommandOptionType::String,
             "game_mode",
-            "You can type-to-search for more if you don't see all choices",
+            "Type to search for a game mode",
         )
-        .required(is_value_required);
-        for game_mode in game_modes {
-            // !TODO: verify that having no game modes, and thus no choices to add, does not result
-            // in arbitrary strings allowed as input
-            game_mode_option =
-                game_mode_option.add_string_choice(&game_mode.label, &game_mode.label);
-        }
-        game_mode_option
+        .set_autocomplete(true)
+        .required(is_value_required)
     }
 }
 
@@ -216,20 +559,17 @@ pub fn build_nocaptain() -> CreateCommand {
     CreateCommand::new("nocaptain").description("Exclude yourself from random captain selection")
 }
 
-/// Create a /pick command using a player list to create options.
-pub fn build_pick(players: &Vec<User>) -> CreateCommand {
-    let mut player_option = CreateCommandOption::new(
+/// Create a /pick command. The players left to pick are suggested by autocomplete,
+/// with their user ids as values.
+pub fn build_pick() -> CreateCommand {
+    let player_option = CreateCommandOption::new(
         CommandOptionType::String,
         "player",
         "A user you want to pick for your team",
     )
+    .set_autocomplete(true)
     .required(true);
 
-    for player in players {
-        // Sending the number itself seems to not work, so converting to string
-        player_option = player_option.add_string_choice(&player.name, player.id.get().to_string());
-    }
-
     CreateCommand::new("pick")
         .description("Choose a player for your team")
         .add_option(player_option)
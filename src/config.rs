@@ -0,0 +1,351 @@
+//! Typed configuration of the bot.
+//!
+//! Values are read from a TOML file (`rusty_bot.toml`, or the path in `RUSTY_BOT_CONFIG`),
+//! then overridden by environment variables (which may be set in a `.env` file).
+//! See `rusty_bot.example.toml` for every setting and the environment variable overriding it.
+//!
+//! Everything is validated at startup, and all problems are reported together.
+//! Once loaded, the config is shared through the serenity `TypeMap` under [`ConfigRef`].
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::io::ErrorKind;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serenity::client::Context;
+use serenity::model::id::{ChannelId, UserId};
+use serenity::prelude::TypeMapKey;
+
+use crate::error::ConfigError;
+use crate::utils::ops::OpsEvent;
+
+const DEFAULT_PATH: &str = "rusty_bot.toml";
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discord: DiscordConfig,
+    pub mongo: MongoConfig,
+    pub migration: MigrationConfig,
+    pub ops: OpsConfig,
+    pub status_server: StatusServerConfig,
+    pub pugs: PugsConfig,
+    pub incidents: IncidentsConfig,
+    pub shutdown: ShutdownConfig,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    /// `DISCORD_TOKEN`
+    pub token: String,
+    /// Users treated as superusers, besides the bot's owner. `SUPERUSERS`
+    pub superusers: Vec<UserId>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MongoConfig {
+    /// Connection string. `MONGO_URI`
+    pub uri: String,
+    /// How many seconds to keep pinging the database at startup before giving up.
+    /// `MONGO_READY_MAX_WAIT`
+    pub ready_max_wait_secs: u64,
+}
+
+impl Default for MongoConfig {
+    fn default() -> Self {
+        MongoConfig {
+            uri: String::default(),
+            ready_max_wait_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MigrationConfig {
+    /// Only log what pending migrations would change. `MIGRATION_DRY_RUN`
+    pub dry_run: bool,
+}
+
+/// Where ops events are reported, and which of them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpsConfig {
+    /// Channel to post reports in. `OPS_CHANNEL_ID`
+    pub channel_id: Option<ChannelId>,
+    /// Users to send reports to by direct message. `OPS_RECIPIENTS`
+    pub recipients: Vec<UserId>,
+    /// `OPS_EVENTS`
+    pub events: HashSet<OpsEvent>,
+    /// CPU load average (in percent) above which a load alert fires. `OPS_CPU_LOAD_LIMIT`
+    pub cpu_load_limit: f64,
+    /// Free memory (in MB) below which a load alert fires. `OPS_MIN_FREE_MEMORY_MB`
+    pub min_free_memory_mb: f64,
+}
+
+impl Default for OpsConfig {
+    fn default() -> Self {
+        OpsConfig {
+            channel_id: None,
+            recipients: Vec::default(),
+            events: OpsEvent::ALL.into_iter().collect(),
+            cpu_load_limit: 80.0,
+            min_free_memory_mb: 200.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusServerConfig {
+    /// Address to serve `/healthz` and `/metrics` on, if any. `STATUS_SERVER_ADDR`
+    pub addr: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PugsConfig {
+    /// How long players get to captain a pug themselves before captains are picked at random.
+    /// `AUTO_CAPTAIN_WAIT_SECS`
+    pub auto_captain_wait_secs: i64,
+    /// How long players stay in a queue before being removed. `STALE_JOIN_HOURS`
+    pub stale_join_hours: i64,
+    /// Largest party which can join a game mode, unless the game mode sets its own limit.
+    /// `MAX_PARTY_SIZE`
+    pub max_party_size: i64,
+    /// How long a reported result waits for the other captain to confirm it, before it is
+    /// left for moderators to settle. `RESULT_CONFIRM_MINS`
+    pub result_confirm_mins: i64,
+    /// How long players get to vote for a map once teams are picked. `MAP_VOTE_SECS`
+    pub map_vote_secs: i64,
+    /// How many of a game mode's most recently played maps are left out of map votes.
+    /// `RECENT_MAPS_EXCLUDED`
+    pub recent_maps_excluded: i64,
+}
+
+impl Default for PugsConfig {
+    fn default() -> Self {
+        PugsConfig {
+            auto_captain_wait_secs: 30,
+            stale_join_hours: 4,
+            max_party_size: 2,
+            result_confirm_mins: 30,
+            map_vote_secs: 60,
+            recent_maps_excluded: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IncidentsConfig {
+    /// How long incidents are kept before they are deleted. `INCIDENT_RETENTION_DAYS`
+    pub retention_days: i64,
+}
+
+impl Default for IncidentsConfig {
+    fn default() -> Self {
+        IncidentsConfig { retention_days: 30 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// How long in-flight work gets to finish once shutting down begins.
+    /// Cloud Run kills the container 10 seconds after sending SIGTERM. `SHUTDOWN_DEADLINE_SECS`
+    pub deadline_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig { deadline_secs: 8 }
+    }
+}
+
+pub struct ConfigRef;
+impl TypeMapKey for ConfigRef {
+    type Value = Arc<Config>;
+}
+
+pub async fn get(ctx: &Context) -> Arc<Config> {
+    let data = ctx.data.read().await;
+    data.get::<ConfigRef>()
+        .expect("Expected the config to be available for use")
+        .clone()
+}
+
+/// Read the config file (if there is one), apply environment overrides and validate the result.
+pub fn load() -> Result<Config, ConfigError> {
+    let (path, required) = match env::var("RUSTY_BOT_CONFIG") {
+        Ok(path) => (path, true),
+        Err(_) => (DEFAULT_PATH.to_string(), false),
+    };
+    let mut config = match fs::read_to_string(&path) {
+        Ok(text) => toml::from_str::<Config>(&text)
+            .map_err(|err| ConfigError(vec![format!("{}: {}", path, err)]))?,
+        Err(err) if err.kind() == ErrorKind::NotFound && !required => Config::default(),
+        Err(err) => return Err(ConfigError(vec![format!("{}: {}", path, err)])),
+    };
+
+    let mut errors = Vec::default();
+    config.apply_env(&mut errors);
+    config.validate(&mut errors);
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(ConfigError(errors))
+    }
+}
+
+impl Config {
+    fn apply_env(&mut self, errors: &mut Vec<String>) {
+        if let Some(token) = env_value(errors, "DISCORD_TOKEN") {
+            self.discord.token = token;
+        }
+        if let Some(superusers) = env_list(errors, "SUPERUSERS") {
+            self.discord.superusers = superusers;
+        }
+        if let Some(uri) = env_value(errors, "MONGO_URI") {
+            self.mongo.uri = uri;
+        }
+        if let Some(secs) = env_value(errors, "MONGO_READY_MAX_WAIT") {
+            self.mongo.ready_max_wait_secs = secs;
+        }
+        // set means enabled, whatever the value
+        if env::var("MIGRATION_DRY_RUN").is_ok() {
+            self.migration.dry_run = true;
+        }
+        if let Some(channel_id) = env_value(errors, "OPS_CHANNEL_ID") {
+            self.ops.channel_id = Some(channel_id);
+        }
+        if let Some(recipients) = env_list(errors, "OPS_RECIPIENTS") {
+            self.ops.recipients = recipients;
+        }
+        if let Some(events) = env_list::<OpsEvent>(errors, "OPS_EVENTS") {
+            self.ops.events = events.into_iter().collect();
+        }
+        if let Some(limit) = env_value(errors, "OPS_CPU_LOAD_LIMIT") {
+            self.ops.cpu_load_limit = limit;
+        }
+        if let Some(limit) = env_value(errors, "OPS_MIN_FREE_MEMORY_MB") {
+            self.ops.min_free_memory_mb = limit;
+        }
+        if let Some(addr) = env_value(errors, "STATUS_SERVER_ADDR") {
+            self.status_server.addr = Some(addr);
+        }
+        if let Some(secs) = env_value(errors, "AUTO_CAPTAIN_WAIT_SECS") {
+            self.pugs.auto_captain_wait_secs = secs;
+        }
+        if let Some(hours) = env_value(errors, "STALE_JOIN_HOURS") {
+            self.pugs.stale_join_hours = hours;
+        }
+        if let Some(size) = env_value(errors, "MAX_PARTY_SIZE") {
+            self.pugs.max_party_size = size;
+        }
+        if let Some(mins) = env_value(errors, "RESULT_CONFIRM_MINS") {
+            self.pugs.result_confirm_mins = mins;
+        }
+        if let Some(secs) = env_value(errors, "MAP_VOTE_SECS") {
+            self.pugs.map_vote_secs = secs;
+        }
+        if let Some(count) = env_value(errors, "RECENT_MAPS_EXCLUDED") {
+            self.pugs.recent_maps_excluded = count;
+        }
+        if let Some(days) = env_value(errors, "INCIDENT_RETENTION_DAYS") {
+            self.incidents.retention_days = days;
+        }
+        if let Some(secs) = env_value(errors, "SHUTDOWN_DEADLINE_SECS") {
+            self.shutdown.deadline_secs = secs;
+        }
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if self.discord.token.is_empty() {
+            errors.push("discord.token (DISCORD_TOKEN) is required".to_string());
+        }
+        if self.mongo.uri.is_empty() {
+            errors.push("mongo.uri (MONGO_URI) is required".to_string());
+        }
+        if self.ops.cpu_load_limit <= 0.0 {
+            errors.push("ops.cpu_load_limit (OPS_CPU_LOAD_LIMIT) must be positive".to_string());
+        }
+        if self.ops.min_free_memory_mb < 0.0 {
+            errors.push(
+                "ops.min_free_memory_mb (OPS_MIN_FREE_MEMORY_MB) cannot be negative".to_string(),
+            );
+        }
+        if self.pugs.auto_captain_wait_secs <= 0 {
+            errors.push(
+                "pugs.auto_captain_wait_secs (AUTO_CAPTAIN_WAIT_SECS) must be positive".to_string(),
+            );
+        }
+        if self.pugs.stale_join_hours <= 0 {
+            errors.push("pugs.stale_join_hours (STALE_JOIN_HOURS) must be positive".to_string());
+        }
+        if self.pugs.max_party_size <= 0 {
+            errors.push("pugs.max_party_size (MAX_PARTY_SIZE) must be positive".to_string());
+        }
+        if self.pugs.result_confirm_mins <= 0 {
+            errors.push(
+                "pugs.result_confirm_mins (RESULT_CONFIRM_MINS) must be positive".to_string(),
+            );
+        }
+        if self.pugs.map_vote_secs <= 0 {
+            errors.push("pugs.map_vote_secs (MAP_VOTE_SECS) must be positive".to_string());
+        }
+        if self.pugs.recent_maps_excluded < 0 {
+            errors.push(
+                "pugs.recent_maps_excluded (RECENT_MAPS_EXCLUDED) cannot be negative".to_string(),
+            );
+        }
+        if self.incidents.retention_days <= 0 {
+            errors.push(
+                "incidents.retention_days (INCIDENT_RETENTION_DAYS) must be positive".to_string(),
+            );
+        }
+    }
+}
+
+/// Parse an environment variable, if it is set.
+fn env_value<T>(errors: &mut Vec<String>, name: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = env::var(name).ok()?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(err) => {
+            errors.push(format!("{} is invalid ({}): {}", name, value, err));
+            None
+        }
+    }
+}
+
+/// Parse a comma separated environment variable, if it is set.
+fn env_list<T>(errors: &mut Vec<String>, name: &str) -> Option<Vec<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = env::var(name).ok()?;
+    let mut items = Vec::default();
+    for item in value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        match item.parse() {
+            Ok(parsed) => items.push(parsed),
+            Err(err) => errors.push(format!("{} has an invalid item ({}): {}", name, item, err)),
+        }
+    }
+    Some(items)
+}
@@ -1,40 +1,44 @@
+pub mod index;
+pub mod migration;
 pub mod model;
 pub mod read;
+pub mod readiness;
 pub mod write;
-use std::env;
+use std::time::Duration;
 
+use mongodb::error::Error;
 use mongodb::options::ClientOptions;
 use mongodb::Client;
 use tracing::{info, instrument};
 
-/// Default number of seconds to wait for a useable/ready MongoDB client
-/// after this application's discord client is ready.
-pub const DEFAULT_MONGO_READY_MAX_WAIT: u8 = 30;
-
 pub mod collection_name {
     pub const COMMANDS: &str = "commands";
     pub const PUG_CHANNELS: &str = "pug_channel";
     pub const GAME_MODES: &str = "game_modes";
     pub const GAME_MODE_JOINS: &str = "game_mode_joins";
-    pub const PLAYER_ROSTER: &str = "player_roster";
-    pub const PICKING_SESSIONS: &str = "picking_sessions";
-    pub const COMPLETED_PUGS: &str = "completed_pugs";
+    pub const PUGS: &str = "pugs";
+    pub const QUEUE_NOTIFICATIONS: &str = "queue_notifications";
+    pub const SEASONS: &str = "seasons";
+    pub const SEASON_ARCHIVES: &str = "season_archives";
+    pub const SCHEMA_VERSION: &str = "schema_version";
+    pub const INCIDENTS: &str = "incidents";
+    pub const GUILD_SETTINGS: &str = "guild_settings";
+    pub const PARTIES: &str = "parties";
 }
 
-/// Creates a [`mongodb::Client`] connected to the database cluster and store a client
-#[instrument]
-pub async fn setup() -> Client {
+/// Creates a [`mongodb::Client`] for the database cluster, and waits (for up to `max_wait`)
+/// until the cluster answers a ping.
+#[instrument(skip(connection_string))]
+pub async fn setup(connection_string: String, max_wait: Duration) -> Result<Client, Error> {
     info!("Launching connection to database deployment/cluster");
-    let connection_string = env::var("MONGO_URI").expect("Expected MONGO_URI in the environment");
 
     // Parse a connection string into an options struct.
-    let mut client_options = ClientOptions::parse(connection_string)
-        .await
-        .expect("Expected successful parsing of connection string");
+    let mut client_options = ClientOptions::parse(connection_string).await?;
 
     client_options.app_name = Some("Russ T Bot".to_string());
 
-    // Try to get and return a handle to the db cluster/deployment.
-    Client::with_options(client_options)
-        .expect("Expected a new mongodb::Client connected to the cluster/deployment")
+    // Get a handle to the db cluster/deployment. This does not connect yet
+    let client = Client::with_options(client_options)?;
+    readiness::wait_until_ready(&client, max_wait).await?;
+    Ok(client)
 }
@@ -0,0 +1,149 @@
+//! Indexes of the collections of a guild database.
+//!
+//! Every index lives in [`registry`], and [`ensure_indexes`] creates any that are missing.
+//! Creating an index which already exists is a no-op, so this is safe to run on every startup.
+
+use std::time::Duration;
+
+use itertools::Itertools;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::options::IndexOptions;
+use mongodb::{Database, IndexModel};
+use tracing::info;
+
+use super::collection_name::{
+    COMMANDS, GAME_MODES, GAME_MODE_JOINS, INCIDENTS, PARTIES, PUGS, QUEUE_NOTIFICATIONS, SEASONS,
+    SEASON_ARCHIVES,
+};
+
+struct IndexSpec {
+    collection: &'static str,
+    keys: Document,
+    options: Option<IndexOptions>,
+}
+
+impl IndexSpec {
+    fn new(collection: &'static str, keys: Document) -> Self {
+        IndexSpec {
+            collection,
+            keys,
+            options: None,
+        }
+    }
+
+    fn unique(collection: &'static str, keys: Document) -> Self {
+        IndexSpec {
+            collection,
+            keys,
+            options: Some(IndexOptions::builder().unique(true).build()),
+        }
+    }
+
+    /// A unique index over only the documents matching `filter`.
+    fn unique_where(collection: &'static str, keys: Document, filter: Document) -> Self {
+        IndexSpec {
+            collection,
+            keys,
+            options: Some(
+                IndexOptions::builder()
+                    .unique(true)
+                    .partial_filter_expression(filter)
+                    .build(),
+            ),
+        }
+    }
+
+    /// A TTL index on a single date field: documents are deleted once that date has passed.
+    fn expiring(collection: &'static str, field: &str) -> Self {
+        IndexSpec {
+            collection,
+            keys: doc! { field: 1 },
+            options: Some(
+                IndexOptions::builder()
+                    .expire_after(Duration::from_secs(0))
+                    .build(),
+            ),
+        }
+    }
+}
+
+/// All indexes of a guild database.
+///
+/// Indexes are left with their default names (derived from their keys), so an index
+/// created by an earlier migration is recognised as the same index.
+/// Changing the keys or options of an index means it has to be dropped first, by a migration.
+fn registry() -> Vec<IndexSpec> {
+    vec![
+        IndexSpec::unique(GAME_MODES, doc! { "label": 1 }),
+        IndexSpec::unique(COMMANDS, doc! { "name": 1 }),
+        // a player is in a game mode's queue at most once
+        IndexSpec::unique(
+            GAME_MODE_JOINS,
+            doc! { "game_mode_label": 1, "player_user_id": 1 },
+        ),
+        // removing players from all queues when a pug fills
+        IndexSpec::new(GAME_MODE_JOINS, doc! { "player_user_id": 1 }),
+        // finding stale joins
+        IndexSpec::new(GAME_MODE_JOINS, doc! { "joined": 1 }),
+        IndexSpec::unique(PUGS, doc! { "thread_channel_id": 1 }),
+        // the current picking session, and completed pugs over time
+        IndexSpec::new(PUGS, doc! { "state": 1, "created": -1 }),
+        // completed pugs of a game mode, e.g. for /last and leaderboards
+        IndexSpec::new(PUGS, doc! { "state": 1, "game_mode": 1, "created": -1 }),
+        IndexSpec::new(
+            QUEUE_NOTIFICATIONS,
+            doc! { "game_mode_label": 1, "threshold": 1 },
+        ),
+        // a user has at most one subscription per game mode
+        IndexSpec::unique(
+            QUEUE_NOTIFICATIONS,
+            doc! { "user_id": 1, "game_mode_label": 1 },
+        ),
+        IndexSpec::unique(SEASONS, doc! { "name": 1 }),
+        // at most one season is active, even if /season start is used twice at once
+        IndexSpec::unique_where(
+            SEASONS,
+            doc! { "ended": 1 },
+            doc! { "ended": { "$type": "null" } },
+        ),
+        IndexSpec::new(
+            SEASON_ARCHIVES,
+            doc! { "season": 1, "game_mode": 1, "rank": 1 },
+        ),
+        IndexSpec::unique(INCIDENTS, doc! { "incident_id": 1 }),
+        // incidents are deleted once their retention window is over
+        IndexSpec::expiring(INCIDENTS, "expires"),
+        // reported results waiting too long for confirmation are escalated
+        IndexSpec::new(
+            PUGS,
+            doc! { "result_report.escalated": 1, "result_report.reported": 1 },
+        ),
+        // map votes which should have ended, e.g. while the bot was restarting
+        IndexSpec::new(PUGS, doc! { "map_vote.ends": 1 }),
+        // a player leads at most one party
+        IndexSpec::unique(PARTIES, doc! { "leader": 1 }),
+        IndexSpec::new(PARTIES, doc! { "members": 1 }),
+        IndexSpec::new(PARTIES, doc! { "invites": 1 }),
+    ]
+}
+
+/// Create any indexes from the [`registry`] which a guild database does not have yet.
+pub async fn ensure_indexes(db: Database) -> Result<(), Error> {
+    let by_collection = registry()
+        .into_iter()
+        .into_group_map_by(|spec| spec.collection);
+    for (collection, specs) in by_collection {
+        let models = specs.into_iter().map(|spec| {
+            IndexModel::builder()
+                .keys(spec.keys)
+                .options(spec.options)
+                .build()
+        });
+        db.collection::<Document>(collection)
+            .create_indexes(models, None)
+            .await?;
+    }
+    info!("Indexes of {} are in place", db.name());
+    Ok(())
+}
@@ -0,0 +1,541 @@
+//! Ordered migrations which bring a guild database up to date with the models in
+//! [`super::model`].
+//!
+//! Each guild database records the version of the latest migration applied to it,
+//! and [`migrate`] applies every migration with a greater version, in order.
+//! Migrations must never be edited or reordered once released - add a new one instead.
+
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, to_document, Bson, Document};
+use mongodb::error::Error;
+use mongodb::options::{FindOptions, IndexOptions, UpdateOptions};
+use mongodb::{Database, IndexModel};
+use serde::Deserialize;
+use tracing::info;
+
+use super::collection_name::{
+    COMMANDS, GAME_MODES, GAME_MODE_JOINS, PUGS, PUG_CHANNELS, QUEUE_NOTIFICATIONS, SEASONS,
+};
+use super::model::{GameModeJoin, MatchOutcome, Pug, PugChannel, PugState, Team, TeamVoiceChat};
+
+/// Collections which held pugs before they were stored as a single [`Pug`] document.
+/// They are left in place after being migrated, and are otherwise unused.
+mod legacy_collection_name {
+    pub const PLAYER_ROSTER: &str = "player_roster";
+    pub const PICKING_SESSIONS: &str = "picking_sessions";
+    pub const COMPLETED_PUGS: &str = "completed_pugs";
+    pub const COMPLETED_TWO_PLAYER_PUGS: &str = "completed_two_player_pugs";
+}
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    /// Applies the migration, or only reports what it would change when dry running.
+    /// Returns a summary of what was (or would be) changed.
+    run: fn(Database, bool) -> BoxFuture<'static, Result<String, Error>>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Give pug channels saved before `allowed_game_modes` existed an empty list",
+        run: |db, dry_run| default_allowed_game_modes(db, dry_run).boxed(),
+    },
+    Migration {
+        version: 2,
+        description: "Move picking sessions, player rosters and completed pugs into `pugs`",
+        run: |db, dry_run| consolidate_pugs(db, dry_run).boxed(),
+    },
+    Migration {
+        version: 3,
+        description: "Create indexes for finding pugs and stale queue joins",
+        run: |db, dry_run| create_query_indexes(db, dry_run).boxed(),
+    },
+    Migration {
+        version: 4,
+        description: "Remove duplicate game modes, commands and queue joins, so they can be uniquely indexed",
+        run: |db, dry_run| remove_duplicates(db, dry_run).boxed(),
+    },
+    Migration {
+        version: 5,
+        description: "Record in the guild settings that `.configure` was run, for guilds with more commands than /help",
+        run: |db, dry_run| record_commands_configured(db, dry_run).boxed(),
+    },
+    Migration {
+        version: 6,
+        description: "End all but the latest active season, and drop the season index on `ended` so it can be recreated as unique among active seasons",
+        run: |db, dry_run| keep_one_active_season(db, dry_run).boxed(),
+    },
+    Migration {
+        version: 7,
+        description: "Remove duplicate queue notification subscriptions, and drop their index so it can be recreated as unique",
+        run: |db, dry_run| remove_duplicate_queue_notifications(db, dry_run).boxed(),
+    },
+    Migration {
+        version: 8,
+        description: "Create the indexes of the index registry, now that duplicates were removed for the unique ones",
+        run: |db, dry_run| create_registry_indexes(db, dry_run).boxed(),
+    },
+];
+
+/// Apply all migrations newer than the schema version of a guild database, in order.
+///
+/// When `dry_run` is set, each pending migration only logs what it would change,
+/// and the schema version is left as it is.
+///
+/// Returns the schema version the database is at (or would be at, when dry running).
+pub async fn migrate(db: Database, dry_run: bool) -> Result<i64, Error> {
+    let current_version = super::read::get_schema_version(db.clone())
+        .await?
+        .map_or(0, |schema_version| schema_version.version);
+
+    let mut version = current_version;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current_version) {
+        info!(
+            "{}Applying migration {} to {}: {}",
+            if dry_run { "[dry run] " } else { "" },
+            migration.version,
+            db.name(),
+            migration.description
+        );
+        let summary = (migration.run)(db.clone(), dry_run).await?;
+        info!(
+            "{}Migration {} of {}: {}",
+            if dry_run { "[dry run] " } else { "" },
+            migration.version,
+            db.name(),
+            summary
+        );
+        if !dry_run {
+            super::write::set_schema_version(db.clone(), migration.version).await?;
+        }
+        version = migration.version;
+    }
+    Ok(version)
+}
+
+async fn default_allowed_game_modes(db: Database, dry_run: bool) -> Result<String, Error> {
+    let collection = db.collection::<PugChannel>(PUG_CHANNELS);
+    let filter = doc! { "allowed_game_modes": { "$exists": false } };
+    if dry_run {
+        let count = collection.count_documents(filter, None).await?;
+        return Ok(format!("{} pug channels would be updated", count));
+    }
+    let update = doc! { "$set": { "allowed_game_modes": [] } };
+    let result = collection.update_many(filter, update, None).await?;
+    Ok(format!("{} pug channels updated", result.modified_count))
+}
+
+#[derive(Deserialize)]
+struct LegacyPickingSession {
+    created: DateTime<Utc>,
+    game_mode: String,
+    thread_channel_id: i64,
+    pick_sequence: Vec<Team>,
+    last_reset: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct LegacyPlayer {
+    is_captain: bool,
+    exclude_from_random_captaining: bool,
+    user_id: i64,
+    team: Option<Team>,
+    pick_position: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct LegacyCompletedPug {
+    created: DateTime<Utc>,
+    game_mode: String,
+    thread_channel_id: i64,
+    blue_team_captain: i64,
+    blue_team: Vec<i64>,
+    red_team_captain: i64,
+    red_team: Vec<i64>,
+    voice_chat: TeamVoiceChat,
+    #[serde(default)]
+    outcome: Option<MatchOutcome>,
+    #[serde(default)]
+    season: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LegacyCompletedTwoPlayerPug {
+    created: DateTime<Utc>,
+    game_mode: String,
+    thread_channel_id: i64,
+    blue_player: i64,
+    red_player: i64,
+}
+
+impl From<LegacyCompletedPug> for Pug {
+    fn from(legacy: LegacyCompletedPug) -> Self {
+        let players = [legacy.blue_team_captain, legacy.red_team_captain]
+            .into_iter()
+            .chain(legacy.blue_team.iter().copied())
+            .chain(legacy.red_team.iter().copied())
+            .collect();
+        let mut pug = Pug::new(
+            legacy.game_mode,
+            legacy.thread_channel_id,
+            players,
+            Vec::default(),
+        );
+        pug.created = legacy.created;
+        pug.state = PugState::Completed;
+        pug.blue_team_captain = Some(legacy.blue_team_captain);
+        pug.blue_team = legacy.blue_team;
+        pug.red_team_captain = Some(legacy.red_team_captain);
+        pug.red_team = legacy.red_team;
+        pug.voice_chat = Some(legacy.voice_chat);
+        pug.outcome = legacy.outcome;
+        pug.season = legacy.season;
+        pug
+    }
+}
+
+impl From<LegacyCompletedTwoPlayerPug> for Pug {
+    fn from(legacy: LegacyCompletedTwoPlayerPug) -> Self {
+        let mut pug = Pug::new(
+            legacy.game_mode,
+            legacy.thread_channel_id,
+            vec![legacy.blue_player, legacy.red_player],
+            Vec::default(),
+        );
+        pug.created = legacy.created;
+        pug.state = PugState::Completed;
+        pug.blue_team_captain = Some(legacy.blue_player);
+        pug.red_team_captain = Some(legacy.red_player);
+        pug
+    }
+}
+
+/// Combine a picking session with its roster of players.
+fn legacy_picking_session_to_pug(session: LegacyPickingSession, roster: Vec<LegacyPlayer>) -> Pug {
+    let mut pug = Pug::new(
+        session.game_mode,
+        session.thread_channel_id,
+        roster.iter().map(|player| player.user_id).collect(),
+        session.pick_sequence,
+    );
+    pug.created = session.created;
+    pug.last_reset = session.last_reset;
+    pug.captain_opt_outs = roster
+        .iter()
+        .filter(|player| player.exclude_from_random_captaining)
+        .map(|player| player.user_id)
+        .collect();
+
+    // picked players are ordered by when they were picked
+    let mut picked = roster
+        .iter()
+        .filter(|player| player.pick_position.is_some())
+        .collect::<Vec<&LegacyPlayer>>();
+    picked.sort_by_key(|player| player.pick_position);
+    for player in roster.iter().filter(|player| player.is_captain) {
+        match player.team {
+            Some(Team::Blue) => pug.blue_team_captain = Some(player.user_id),
+            Some(Team::Red) => pug.red_team_captain = Some(player.user_id),
+            None => {}
+        }
+    }
+    for player in picked.into_iter().filter(|player| !player.is_captain) {
+        match player.team {
+            Some(Team::Blue) => pug.blue_team.push(player.user_id),
+            Some(Team::Red) => pug.red_team.push(player.user_id),
+            None => {}
+        }
+    }
+    pug
+}
+
+/// Save a pug migrated from the legacy collections, unless a pug for its thread already exists.
+///
+/// Returns whether the pug was saved.
+async fn insert_migrated_pug(db: Database, pug: &Pug, dry_run: bool) -> Result<bool, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let filter = doc! { "thread_channel_id": pug.thread_channel_id };
+    if dry_run {
+        return Ok(collection.count_documents(filter, None).await? == 0);
+    }
+    let document = to_document(pug).expect("Expected a pug to be serializable");
+    let options = UpdateOptions::builder().upsert(true).build();
+    let result = collection
+        .update_one(filter, doc! { "$setOnInsert": document }, options)
+        .await?;
+    Ok(result.upserted_id.is_some())
+}
+
+/// Legacy pugs of each collection are migrated in turn, and a thread only ever gets one pug,
+/// so a picking session which also has a completed pug record is not migrated.
+async fn consolidate_pugs(db: Database, dry_run: bool) -> Result<String, Error> {
+    use legacy_collection_name::*;
+
+    let mut migrated = 0;
+    let mut skipped = 0;
+
+    let mut completed_pugs = db
+        .collection::<LegacyCompletedPug>(COMPLETED_PUGS)
+        .find(None, None)
+        .await?;
+    while let Some(legacy) = completed_pugs.try_next().await? {
+        match insert_migrated_pug(db.clone(), &Pug::from(legacy), dry_run).await? {
+            true => migrated += 1,
+            false => skipped += 1,
+        }
+    }
+
+    let mut two_player_pugs = db
+        .collection::<LegacyCompletedTwoPlayerPug>(COMPLETED_TWO_PLAYER_PUGS)
+        .find(None, None)
+        .await?;
+    while let Some(legacy) = two_player_pugs.try_next().await? {
+        match insert_migrated_pug(db.clone(), &Pug::from(legacy), dry_run).await? {
+            true => migrated += 1,
+            false => skipped += 1,
+        }
+    }
+
+    let mut picking_sessions = db
+        .collection::<LegacyPickingSession>(PICKING_SESSIONS)
+        .find(None, None)
+        .await?;
+    while let Some(session) = picking_sessions.try_next().await? {
+        let roster = db
+            .collection::<LegacyPlayer>(PLAYER_ROSTER)
+            .find(
+                doc! { "channel_id_for_picking_session": session.thread_channel_id },
+                None,
+            )
+            .await?
+            .try_collect::<Vec<LegacyPlayer>>()
+            .await?;
+        let pug = legacy_picking_session_to_pug(session, roster);
+        match insert_migrated_pug(db.clone(), &pug, dry_run).await? {
+            true => migrated += 1,
+            false => skipped += 1,
+        }
+    }
+
+    Ok(format!(
+        "{} pugs {}migrated, {} skipped because a pug already exists for their thread",
+        migrated,
+        if dry_run { "would be " } else { "" },
+        skipped
+    ))
+}
+
+async fn create_query_indexes(db: Database, dry_run: bool) -> Result<String, Error> {
+    if dry_run {
+        return Ok("3 indexes would be created".to_string());
+    }
+    let pugs = db.collection::<Pug>(PUGS);
+    pugs.create_index(
+        IndexModel::builder()
+            .keys(doc! { "thread_channel_id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build(),
+        None,
+    )
+    .await?;
+    // the current picking session is the latest pug being picked, and
+    // completed pugs are mostly looked up by state and time
+    pugs.create_index(
+        IndexModel::builder()
+            .keys(doc! { "state": 1, "created": -1 })
+            .build(),
+        None,
+    )
+    .await?;
+    // stale joins are found by join time
+    db.collection::<GameModeJoin>(GAME_MODE_JOINS)
+        .create_index(
+            IndexModel::builder().keys(doc! { "joined": 1 }).build(),
+            None,
+        )
+        .await?;
+    Ok("3 indexes created".to_string())
+}
+
+/// For each set of documents sharing the same values of `keys`, delete all but the first one
+/// that was inserted.
+///
+/// Returns the number of documents which were (or would be) deleted.
+async fn delete_duplicates(
+    db: Database,
+    collection_name: &str,
+    keys: &[&str],
+    dry_run: bool,
+) -> Result<u64, Error> {
+    let collection = db.collection::<Document>(collection_name);
+    let group_id = keys
+        .iter()
+        .map(|key| (key.to_string(), Bson::String(format!("${}", key))))
+        .collect::<Document>();
+    let pipeline = vec![
+        // object ids increase with insertion time
+        doc! { "$sort": { "_id": 1 } },
+        doc! { "$group": { "_id": group_id, "ids": { "$push": "$_id" } } },
+        doc! { "$match": { "ids.1": { "$exists": true } } },
+    ];
+    let mut duplicate_ids: Vec<Bson> = Vec::default();
+    let mut groups = collection.aggregate(pipeline, None).await?;
+    while let Some(group) = groups.try_next().await? {
+        if let Ok(ids) = group.get_array("ids") {
+            duplicate_ids.extend(ids.iter().skip(1).cloned());
+        }
+    }
+
+    if dry_run || duplicate_ids.is_empty() {
+        return Ok(duplicate_ids.len() as u64);
+    }
+    let result = collection
+        .delete_many(doc! { "_id": { "$in": duplicate_ids } }, None)
+        .await?;
+    Ok(result.deleted_count)
+}
+
+async fn remove_duplicates(db: Database, dry_run: bool) -> Result<String, Error> {
+    let game_modes = delete_duplicates(db.clone(), GAME_MODES, &["label"], dry_run).await?;
+    let commands = delete_duplicates(db.clone(), COMMANDS, &["name"], dry_run).await?;
+    let joins = delete_duplicates(
+        db,
+        GAME_MODE_JOINS,
+        &["game_mode_label", "player_user_id"],
+        dry_run,
+    )
+    .await?;
+    Ok(format!(
+        "{} duplicate game modes, {} duplicate commands and {} duplicate queue joins {}deleted",
+        game_modes,
+        commands,
+        joins,
+        if dry_run { "would be " } else { "" }
+    ))
+}
+
+/// Before guild settings existed, only `.configure` registered commands besides /help.
+async fn record_commands_configured(db: Database, dry_run: bool) -> Result<String, Error> {
+    let configured_commands = db
+        .collection::<Document>(COMMANDS)
+        .count_documents(doc! { "name": { "$ne": "help" } }, None)
+        .await?;
+    if configured_commands == 0 {
+        return Ok("Commands were not configured".to_string());
+    }
+    if dry_run {
+        return Ok("Commands would be marked as configured".to_string());
+    }
+    super::write::set_commands_configured(db, true).await?;
+    Ok("Commands marked as configured".to_string())
+}
+
+/// Before the index on `ended` was unique among active seasons, two uses of /season start
+/// at once could both start a season.
+async fn keep_one_active_season(db: Database, dry_run: bool) -> Result<String, Error> {
+    let seasons = db.collection::<Document>(SEASONS);
+    let active = doc! { "ended": { "$type": "null" } };
+    let latest_first = FindOptions::builder().sort(doc! { "started": -1 }).build();
+    let extra_ids = seasons
+        .find(active, latest_first)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .into_iter()
+        .skip(1)
+        .filter_map(|season| season.get("_id").cloned())
+        .collect::<Vec<Bson>>();
+    let has_index = seasons
+        .list_index_names()
+        .await?
+        .iter()
+        .any(|name| name == "ended_1");
+
+    if dry_run {
+        return Ok(format!(
+            "{} extra active seasons would be ended{}",
+            extra_ids.len(),
+            if has_index {
+                ", and index ended_1 dropped"
+            } else {
+                ""
+            }
+        ));
+    }
+    let ended = match extra_ids.is_empty() {
+        true => 0,
+        false => {
+            let now = mongodb::bson::to_bson(&Utc::now())
+                .expect("Expected a timestamp to be serializable");
+            seasons
+                .update_many(
+                    doc! { "_id": { "$in": extra_ids } },
+                    doc! { "$set": { "ended": now } },
+                    None,
+                )
+                .await?
+                .modified_count
+        }
+    };
+    // the index registry recreates it once migrations are applied
+    if has_index {
+        seasons.drop_index("ended_1", None).await?;
+    }
+    Ok(format!(
+        "{} extra active seasons ended{}",
+        ended,
+        if has_index {
+            ", and index ended_1 dropped"
+        } else {
+            ""
+        }
+    ))
+}
+
+async fn remove_duplicate_queue_notifications(
+    db: Database,
+    dry_run: bool,
+) -> Result<String, Error> {
+    let duplicates = delete_duplicates(
+        db.clone(),
+        QUEUE_NOTIFICATIONS,
+        &["user_id", "game_mode_label"],
+        dry_run,
+    )
+    .await?;
+    let collection = db.collection::<Document>(QUEUE_NOTIFICATIONS);
+    let has_index = collection
+        .list_index_names()
+        .await?
+        .iter()
+        .any(|name| name == "user_id_1_game_mode_label_1");
+    // the index registry recreates it once migrations are applied
+    if has_index && !dry_run {
+        collection
+            .drop_index("user_id_1_game_mode_label_1", None)
+            .await?;
+    }
+    Ok(format!(
+        "{} duplicate subscriptions {}deleted{}",
+        duplicates,
+        if dry_run { "would be " } else { "" },
+        match (has_index, dry_run) {
+            (false, _) => "",
+            (true, true) => ", and index user_id_1_game_mode_label_1 would be dropped",
+            (true, false) => ", and index user_id_1_game_mode_label_1 dropped",
+        }
+    ))
+}
+
+/// Indexes added to the registry later are created at startup by [`super::index::ensure_indexes`],
+/// which is safe to run again.
+async fn create_registry_indexes(db: Database, dry_run: bool) -> Result<String, Error> {
+    if dry_run {
+        return Ok("Missing indexes of the index registry would be created".to_string());
+    }
+    super::index::ensure_indexes(db).await?;
+    Ok("Missing indexes of the index registry created".to_string())
+}
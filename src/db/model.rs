@@ -2,9 +2,9 @@ use chrono::{DateTime, Utc};
 use mongodb::bson::Bson;
 use serde::{Deserialize, Serialize};
 use serenity::model::application::Command;
+use std::collections::HashMap;
 use std::convert::From;
 
-
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub enum Team {
     Blue,
@@ -27,14 +27,87 @@ impl From<Team> for Bson {
 pub struct PugChannel {
     pub channel_id: i64,
     pub name: Option<String>,
-    // !FIXME: this new field might cause issues with existing records read from db
+    /// Records saved before this field existed are given an empty list by a migration
     pub allowed_game_modes: Vec<String>,
+    /// The message showing the state of every queue, kept up to date by the bot
+    #[serde(default)]
+    pub board_message_id: Option<i64>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Hash)]
 pub struct GameMode {
     pub label: String,
     pub player_count: i64,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Other names the game mode can be found by, e.g. when joining
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub emoji: Option<String>,
+    /// How long players stay in the queue before being removed, overriding `pugs.stale_join_hours`
+    #[serde(default)]
+    pub max_queue_hours: Option<i64>,
+    /// Disabled game modes cannot be joined, and are left out of /list
+    #[serde(default)]
+    pub disabled: bool,
+    /// Largest party which can join, overriding `pugs.max_party_size`
+    #[serde(default)]
+    pub max_party_size: Option<i64>,
+    /// Maps voted on once the teams of a pug are picked, managed with /map
+    #[serde(default)]
+    pub maps: Vec<String>,
+}
+
+impl GameMode {
+    /// The label, preceded by the emoji if there is one.
+    pub fn display_name(&self) -> String {
+        match &self.emoji {
+            Some(emoji) => format!("{} {}", emoji, self.label),
+            None => self.label.clone(),
+        }
+    }
+
+    /// Largest party which can join, given the default limit of `pugs.max_party_size`.
+    ///
+    /// A party always has to fit on one team, so this is never more than half of the players.
+    pub fn party_size_limit(&self, default_max_party_size: i64) -> usize {
+        let max_party_size = self.max_party_size.unwrap_or(default_max_party_size);
+        max_party_size.min(self.player_count / 2).max(1) as usize
+    }
+
+    /// Whether the game mode goes by `name`, as its label or one of its aliases, ignoring case.
+    pub fn is_called(&self, name: &str) -> bool {
+        let name = name.trim().to_lowercase();
+        std::iter::once(&self.label)
+            .chain(self.aliases.iter())
+            .any(|own_name| own_name.to_lowercase() == name)
+    }
+
+    /// The game mode which goes by `name`, preferring one labelled so over one with it as alias.
+    pub fn resolve<'a>(game_modes: &'a [GameMode], name: &str) -> Option<&'a GameMode> {
+        let label = name.trim().to_lowercase();
+        game_modes
+            .iter()
+            .find(|game_mode| game_mode.label.to_lowercase() == label)
+            .or_else(|| {
+                game_modes
+                    .iter()
+                    .find(|game_mode| game_mode.is_called(name))
+            })
+    }
+}
+
+/// Players who queue together, and are put on the same team when possible.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Party {
+    /// The player who created the party. Only they can invite players, or disband it
+    pub leader: i64,
+    /// Everyone in the party, the leader included
+    pub members: Vec<i64>,
+    /// Players who were invited, and have not accepted yet
+    pub invites: Vec<i64>,
+    pub created: DateTime<Utc>,
 }
 
 /// A model that represents a player who has joined the waiting queue for a certain game mode
@@ -45,12 +118,43 @@ pub struct GameModeJoin {
     pub joined: DateTime<Utc>,
 }
 
+/// How a subscriber wants to be alerted when a queue is about to fill.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum NotificationDelivery {
+    /// Send a direct message to the subscriber
+    DirectMessage,
+    /// Mention the subscriber in the pug channel
+    ChannelMention,
+}
+
+/// A model that represents a user's opt-in to be alerted when the waiting
+/// queue of a certain game mode reaches a certain number of players.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct QueueNotification {
+    pub user_id: i64,
+    pub game_mode_label: String,
+    /// Queue size (number of players) at which the alert fires
+    pub threshold: i64,
+    pub delivery: NotificationDelivery,
+    /// Hour of day (UTC, 0-23) from which alerts are suppressed
+    pub quiet_hours_start: Option<i64>,
+    /// Hour of day (UTC, 0-23) at which alerts resume
+    pub quiet_hours_end: Option<i64>,
+    /// Timestamp of the last alert, used for enforcing a cooldown
+    pub last_notified: Option<DateTime<Utc>>,
+}
+
 /// Basically a slimmed down [`serenity::model::interactions::application_command::Command`]
 /// with only the field we need to check/store in the database.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct GuildCommand {
     pub command_id: i64,
     pub name: String,
+    /// The definition the command was last set with, as JSON.
+    /// Records saved before this field existed have none, so their command is set again
+    /// on the next sync.
+    #[serde(default)]
+    pub definition: Option<String>,
 }
 
 impl PartialEq<Command> for GuildCommand {
@@ -59,21 +163,12 @@ impl PartialEq<Command> for GuildCommand {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-pub struct PickingSession {
-    pub created: DateTime<Utc>,
-    pub game_mode: String,
-    /// Channel Id of the thread created for managing/organizing  
-    /// a filled pug. This is the primary identifier of a picking session.
-    pub thread_channel_id: i64,
-    pub pick_sequence: Vec<Team>,
-    /// Timestamp for tracking latest reset if any. This is useful for
-    /// the auto captain countdown to also reset if this value changes.
-    pub last_reset: Option<DateTime<Utc>>,
-    // !TODO: for tracking resets/countdowns
-    // When adding this field, ensure you set to null on
-    // all existing records
-    // pub auto_captain_countdown: Option<AutoCaptainCountDown>
+/// Settings of a guild, kept in a single document.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// Whether `.configure` was run, so the full command set is registered rather than only /help
+    pub commands_configured: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
@@ -82,25 +177,6 @@ pub struct AutoCaptainCountDown {
     message_id: i64,
 }
 
-/// A model that represents a participant/player
-/// involved with a picking session.
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-pub struct Player {
-    pub channel_id_for_picking_session: i64,
-    pub is_captain: bool,
-    pub exclude_from_random_captaining: bool,
-    pub user_id: i64,
-    pub team: Option<Team>,
-    /// The position of your being picked to a team.
-    ///
-    /// e.g. in a 10 player blitz pug, captain of red team happens to be picking second
-    /// (after blue captain - note that captain who picks first is randomly determined),
-    /// and picks you first. Your pick position is `1`. The last picked player in such a game mode
-    /// (typically 5 players per team) would have a pick position of `4`.
-    /// When a player is a captain, they do not get assigned a pick position.
-    pub pick_position: Option<i64>,
-}
-
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct TeamVoiceChat {
     pub category: ChannelState,
@@ -114,47 +190,432 @@ pub struct ChannelState {
     pub is_deleted_from_guild_channel_list: bool,
 }
 
+/// The result of a match played by the teams of a [`Pug`].
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum MatchOutcome {
+    BlueWin,
+    RedWin,
+    Draw,
+}
+
+impl From<MatchOutcome> for Bson {
+    fn from(outcome: MatchOutcome) -> Self {
+        match outcome {
+            // Must match the enum variants exactly, see `From<Team> for Bson`
+            MatchOutcome::BlueWin => Bson::String("BlueWin".to_string()),
+            MatchOutcome::RedWin => Bson::String("RedWin".to_string()),
+            MatchOutcome::Draw => Bson::String("Draw".to_string()),
+        }
+    }
+}
+
+/// A match result reported by a captain, waiting for the captain of the other team to confirm it.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ResultReport {
+    pub reporter: i64,
+    pub outcome: MatchOutcome,
+    pub score: Option<String>,
+    pub reported: DateTime<Utc>,
+    /// Set when the report was disputed, contradicted or not confirmed in time,
+    /// leaving it for a moderator to settle with /setresult
+    pub escalated: bool,
+}
+
+/// A vote on the map of a [`Pug`], held in its thread once its teams are picked.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-pub struct CompletedPug {
+pub struct MapVote {
+    /// Maps which can be voted for, in the order their buttons are shown
+    pub candidates: Vec<String>,
+    /// The map each player voted for, by user id (as a string, since it is a document key)
+    pub votes: HashMap<String, String>,
+    pub ends: DateTime<Utc>,
+    /// The message with the voting buttons, which shows the chosen map once the vote ends
+    pub message_id: Option<i64>,
+}
+
+/// Stage of a [`Pug`]'s lifecycle.
+///
+/// Players wait in [`GameModeJoin`] queues, and a [`Pug`] is created
+/// when a queue fills, starting out in [`PugState::Picking`]
+/// (or straight away in [`PugState::Completed`] for two player game modes).
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum PugState {
+    /// Captains are being chosen, or are picking their teams
+    Picking,
+    /// Teams are final
+    Completed,
+}
+
+impl From<PugState> for Bson {
+    fn from(state: PugState) -> Self {
+        match state {
+            // Must match the enum variants exactly, see `From<Team> for Bson`
+            PugState::Picking => Bson::String("Picking".to_string()),
+            PugState::Completed => Bson::String("Completed".to_string()),
+        }
+    }
+}
+
+/// A filled pug, from picking through to completion, stored as a single document.
+///
+/// Every state transition is a single update of this document which increments
+/// `version`, and is only applied if `version` still matches what the update was
+/// computed from. So when, for example, both captains use /pick at the same time,
+/// one of them wins and the other is told to try again instead of corrupting the pug.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Pug {
     pub created: DateTime<Utc>,
     pub game_mode: String,
+    /// Channel Id of the thread created for managing/organizing
+    /// a filled pug. This is the primary identifier of a pug.
     pub thread_channel_id: i64,
-    pub blue_team_captain: i64,
+    pub state: PugState,
+    pub version: i64,
+    /// Everyone who was pulled out of the queue when it filled
+    pub players: Vec<i64>,
+    pub pick_sequence: Vec<Team>,
+    /// Timestamp for tracking latest reset if any. This is useful for
+    /// the auto captain countdown to also reset if this value changes.
+    pub last_reset: Option<DateTime<Utc>>,
+    /// Players who used /nocapt to avoid being randomly made captain
+    pub captain_opt_outs: Vec<i64>,
+    pub blue_team_captain: Option<i64>,
+    /// Players picked for blue team, in the order they were picked.
+    /// Like the red team array, this does NOT include the captain.
     pub blue_team: Vec<i64>,
-    pub red_team_captain: i64,
+    pub red_team_captain: Option<i64>,
+    /// Players picked for red team, in the order they were picked
     pub red_team: Vec<i64>,
-    pub voice_chat: TeamVoiceChat,
+    pub voice_chat: Option<TeamVoiceChat>,
+    /// The result of the match, if one has been recorded
+    #[serde(default)]
+    pub outcome: Option<MatchOutcome>,
+    /// The score of the match, as reported along with its outcome
+    #[serde(default)]
+    pub score: Option<String>,
+    /// The captain whose report of the outcome was confirmed, or the moderator who set it
+    #[serde(default)]
+    pub reported_by: Option<i64>,
+    /// A reported result which has not been confirmed yet
+    #[serde(default)]
+    pub result_report: Option<ResultReport>,
+    /// Name of the [`Season`] that was active when the pug was completed, if any
+    pub season: Option<String>,
+    /// Players who queued together as a [`Party`], and are put on the same team when possible
+    #[serde(default)]
+    pub parties: Vec<Vec<i64>>,
+    /// The map chosen by vote once the teams were picked, if the game mode has a map pool
+    #[serde(default)]
+    pub map: Option<String>,
+    /// The map vote while it is running
+    #[serde(default)]
+    pub map_vote: Option<MapVote>,
 }
 
+impl Pug {
+    /// A pug which has just filled, with nobody captaining or picked yet.
+    pub fn new(
+        game_mode: String,
+        thread_channel_id: i64,
+        players: Vec<i64>,
+        pick_sequence: Vec<Team>,
+    ) -> Self {
+        Pug {
+            created: Utc::now(),
+            game_mode,
+            thread_channel_id,
+            state: PugState::Picking,
+            version: 0,
+            players,
+            pick_sequence,
+            last_reset: None,
+            captain_opt_outs: Vec::default(),
+            blue_team_captain: None,
+            blue_team: Vec::default(),
+            red_team_captain: None,
+            red_team: Vec::default(),
+            voice_chat: None,
+            outcome: None,
+            score: None,
+            reported_by: None,
+            result_report: None,
+            season: None,
+            parties: Vec::default(),
+            map: None,
+            map_vote: None,
+        }
+    }
+
+    pub fn captain(&self, team: Team) -> Option<i64> {
+        match team {
+            Team::Blue => self.blue_team_captain,
+            Team::Red => self.red_team_captain,
+        }
+    }
+
+    pub fn has_both_captains(&self) -> bool {
+        self.blue_team_captain.is_some() && self.red_team_captain.is_some()
+    }
+
+    pub fn is_captain(&self, user_id: i64) -> bool {
+        self.blue_team_captain == Some(user_id) || self.red_team_captain == Some(user_id)
+    }
+
+    /// The captain of the team a player is playing against, if they are in this pug.
+    pub fn opposing_captain(&self, user_id: i64) -> Option<i64> {
+        match self.team_of(user_id)? {
+            Team::Blue => self.red_team_captain,
+            Team::Red => self.blue_team_captain,
+        }
+    }
+
+    /// The team a player is on (whether as captain or picked), if any.
+    pub fn team_of(&self, user_id: i64) -> Option<Team> {
+        if self.blue_team_captain == Some(user_id) || self.blue_team.contains(&user_id) {
+            Some(Team::Blue)
+        } else if self.red_team_captain == Some(user_id) || self.red_team.contains(&user_id) {
+            Some(Team::Red)
+        } else {
+            None
+        }
+    }
+
+    /// Players who are neither captaining nor on a team yet.
+    pub fn pickable_players(&self) -> Vec<i64> {
+        self.players
+            .iter()
+            .filter(|&&user_id| self.team_of(user_id).is_none())
+            .copied()
+            .collect()
+    }
+
+    /// The team whose captain picks next.
+    ///
+    /// The first entry of the pick sequence corresponds to the captains being chosen,
+    /// so the entry for a pick comes after those of all the picks made before it.
+    pub fn next_team_to_pick(&self) -> Option<Team> {
+        let picks_made = self.blue_team.len() + self.red_team.len();
+        self.pick_sequence.get(picks_made + 1).copied()
+    }
+
+    /// The players a player queued with, not including themselves.
+    pub fn party_mates(&self, user_id: i64) -> Vec<i64> {
+        self.parties
+            .iter()
+            .find(|party| party.contains(&user_id))
+            .map(|party| {
+                party
+                    .iter()
+                    .filter(|&&member| member != user_id)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// How many players a team has, its captain included.
+    pub fn team_size(&self, team: Team) -> usize {
+        let picked = match team {
+            Team::Blue => self.blue_team.len(),
+            Team::Red => self.red_team.len(),
+        };
+        picked + self.captain(team).is_some() as usize
+    }
+
+    /// How many more players a team can take.
+    pub fn room_on(&self, team: Team) -> usize {
+        (self.players.len() / 2).saturating_sub(self.team_size(team))
+    }
+
+    /// The players added to a team when a player is picked for it: the player, followed by
+    /// those of their party mates who can still be picked, as far as the team has room.
+    pub fn pick_block(&self, user_id: i64, team: Team) -> Vec<(i64, Team)> {
+        let pickable = self.pickable_players();
+        std::iter::once(user_id)
+            .chain(
+                self.party_mates(user_id)
+                    .into_iter()
+                    .filter(|member| pickable.contains(member)),
+            )
+            .take(self.room_on(team))
+            .map(|user_id| (user_id, team))
+            .collect()
+    }
+
+    /// The party mates of the captains, placed on their captain's team once both are chosen.
+    pub fn captains_party_mates(&self) -> Vec<(i64, Team)> {
+        let pickable = self.pickable_players();
+        let mut picks: Vec<(i64, Team)> = Vec::default();
+        for team in [Team::Red, Team::Blue] {
+            let captain = match self.captain(team) {
+                Some(captain) => captain,
+                None => continue,
+            };
+            let mates = self
+                .party_mates(captain)
+                .into_iter()
+                .filter(|mate| pickable.contains(mate))
+                .filter(|mate| !picks.iter().any(|(picked, _)| picked == mate))
+                .take(self.room_on(team))
+                .collect::<Vec<i64>>();
+            picks.extend(mates.into_iter().map(|mate| (mate, team)));
+        }
+        picks
+    }
+
+    /// The picks which complete the teams, if making `picks` leaves nothing to choose:
+    /// either nobody is left, or one team is full and everyone left goes to the other.
+    pub fn final_picks(&self, picks: &[(i64, Team)]) -> Option<Vec<(i64, Team)>> {
+        let room_after = |team: Team| {
+            let picked = picks.iter().filter(|(_, t)| *t == team).count();
+            self.room_on(team).saturating_sub(picked)
+        };
+        let left = self
+            .pickable_players()
+            .into_iter()
+            .filter(|user_id| !picks.iter().any(|(picked, _)| picked == user_id))
+            .collect::<Vec<i64>>();
+        let team_with_room = match (room_after(Team::Red), room_after(Team::Blue)) {
+            _ if left.is_empty() => Team::Red,
+            (0, _) => Team::Blue,
+            (_, 0) => Team::Red,
+            _ => return None,
+        };
+        let mut final_picks = picks.to_vec();
+        final_picks.extend(left.into_iter().map(|user_id| (user_id, team_with_room)));
+        Some(final_picks)
+    }
+}
+
+/// How many times a certain player appeared alongside (or against) another.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-pub struct CompletedTwoPlayerPug {
-    pub created: DateTime<Utc>,
+pub struct PlayerFrequency {
+    #[serde(rename = "_id")]
+    pub user_id: i64,
+    pub count: i64,
+}
+
+/// Aggregated participation figures of a player, computed from completed [`Pug`]s.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PlayerStats {
+    pub games_played: i64,
+    pub times_captained: i64,
+    pub last_played: Option<DateTime<Utc>>,
+    /// Most frequent teammates, in descending order of frequency
+    pub teammates: Vec<PlayerFrequency>,
+    /// Most frequent opponents, in descending order of frequency
+    pub opponents: Vec<PlayerFrequency>,
+}
+
+/// Aggregated pick positions of a player, computed from completed [`Pug`]s.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct PickPositionStats {
+    pub average_pick_position: f64,
+    pub times_picked: i64,
+}
+
+/// A player's standing in a game mode, computed from completed [`Pug`]s.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct LeaderboardEntry {
+    #[serde(rename = "_id")]
+    pub user_id: i64,
+    pub games_played: i64,
+    /// Number of games played which have a recorded [`MatchOutcome`]
+    pub games_decided: i64,
+    pub wins: i64,
+    pub times_captained: i64,
+}
+
+/// A named competitive period of a guild. At most one season is active
+/// (i.e. has not ended) at any time.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Season {
+    pub name: String,
+    pub started: DateTime<Utc>,
+    pub ended: Option<DateTime<Utc>>,
+}
+
+/// A player's final standing in a game mode, archived when a [`Season`] ends.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SeasonStanding {
+    pub season: String,
     pub game_mode: String,
-    pub thread_channel_id: i64,
-    pub blue_player: i64,
-    pub red_player: i64,
-}
-
-// EXPERIMENT BELOW:
-// see if the $operation syntax can be used
-// to perform partial/tiny updates so we aren't
-// // always reading and writing the entire struct :/
-// Because several actions to update the session will rely on
-// multiple db operations across several collections, session usage is crucial
-//
-// pub struct Session {
-//     thread_channel_id
-//     game_mode:
-//     created: Datetime<Utc>
-//     current_pick_position: 0
-//     pick_sequence: Vec<Team>
-//     players: HashSet<user_id> set of players pulled out of queue
-//     !NOTE: for logical compatibility with existing design,
-//     team captain should NOT be included in team Vec
-//     blue_captain: Option<user_id>
-//     blue_team: Vec<user_id>
-//     red_captain: Option<user_id>
-//     red_team: Vec<user_id>
-//     captain_opt_outs: HashSet<user_id>
-//     voice_chat: TeamVoiceChat
-// }
+    /// Position on the season's games played leaderboard of the game mode
+    pub rank: i64,
+    pub user_id: i64,
+    pub games_played: i64,
+    pub games_decided: i64,
+    pub wins: i64,
+    pub times_captained: i64,
+}
+
+/// The latest migration applied to a guild database, see [`crate::db::migration`].
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SchemaVersion {
+    pub version: i64,
+    pub updated: DateTime<Utc>,
+}
+
+/// An error returned by a command (or component) handler, kept for troubleshooting
+/// until `expires`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Incident {
+    /// The short id given to the user in the error reply
+    pub incident_id: String,
+    pub created: DateTime<Utc>,
+    /// When the incident is deleted. Stored as a BSON date, as required by the TTL index.
+    pub expires: mongodb::bson::DateTime,
+    /// Name of the command, or custom id of the component, which failed
+    pub command: String,
+    /// Options the command was used with, as `name: value`
+    pub options: Vec<String>,
+    pub user_id: i64,
+    pub channel_id: i64,
+    /// The error, followed by each of its causes
+    pub error_chain: Vec<String>,
+    pub notes: Vec<IncidentNote>,
+}
+
+/// Something the user who ran into an [`Incident`] added to it, e.g. what they were trying to do.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct IncidentNote {
+    pub added: DateTime<Utc>,
+    pub note: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_mode(label: &str, aliases: &[&str]) -> GameMode {
+        GameMode {
+            label: label.to_string(),
+            aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+            ..GameMode::default()
+        }
+    }
+
+    #[test]
+    fn game_modes_are_resolved_by_label_or_alias_ignoring_case() {
+        let game_modes = [game_mode("ctf", &["Flag"]), game_mode("tdm", &[])];
+        for name in ["ctf", "CTF", " ctf ", "flag", "FLAG"] {
+            assert_eq!(
+                GameMode::resolve(&game_modes, name).map(|g| g.label.as_str()),
+                Some("ctf"),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(GameMode::resolve(&game_modes, "ffa"), None);
+        assert_eq!(GameMode::resolve(&game_modes, ""), None);
+    }
+
+    #[test]
+    fn a_label_is_preferred_over_an_alias() {
+        let game_modes = [game_mode("tdm2v2", &["tdm"]), game_mode("TDM", &[])];
+        assert_eq!(
+            GameMode::resolve(&game_modes, "tdm").map(|g| g.label.as_str()),
+            Some("TDM")
+        );
+    }
+}
@@ -1,16 +1,15 @@
 use std::collections::HashMap;
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures::stream::TryStreamExt;
-use mongodb::bson::doc;
+use mongodb::bson::{doc, from_document, Bson, Document};
 use mongodb::error::Error;
-use mongodb::options::FindOneOptions;
-use mongodb::Database;
-
-use crate::db::collection_name::PLAYER_ROSTER;
+use mongodb::options::{FindOneOptions, FindOptions};
+use mongodb::{Cursor, Database};
 
 use super::collection_name::{
-    COMMANDS, COMPLETED_PUGS, GAME_MODES, GAME_MODE_JOINS, PICKING_SESSIONS, PUG_CHANNELS,
+    COMMANDS, GAME_MODES, GAME_MODE_JOINS, GUILD_SETTINGS, INCIDENTS, PARTIES, PUGS, PUG_CHANNELS,
+    QUEUE_NOTIFICATIONS, SCHEMA_VERSION, SEASONS,
 };
 use super::model::*;
 
@@ -21,14 +20,11 @@ pub async fn get_game_modes(db: Database) -> Result<Vec<GameMode>, Error> {
     cursor.try_collect().await
 }
 
-pub async fn find_game_mode(
-    db: Database,
-    game_mode_label: &String,
-) -> Result<Option<GameMode>, Error> {
-    let filter = doc! {
-        "label": game_mode_label
-    };
-    db.collection(GAME_MODES).find_one(filter, None).await
+/// Find a game mode by its label, or by one of its aliases, ignoring case.
+/// See [`GameMode::resolve`].
+pub async fn find_game_mode(db: Database, name: &str) -> Result<Option<GameMode>, Error> {
+    let game_modes = get_game_modes(db).await?;
+    Ok(GameMode::resolve(&game_modes, name).cloned())
 }
 
 /// Get players in the waiting queue for a game mode
@@ -70,77 +66,73 @@ pub async fn get_commands(db: Database) -> Result<Vec<GuildCommand>, Error> {
     cursor.try_collect().await
 }
 
-/// Get a single guild command matching the provided filter.
-pub async fn find_command<S>(db: Database, name: S) -> Result<Option<GuildCommand>, Error>
-where
-    S: ToString,
-{
-    let query = doc! {"name": name.to_string()};
-    db.collection(COMMANDS).find_one(query, None).await
-}
-
-pub async fn get_current_picking_session(db: Database) -> Result<Option<PickingSession>, Error> {
+/// Get the most recent pug which is still being picked, if any.
+pub async fn get_current_picking_session(db: Database) -> Result<Option<Pug>, Error> {
+    let filter = doc! { "state": PugState::Picking };
     let options = FindOneOptions::builder()
         // -1 sorts them in descending order
         .sort(doc! { "created": -1 })
         .build();
-    db.collection(PICKING_SESSIONS)
-        .find_one(None, options)
-        .await
+    db.collection::<Pug>(PUGS).find_one(filter, options).await
 }
 
-pub async fn is_captain_position_available(
-    db: Database,
-    &pug_thread_channel_id: &u64,
-) -> Result<bool, Error> {
-    let collection = db.collection::<Player>(PLAYER_ROSTER);
-    let filter = doc! {
-        "channel_id_for_picking_session": pug_thread_channel_id as i64,
-        "is_captain": true,
-    };
-
-    let current_captain_count = collection.count_documents(filter, None).await?;
-    if current_captain_count < 2 {
-        return Ok(true);
-    }
-    Ok(false)
+pub async fn find_pug(db: Database, thread_channel_id: i64) -> Result<Option<Pug>, Error> {
+    let filter = doc! { "thread_channel_id": thread_channel_id };
+    db.collection::<Pug>(PUGS).find_one(filter, None).await
 }
 
-pub async fn get_picking_session_members(
+/// Get completed pugs whose reported result has waited longer than `max_age` for
+/// confirmation, and has not been escalated yet.
+pub async fn get_unconfirmed_result_reports(
     db: Database,
-    &pug_thread_channel_id: &u64,
-) -> Result<Vec<Player>, Error> {
-    let collection = db.collection::<Player>(PLAYER_ROSTER);
+    max_age: chrono::Duration,
+) -> Result<Vec<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
     let filter = doc! {
-        "channel_id_for_picking_session": pug_thread_channel_id as i64,
+        "state": PugState::Completed,
+        "result_report.escalated": false,
+        "result_report.reported": { "$lt": (Utc::now() - max_age).to_rfc3339() },
     };
-
     let cursor = collection.find(filter, None).await?;
     cursor.try_collect().await
 }
 
-/// Fetch guild command records for the following guild commands:
-///
-/// - **/captain**
-/// - **/nocapt**
-/// - **/autocaptain**
-pub async fn get_captain_related_guild_commands(db: Database) -> Result<Vec<GuildCommand>, Error> {
-    let collection = db.collection::<GuildCommand>(COMMANDS);
+/// Get pugs whose map vote should have ended by now.
+pub async fn get_ended_map_votes(db: Database) -> Result<Vec<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
     let filter = doc! {
-        "name": {
-            "$in": ["captain", "nocapt", "autocaptain"]
-        },
+        "map_vote.ends": { "$lt": Utc::now().to_rfc3339() },
     };
-
     let cursor = collection.find(filter, None).await?;
     cursor.try_collect().await
 }
 
+/// Get the maps of the most recently completed pugs of a game mode, most recent first.
+pub async fn get_recent_maps(
+    db: Database,
+    game_mode_label: &str,
+    limit: i64,
+) -> Result<Vec<String>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let filter = doc! {
+        "state": PugState::Completed,
+        "game_mode": game_mode_label,
+        "map": { "$ne": Bson::Null },
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "created": -1 })
+        .limit(limit)
+        .build();
+    let cursor = collection.find(filter, options).await?;
+    let pugs: Vec<Pug> = cursor.try_collect().await?;
+    Ok(pugs.into_iter().filter_map(|pug| pug.map).collect())
+}
+
 pub async fn get_voice_channels_pending_deletion(
     db: Database,
     max_age: chrono::Duration,
 ) -> Result<Vec<TeamVoiceChat>, Error> {
-    let collection = db.collection::<CompletedPug>(COMPLETED_PUGS);
+    let collection = db.collection::<Pug>(PUGS);
     let _filter = doc! {
         "voice_chat.is_deleted_from_guild_channel_list": {
             "$or": [
@@ -151,29 +143,43 @@ pub async fn get_voice_channels_pending_deletion(
         }
     };
 
-    let cursor = collection.find(None, None).await?;
-    let results: Vec<CompletedPug> = cursor.try_collect().await?;
+    let filter = doc! { "voice_chat": { "$ne": Bson::Null } };
+    let cursor = collection.find(filter, None).await?;
+    let results: Vec<Pug> = cursor.try_collect().await?;
 
     let mut voice_channels = Vec::default();
-    for completed_pug in results {
-        if Utc::now() - completed_pug.created > max_age {
-            voice_channels.push(completed_pug.voice_chat);
+    for pug in results {
+        if Utc::now() - pug.created > max_age {
+            voice_channels.extend(pug.voice_chat);
         }
     }
     Ok(voice_channels)
 }
 
+/// Get queue joins older than `max_age`, or older than the `max_queue_hours` of their
+/// game mode if it has one.
 pub async fn get_stale_game_mode_joins(
     db: Database,
     max_age: chrono::Duration,
 ) -> Result<Vec<GameModeJoin>, Error> {
     let collection = db.collection::<GameModeJoin>(GAME_MODE_JOINS);
-    let oldest_created_datetime = (Utc::now() - max_age).to_rfc3339();
-    let filter = doc! {
-        "joined": {
-            "$lt": oldest_created_datetime
+    let now = Utc::now();
+    let mut overridden_labels = Vec::default();
+    let mut conditions = Vec::default();
+    for game_mode in get_game_modes(db.clone()).await? {
+        if let Some(hours) = game_mode.max_queue_hours {
+            conditions.push(doc! {
+                "game_mode_label": game_mode.label.clone(),
+                "joined": { "$lt": (now - chrono::Duration::hours(hours)).to_rfc3339() },
+            });
+            overridden_labels.push(game_mode.label);
         }
-    };
+    }
+    conditions.push(doc! {
+        "game_mode_label": { "$nin": overridden_labels },
+        "joined": { "$lt": (now - max_age).to_rfc3339() },
+    });
+    let filter = doc! { "$or": conditions };
 
     let cursor = collection.find(filter, None).await?;
     let results = cursor.try_collect().await?;
@@ -186,3 +192,391 @@ pub async fn get_pug_channel(db: Database) -> Result<Option<PugChannel>, Error>
 
     collection.find_one(filter, None).await
 }
+
+/// Get queue notification subscriptions for a game mode which are due to fire when its
+/// queue grows from `previous_size` to `queue_size` players, i.e. with a threshold in
+/// `(previous_size, queue_size]`. A party joining can grow a queue by several players at once.
+pub async fn get_queue_notification_subscribers(
+    db: Database,
+    game_mode_label: &str,
+    previous_size: i64,
+    queue_size: i64,
+) -> Result<Vec<QueueNotification>, Error> {
+    let collection = db.collection::<QueueNotification>(QUEUE_NOTIFICATIONS);
+    let filter = doc! {
+        "game_mode_label": game_mode_label,
+        "threshold": { "$gt": previous_size, "$lte": queue_size },
+    };
+
+    let cursor = collection.find(filter, None).await?;
+    cursor.try_collect().await
+}
+
+/// How many of a player's most frequent teammates/opponents to include in [`PlayerStats`].
+const FREQUENT_PLAYERS_LIMIT: i64 = 3;
+
+/// Aggregate a player's participation in completed pugs, optionally
+/// restricted to a single game mode and/or season.
+pub async fn get_player_stats(
+    db: Database,
+    &user_id: &u64,
+    game_mode_label: Option<&str>,
+    season: Option<&str>,
+) -> Result<PlayerStats, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let user_id = user_id as i64;
+
+    let mut filter = doc! {
+        "state": PugState::Completed,
+        "$or": [
+            { "blue_team_captain": user_id },
+            { "red_team_captain": user_id },
+            { "blue_team": user_id },
+            { "red_team": user_id },
+        ]
+    };
+    if let Some(label) = game_mode_label {
+        filter.insert("game_mode", label);
+    }
+    if let Some(season) = season {
+        filter.insert("season", season);
+    }
+
+    // Captains are not included in the team arrays, so they are merged in here
+    let blue_roster = doc! { "$concatArrays": [["$blue_team_captain"], "$blue_team"] };
+    let red_roster = doc! { "$concatArrays": [["$red_team_captain"], "$red_team"] };
+    let is_on_blue_team = doc! { "$in": [user_id, blue_roster.clone()] };
+
+    let frequency_pipeline = |field: &str| -> Vec<Document> {
+        vec![
+            doc! { "$unwind": format!("${}", field) },
+            doc! { "$match": { field: { "$ne": user_id } } },
+            doc! { "$group": { "_id": format!("${}", field), "count": { "$sum": 1 } } },
+            doc! { "$sort": { "count": -1, "_id": 1 } },
+            doc! { "$limit": FREQUENT_PLAYERS_LIMIT },
+        ]
+    };
+
+    let pipeline = vec![
+        doc! { "$match": filter },
+        doc! {
+            "$project": {
+                "created": 1,
+                "is_captain": {
+                    "$or": [
+                        { "$eq": ["$blue_team_captain", user_id] },
+                        { "$eq": ["$red_team_captain", user_id] },
+                    ]
+                },
+                "teammates": {
+                    "$cond": [is_on_blue_team.clone(), blue_roster.clone(), red_roster.clone()]
+                },
+                "opponents": {
+                    "$cond": [is_on_blue_team, red_roster, blue_roster]
+                },
+            }
+        },
+        doc! {
+            "$facet": {
+                "summary": [
+                    {
+                        "$group": {
+                            "_id": Bson::Null,
+                            "games_played": { "$sum": 1 },
+                            "times_captained": { "$sum": { "$cond": ["$is_captain", 1, 0] } },
+                            // timestamps are stored as RFC 3339 strings, which sort chronologically
+                            "last_played": { "$max": "$created" },
+                        }
+                    }
+                ],
+                "teammates": frequency_pipeline("teammates"),
+                "opponents": frequency_pipeline("opponents"),
+            }
+        },
+        doc! {
+            "$project": {
+                "games_played": {
+                    "$ifNull": [{ "$arrayElemAt": ["$summary.games_played", 0] }, 0]
+                },
+                "times_captained": {
+                    "$ifNull": [{ "$arrayElemAt": ["$summary.times_captained", 0] }, 0]
+                },
+                "last_played": { "$arrayElemAt": ["$summary.last_played", 0] },
+                "teammates": 1,
+                "opponents": 1,
+            }
+        },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    match cursor.try_next().await? {
+        Some(document) => Ok(from_document(document)?),
+        None => Ok(PlayerStats::default()),
+    }
+}
+
+/// Aggregate the pick positions of a player across completed pugs, optionally restricted to a single game mode and/or season.
+///
+/// Returns `None` if the player has never been picked (e.g. they always captained).
+pub async fn get_pick_position_stats(
+    db: Database,
+    &user_id: &u64,
+    game_mode_label: Option<&str>,
+    season: Option<&str>,
+) -> Result<Option<PickPositionStats>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let user_id = user_id as i64;
+
+    // Captains are not in the team arrays, so only picked players match
+    let mut filter = doc! {
+        "state": PugState::Completed,
+        "$or": [
+            { "blue_team": user_id },
+            { "red_team": user_id },
+        ]
+    };
+    if let Some(label) = game_mode_label {
+        filter.insert("game_mode", label);
+    }
+    if let Some(season) = season {
+        filter.insert("season", season);
+    }
+
+    let pipeline = vec![
+        doc! { "$match": filter },
+        // Team arrays are in the order of picking, so the (1-based) index is the pick position.
+        // The player is absent from one of them, where the index is -1.
+        doc! {
+            "$project": {
+                "pick_position": {
+                    "$add": [
+                        {
+                            "$max": [
+                                { "$indexOfArray": ["$blue_team", user_id] },
+                                { "$indexOfArray": ["$red_team", user_id] },
+                            ]
+                        },
+                        1
+                    ]
+                }
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": Bson::Null,
+                "average_pick_position": { "$avg": "$pick_position" },
+                "times_picked": { "$sum": 1 },
+            }
+        },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    match cursor.try_next().await? {
+        Some(document) => Ok(Some(from_document(document)?)),
+        None => Ok(None),
+    }
+}
+
+/// Aggregate the standings of every player who took part in a completed pug of a game mode,
+/// optionally restricted to pugs of a season and/or pugs created within `[from, to)`.
+///
+/// Entries are not in any particular order.
+pub async fn get_leaderboard_entries(
+    db: Database,
+    game_mode_label: &str,
+    season: Option<&str>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Vec<LeaderboardEntry>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+
+    let mut filter = created_between(from, to);
+    filter.insert("state", PugState::Completed);
+    filter.insert("game_mode", game_mode_label);
+    if let Some(season) = season {
+        filter.insert("season", season);
+    }
+
+    // Captains are not included in the team arrays, so they are merged in here
+    let participants_of = |team: &str, captain: &str, members: &str| {
+        doc! {
+            "$map": {
+                "input": { "$concatArrays": [[format!("${}", captain)], format!("${}", members)] },
+                "in": {
+                    "user_id": "$$this",
+                    "team": team,
+                    "is_captain": { "$eq": ["$$this", format!("${}", captain)] },
+                }
+            }
+        }
+    };
+
+    let pipeline = vec![
+        doc! { "$match": filter },
+        doc! {
+            "$project": {
+                "outcome": 1,
+                "participants": {
+                    "$concatArrays": [
+                        participants_of("Blue", "blue_team_captain", "blue_team"),
+                        participants_of("Red", "red_team_captain", "red_team"),
+                    ]
+                },
+            }
+        },
+        doc! { "$unwind": "$participants" },
+        doc! {
+            "$group": {
+                "_id": "$participants.user_id",
+                "games_played": { "$sum": 1 },
+                "games_decided": {
+                    "$sum": { "$cond": [{ "$gt": ["$outcome", Bson::Null] }, 1, 0] }
+                },
+                "wins": {
+                    "$sum": {
+                        "$cond": [
+                            {
+                                "$or": [
+                                    {
+                                        "$and": [
+                                            { "$eq": ["$participants.team", "Blue"] },
+                                            { "$eq": ["$outcome", MatchOutcome::BlueWin] },
+                                        ]
+                                    },
+                                    {
+                                        "$and": [
+                                            { "$eq": ["$participants.team", "Red"] },
+                                            { "$eq": ["$outcome", MatchOutcome::RedWin] },
+                                        ]
+                                    },
+                                ]
+                            },
+                            1,
+                            0,
+                        ]
+                    }
+                },
+                "times_captained": {
+                    "$sum": { "$cond": ["$participants.is_captain", 1, 0] }
+                },
+            }
+        },
+    ];
+
+    let cursor = collection.aggregate(pipeline, None).await?;
+    let documents: Vec<Document> = cursor.try_collect().await?;
+    documents
+        .into_iter()
+        .map(|document| from_document(document).map_err(Error::from))
+        .collect()
+}
+
+/// Get the season which has been started but not yet ended, if any.
+pub async fn get_active_season(db: Database) -> Result<Option<Season>, Error> {
+    let collection = db.collection::<Season>(SEASONS);
+    let filter = doc! { "ended": Bson::Null };
+    collection.find_one(filter, None).await
+}
+
+pub async fn find_season(db: Database, name: &str) -> Result<Option<Season>, Error> {
+    let collection = db.collection::<Season>(SEASONS);
+    let filter = doc! { "name": name };
+    collection.find_one(filter, None).await
+}
+
+/// Get a completed pug from match history, `match_age` steps back from the most recent one,
+/// optionally restricted to a single game mode and/or season.
+pub async fn get_completed_pug(
+    db: Database,
+    game_mode_label: Option<&str>,
+    season: Option<&str>,
+    match_age: u64,
+) -> Result<Option<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let mut filter = doc! { "state": PugState::Completed };
+    if let Some(label) = game_mode_label {
+        filter.insert("game_mode", label);
+    }
+    if let Some(season) = season {
+        filter.insert("season", season);
+    }
+    let options = FindOneOptions::builder()
+        // -1 sorts them in descending order
+        .sort(doc! { "created": -1 })
+        .skip(match_age)
+        .build();
+    collection.find_one(filter, options).await
+}
+
+/// Filter for records `created` in the range `[from, to)`, where either bound may be left open.
+fn created_between(from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Document {
+    let mut created_range = Document::new();
+    if let Some(from) = from {
+        created_range.insert("$gte", from.to_rfc3339());
+    }
+    if let Some(to) = to {
+        created_range.insert("$lt", to.to_rfc3339());
+    }
+    if created_range.is_empty() {
+        return Document::new();
+    }
+    doc! { "created": created_range }
+}
+
+/// Get a cursor over completed pugs created in the range `[from, to)`, oldest first,
+/// optionally restricted to a single game mode.
+pub async fn get_completed_pugs_between(
+    db: Database,
+    game_mode_label: Option<&str>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Cursor<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let mut filter = created_between(from, to);
+    filter.insert("state", PugState::Completed);
+    if let Some(label) = game_mode_label {
+        filter.insert("game_mode", label);
+    }
+    let options = FindOptions::builder().sort(doc! { "created": 1 }).build();
+    collection.find(filter, options).await
+}
+
+/// Get the schema version of a guild database. `None` means no migration has been applied.
+pub async fn get_schema_version(db: Database) -> Result<Option<SchemaVersion>, Error> {
+    let collection = db.collection::<SchemaVersion>(SCHEMA_VERSION);
+    collection.find_one(None, None).await
+}
+
+/// Get the settings of the guild, which are all defaults if none were saved yet.
+pub async fn get_guild_settings(db: Database) -> Result<GuildSettings, Error> {
+    let collection = db.collection::<GuildSettings>(GUILD_SETTINGS);
+    Ok(collection.find_one(None, None).await?.unwrap_or_default())
+}
+
+pub async fn find_incident(db: Database, incident_id: &str) -> Result<Option<Incident>, Error> {
+    let collection = db.collection::<Incident>(INCIDENTS);
+    let filter = doc! { "incident_id": incident_id };
+    collection.find_one(filter, None).await
+}
+
+/// Get the party a player is a member of (or leads), if any.
+pub async fn find_party_of(db: Database, user_id: u64) -> Result<Option<Party>, Error> {
+    let collection = db.collection::<Party>(PARTIES);
+    let filter = doc! { "members": user_id as i64 };
+    collection.find_one(filter, None).await
+}
+
+pub async fn get_parties(db: Database) -> Result<Vec<Party>, Error> {
+    let collection = db.collection::<Party>(PARTIES);
+    let cursor = collection.find(None, None).await?;
+    cursor.try_collect().await
+}
+
+/// Get the parties a player has been invited to, oldest first.
+pub async fn get_party_invites(db: Database, user_id: u64) -> Result<Vec<Party>, Error> {
+    let collection = db.collection::<Party>(PARTIES);
+    let filter = doc! { "invites": user_id as i64 };
+    let options = FindOptions::builder().sort(doc! { "created": 1 }).build();
+    let cursor = collection.find(filter, options).await?;
+    cursor.try_collect().await
+}
@@ -0,0 +1,137 @@
+//! Whether the database can be used.
+//!
+//! The [`DbClientRef`] is only stored once MongoDB has answered a ping, so until then the bot
+//! is starting up. Afterwards, [`monitor`] keeps pinging, so commands can be turned away while
+//! the database is unreachable rather than failing halfway through.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use mongodb::bson::doc;
+use mongodb::error::Error;
+use mongodb::Client;
+use serenity::client::Context;
+use serenity::prelude::TypeMapKey;
+use tokio::time::{interval, sleep, timeout, Duration, Instant};
+use tracing::{info, warn};
+
+use crate::DbClientRef;
+
+/// How long to wait for a ping to be answered.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the database is pinged once the bot is running.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(10);
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(8);
+
+/// The reply to interactions which arrive before the database is ready.
+pub const STARTING_UP_RESPONSE: &str =
+    "The bot is starting up, so this was not done. Please try again in a moment";
+/// The reply to interactions which arrive while the database is unreachable.
+pub const UNREACHABLE_RESPONSE: &str =
+    "The bot cannot reach its database right now, so this was not done. Please try again in a moment";
+
+/// Whether the database answered the last ping.
+pub struct DbReachable;
+impl TypeMapKey for DbReachable {
+    type Value = Arc<AtomicBool>;
+}
+
+pub enum DbStatus {
+    /// The database has not answered a ping yet
+    StartingUp,
+    /// The database answered a ping before, but not the last one
+    Unreachable,
+    Ready,
+}
+
+impl DbStatus {
+    /// The reply to interactions turned away because of this status, if they are.
+    pub fn unavailable_response(&self) -> Option<&'static str> {
+        match self {
+            DbStatus::StartingUp => Some(STARTING_UP_RESPONSE),
+            DbStatus::Unreachable => Some(UNREACHABLE_RESPONSE),
+            DbStatus::Ready => None,
+        }
+    }
+}
+
+pub async fn ping(client: &Client) -> Result<(), Error> {
+    let admin_db = client.database("admin");
+    match timeout(PING_TIMEOUT, admin_db.run_command(doc! { "ping": 1 }, None)).await {
+        Ok(result) => result.map(|_| ()),
+        Err(_elapsed) => Err(Error::from(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "Timed out waiting for a ping to be answered",
+        ))),
+    }
+}
+
+/// Ping the database until it answers, backing off between attempts, for up to `max_wait`.
+///
+/// Returns the error of the last attempt if the database never answered.
+pub async fn wait_until_ready(client: &Client, max_wait: Duration) -> Result<(), Error> {
+    let deadline = Instant::now() + max_wait;
+    let mut delay = FIRST_RETRY_DELAY;
+    loop {
+        let err = match ping(client).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        if Instant::now() + delay > deadline {
+            return Err(err);
+        }
+        warn!(
+            "Database did not answer a ping, retrying in {:?}: {}",
+            delay, err
+        );
+        sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+/// Keep pinging the database, recording whether it is reachable. Runs until the process exits.
+pub async fn monitor(client: Client, reachable: Arc<AtomicBool>) {
+    let mut interval = interval(MONITOR_INTERVAL);
+    loop {
+        interval.tick().await;
+        match ping(&client).await {
+            Ok(()) => {
+                if !reachable.swap(true, Ordering::Relaxed) {
+                    info!("The database is reachable again");
+                }
+            }
+            Err(err) => {
+                if reachable.swap(false, Ordering::Relaxed) {
+                    warn!("The database has become unreachable: {}", err);
+                }
+            }
+        }
+    }
+}
+
+pub async fn status(ctx: &Context) -> DbStatus {
+    let data = ctx.data.read().await;
+    if data.get::<DbClientRef>().is_none() {
+        return DbStatus::StartingUp;
+    }
+    match data.get::<DbReachable>() {
+        Some(reachable) if !reachable.load(Ordering::Relaxed) => DbStatus::Unreachable,
+        _ => DbStatus::Ready,
+    }
+}
+
+/// Wait until the database client is available, checking every few seconds.
+pub async fn client(ctx: &Context) -> Client {
+    let mut interval = interval(Duration::from_secs(5));
+    loop {
+        {
+            let data = ctx.data.read().await;
+            if let Some(client) = data.get::<DbClientRef>() {
+                return client.clone();
+            }
+        }
+        info!("Waiting for database client ready");
+        interval.tick().await;
+    }
+}
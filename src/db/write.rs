@@ -1,18 +1,18 @@
 use chrono::Utc;
-use mongodb::bson::{doc, Bson};
+use futures::future::FutureExt;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
 use mongodb::error::Error;
 use mongodb::options::{
     FindOneAndReplaceOptions, FindOneAndUpdateOptions, ReplaceOptions, ReturnDocument,
     UpdateOptions,
 };
-use mongodb::results::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResult};
-use mongodb::{Collection, Database};
-use serenity::model::application::Command;
-
-use crate::db::collection_name::PLAYER_ROSTER;
+use mongodb::results::{DeleteResult, InsertOneResult, UpdateResult};
+use mongodb::{Client, Database};
 
 use super::collection_name::{
-    COMMANDS, COMPLETED_PUGS, GAME_MODES, GAME_MODE_JOINS, PICKING_SESSIONS, PUG_CHANNELS,
+    COMMANDS, GAME_MODES, GAME_MODE_JOINS, GUILD_SETTINGS, INCIDENTS, PARTIES, PUGS, PUG_CHANNELS,
+    QUEUE_NOTIFICATIONS, SCHEMA_VERSION, SEASONS, SEASON_ARCHIVES,
 };
 use super::model::*;
 
@@ -25,10 +25,69 @@ pub async fn write_new_game_mode(
     let game_mode = GameMode {
         label,
         player_count: player_count as i64,
+        ..Default::default()
     };
     collection.insert_one(game_mode, None).await
 }
 
+/// Replace the game mode labelled `label` with `edited`.
+///
+/// If the label changed, every record referring to the game mode by its label (queue joins,
+/// pugs, queue notifications, season standings and the pug channel's allowed game modes)
+/// is updated in the same transaction.
+///
+/// Transactions require the MongoDB deployment to be a replica set.
+pub async fn edit_game_mode(
+    client: &Client,
+    db: Database,
+    label: &str,
+    edited: &GameMode,
+) -> Result<(), Error> {
+    let mut session = client.start_session(None).await?;
+    session
+        .with_transaction(
+            (&db, label, edited),
+            |session, (db, label, edited)| {
+                async move {
+                    db.collection::<GameMode>(GAME_MODES)
+                        .replace_one_with_session(doc! { "label": *label }, *edited, None, session)
+                        .await?;
+                    if edited.label == *label {
+                        return Ok(());
+                    }
+                    let new_label = edited.label.clone();
+                    for (collection_name, field) in [
+                        (GAME_MODE_JOINS, "game_mode_label"),
+                        (QUEUE_NOTIFICATIONS, "game_mode_label"),
+                        (PUGS, "game_mode"),
+                        (SEASON_ARCHIVES, "game_mode"),
+                    ] {
+                        db.collection::<Document>(collection_name)
+                            .update_many_with_session(
+                                doc! { field: *label },
+                                doc! { "$set": { field: new_label.clone() } },
+                                None,
+                                session,
+                            )
+                            .await?;
+                    }
+                    db.collection::<PugChannel>(PUG_CHANNELS)
+                        .update_many_with_session(
+                            doc! { "allowed_game_modes": *label },
+                            doc! { "$set": { "allowed_game_modes.$": new_label } },
+                            None,
+                            session,
+                        )
+                        .await?;
+                    Ok(())
+                }
+                .boxed()
+            },
+            None,
+        )
+        .await
+}
+
 pub async fn delete_game_mode(db: Database, label: String) -> Result<DeleteResult, Error> {
     let collection = db.collection::<GameMode>(GAME_MODES);
     let query = doc! {
@@ -42,16 +101,16 @@ pub async fn delete_game_mode(db: Database, label: String) -> Result<DeleteResul
 /// join timestamp is merely updated.
 pub async fn add_player_to_game_mode_queue(
     db: Database,
-    game_mode_label: &String,
+    game_mode_label: &str,
     player_user_id: &u64,
 ) -> Result<Option<GameModeJoin>, Error> {
     let collection = db.collection(GAME_MODE_JOINS);
     let filter = doc! {
-        "game_mode_label": game_mode_label.clone(),
-        "player_user_id": player_user_id.clone() as i64
+        "game_mode_label": game_mode_label,
+        "player_user_id": *player_user_id as i64
     };
     let join_record = GameModeJoin {
-        game_mode_label: game_mode_label.clone(),
+        game_mode_label: game_mode_label.to_string(),
         player_user_id: *player_user_id as i64,
         joined: Utc::now(),
     };
@@ -78,305 +137,540 @@ pub async fn remove_player_from_game_mode_queue(
     collection.find_one_and_delete(filter, None).await
 }
 
-pub async fn remove_players_from_all_queues(
+/// Remove a player from every queue they are in, returning the join records removed.
+pub async fn remove_player_from_all_queues(
     db: Database,
-    players_user_ids: &Vec<u64>,
-) -> Result<DeleteResult, Error> {
+    player_user_id: u64,
+) -> Result<Vec<GameModeJoin>, Error> {
     let collection = db.collection::<GameModeJoin>(GAME_MODE_JOINS);
+    let filter = doc! { "player_user_id": player_user_id as i64 };
+    let removed: Vec<GameModeJoin> = collection
+        .find(filter.clone(), None)
+        .await?
+        .try_collect()
+        .await?;
+    collection.delete_many(filter, None).await?;
+    Ok(removed)
+}
 
-    let participants = players_user_ids
-        .iter()
-        .map(|id| *id as i64)
-        .collect::<Vec<i64>>();
-
+/// Save a pug which has just filled, removing its players from all queues in the same
+/// transaction, so players are never removed from queues without a pug being saved (or vice versa).
+///
+/// Returns the join records which were removed, so they can be restored with
+/// [`unregister_filled_pug`] if setting up the pug on Discord fails.
+///
+/// Transactions require the MongoDB deployment to be a replica set.
+pub async fn register_filled_pug(
+    client: &Client,
+    db: Database,
+    pug: &Pug,
+) -> Result<Vec<GameModeJoin>, Error> {
+    let joins = db.collection::<GameModeJoin>(GAME_MODE_JOINS);
+    let pugs = db.collection::<Pug>(PUGS);
     let filter = doc! {
         "player_user_id": {
-            "$in": participants
+            "$in": pug.players.clone()
         }
     };
 
-    collection.delete_many(filter, None).await
+    let mut session = client.start_session(None).await?;
+    session
+        .with_transaction(
+            (&joins, &pugs, &filter, pug),
+            |session, (joins, pugs, filter, pug)| {
+                async move {
+                    let removed_joins = joins
+                        .find_with_session(filter.clone(), None, session)
+                        .await?
+                        .stream(session)
+                        .try_collect::<Vec<GameModeJoin>>()
+                        .await?;
+                    joins
+                        .delete_many_with_session(filter.clone(), None, session)
+                        .await?;
+                    pugs.insert_one_with_session(*pug, None, session).await?;
+                    Ok(removed_joins)
+                }
+                .boxed()
+            },
+            None,
+        )
+        .await
 }
 
-/// Remove players from the queue of the specified game mode and put them on
-/// a roster for a picking session.
-///
-/// Uses mongodb session feature for atomicity.
-pub async fn register_picking_session(
+/// Undo [`register_filled_pug`]: delete the pug and return its players to the queues
+/// they were removed from, keeping their original join times.
+pub async fn unregister_filled_pug(
+    client: &Client,
     db: Database,
-    pug_thread_channel_id: &u64,
-    game_mode_label: &String,
-    players: &Vec<u64>,
-    pick_sequence: Vec<Team>,
-) -> Result<InsertOneResult, Error> {
-    // FIXME: use session for atomicity!
-    let picking_session_collection = db.collection(PICKING_SESSIONS);
-    let player_roster_collection = db.collection(PLAYER_ROSTER);
-
-    let roster = players
-        .iter()
-        .map(|user_id| Player {
-            is_captain: false,
-            user_id: *user_id as i64,
-            team: None,
-            exclude_from_random_captaining: false,
-            channel_id_for_picking_session: *pug_thread_channel_id as i64,
-            pick_position: None,
-        })
-        .collect::<Vec<Player>>();
-
-    player_roster_collection.insert_many(roster, None).await?;
-
-    let picking_session = PickingSession {
-        created: Utc::now(),
-        game_mode: game_mode_label.to_string(),
-        thread_channel_id: *pug_thread_channel_id as i64,
-        pick_sequence,
-        last_reset: None,
-    };
-
-    picking_session_collection
-        .insert_one(picking_session, None)
+    thread_channel_id: i64,
+    removed_joins: &[GameModeJoin],
+) -> Result<(), Error> {
+    let joins = db.collection::<GameModeJoin>(GAME_MODE_JOINS);
+    let pugs = db.collection::<Pug>(PUGS);
+
+    let mut session = client.start_session(None).await?;
+    session
+        .with_transaction(
+            (&joins, &pugs, removed_joins),
+            |session, (joins, pugs, removed_joins)| {
+                async move {
+                    pugs.delete_one_with_session(
+                        doc! { "thread_channel_id": thread_channel_id },
+                        None,
+                        session,
+                    )
+                    .await?;
+                    // a player may have joined a queue again in the meantime,
+                    // so join records are replaced rather than inserted
+                    let options = ReplaceOptions::builder().upsert(true).build();
+                    for join_record in removed_joins.iter() {
+                        let filter = doc! {
+                            "game_mode_label": join_record.game_mode_label.clone(),
+                            "player_user_id": join_record.player_user_id
+                        };
+                        joins
+                            .replace_one_with_session(filter, join_record, options.clone(), session)
+                            .await?;
+                    }
+                    Ok(())
+                }
+                .boxed()
+            },
+            None,
+        )
         .await
 }
 
-/// Creates a completed pug record and
-/// clears the queue for the game mode
-pub async fn register_completed_pug(
+/// Apply `update` to a pug which is still in the picking state, but only if it has not
+/// changed since `pug` was read from the database. The version of the pug is incremented.
+///
+/// Returns the updated pug, or `None` if the update was not applied because the pug
+/// was changed in the meantime (or is no longer being picked).
+async fn update_picking_pug(
     db: Database,
-    completed_pug: &CompletedPug,
-) -> Result<InsertOneResult, Error> {
-    // FIXME: use sessions
-    let completed_pug_collection = db.collection::<CompletedPug>(COMPLETED_PUGS);
-    let result = completed_pug_collection
-        .insert_one(completed_pug, None)
-        .await?;
+    pug: &Pug,
+    mut update: Document,
+) -> Result<Option<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let filter = doc! {
+        "thread_channel_id": pug.thread_channel_id,
+        "state": PugState::Picking,
+        "version": pug.version,
+    };
+    update.insert("$inc", doc! { "version": 1 });
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
+    collection
+        .find_one_and_update(filter, update, options)
+        .await
+}
 
-    let picking_session_query = doc! {
-        "thread_channel_id": completed_pug.thread_channel_id.clone()
+/// Build a `$push` for appending picked players to the end of their team arrays.
+fn push_picks(picks: &[(i64, Team)]) -> Document {
+    let picked_for = |team: Team| {
+        picks
+            .iter()
+            .filter(|(_, t)| *t == team)
+            .map(|(user_id, _)| *user_id)
+            .collect::<Vec<i64>>()
     };
+    doc! {
+        "blue_team": { "$each": picked_for(Team::Blue) },
+        "red_team": { "$each": picked_for(Team::Red) },
+    }
+}
 
-    let picking_session_collection = db.collection::<PickingSession>(PICKING_SESSIONS);
-    picking_session_collection
-        .delete_one(picking_session_query, None)
-        .await?;
+/// Make players captains of a pug. Teams which are `None` keep their current captain (if any).
+pub async fn set_captains(
+    db: Database,
+    pug: &Pug,
+    blue_team_captain: Option<i64>,
+    red_team_captain: Option<i64>,
+) -> Result<Option<Pug>, Error> {
+    let mut captains = Document::new();
+    if let Some(user_id) = blue_team_captain {
+        captains.insert("blue_team_captain", user_id);
+    }
+    if let Some(user_id) = red_team_captain {
+        captains.insert("red_team_captain", user_id);
+    }
+    update_picking_pug(db, pug, doc! { "$set": captains }).await
+}
+
+/// Add players to teams, e.g. a picked player along with their party.
+pub async fn pick_players(
+    db: Database,
+    pug: &Pug,
+    picks: &[(i64, Team)],
+) -> Result<Option<Pug>, Error> {
+    let update = doc! { "$push": push_picks(picks) };
+    update_picking_pug(db, pug, update).await
+}
 
-    Ok(result)
+/// Make the final picks of a pug and mark it completed, tagging it with the active season (if any).
+pub async fn complete_picking(
+    db: Database,
+    pug: &Pug,
+    final_picks: &[(i64, Team)],
+    season: Option<String>,
+) -> Result<Option<Pug>, Error> {
+    let update = doc! {
+        "$push": push_picks(final_picks),
+        "$set": {
+            "state": PugState::Completed,
+            "season": season,
+        }
+    };
+    update_picking_pug(db, pug, update).await
 }
 
-pub async fn pick_player_for_team(
+/// Save a captain's report of a completed pug's result, unless a result was already
+/// recorded or reported.
+///
+/// Returns the updated pug, or `None` if nothing was saved.
+pub async fn report_result(
     db: Database,
-    &thread_channel_id: &u64,
-    &player_user_id: &u64,
-    &team: &Team,
-    &pick_position: &usize,
-) -> Result<Option<Player>, Error> {
-    let collection = db.collection(PLAYER_ROSTER);
+    thread_channel_id: i64,
+    report: &ResultReport,
+) -> Result<Option<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
     let filter = doc! {
-        "channel_id_for_picking_session": thread_channel_id.to_string(),
-        "user_id": player_user_id.to_string(),
+        "thread_channel_id": thread_channel_id,
+        "state": PugState::Completed,
+        "outcome": Bson::Null,
+        "result_report": Bson::Null,
     };
     let update = doc! {
         "$set": {
-            "team": team,
-            "pick_position": pick_position.to_string(),
-        }
+            "result_report": mongodb::bson::to_bson(report)
+                .expect("Expected a result report to be serializable")
+        },
+        "$inc": { "version": 1 },
     };
-    collection.find_one_and_update(filter, update, None).await
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
+    collection
+        .find_one_and_update(filter, update, options)
+        .await
+}
+
+/// Apply `update` to a pug with a pending result report, unless the pug was changed since
+/// it was read from the database or its report was escalated. The version of the pug is
+/// incremented.
+async fn update_reported_pug(
+    db: Database,
+    pug: &Pug,
+    mut update: Document,
+) -> Result<Option<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let filter = doc! {
+        "thread_channel_id": pug.thread_channel_id,
+        "version": pug.version,
+        "outcome": Bson::Null,
+        "result_report.escalated": false,
+    };
+    update.insert("$inc", doc! { "version": 1 });
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
+    collection
+        .find_one_and_update(filter, update, options)
+        .await
 }
 
-pub async fn reset_pug(db: Database, &thread_channel_id: &u64) -> Result<UpdateResult, Error> {
-    let collection = db.collection::<Player>(PLAYER_ROSTER);
-    let query = doc! {"channel_id_for_picking_session": thread_channel_id as i64};
+/// Record the outcome of a pug's reported result once the other captain confirms it, but only
+/// if the report is still the one pending (and has not been escalated).
+///
+/// Returns the updated pug, or `None` if the pug changed in the meantime.
+pub async fn confirm_result(db: Database, pug: &Pug) -> Result<Option<Pug>, Error> {
+    let report = match &pug.result_report {
+        Some(report) => report,
+        None => return Ok(None),
+    };
     let update = doc! {
         "$set": {
-            "is_captain": false,
-            "exclude_from_random_captaining": false,
-            "team": Bson::Null,
-            "pick_position": Bson::Null
-        }
+            "outcome": report.outcome,
+            "score": report.score.clone(),
+            "reported_by": report.reporter,
+        },
+        "$unset": { "result_report": "" }
     };
-    collection.update_many(query, update, None).await
+    update_reported_pug(db, pug, update).await
 }
 
-pub async fn exclude_player_from_random_captaining() -> Result<(), ()> {
-    todo!();
+/// Leave a pug's reported result for a moderator to settle, if it is still the one pending.
+///
+/// Returns the updated pug, or `None` if the pug changed (or was escalated) in the meantime.
+pub async fn escalate_result_report(db: Database, pug: &Pug) -> Result<Option<Pug>, Error> {
+    let update = doc! { "$set": { "result_report.escalated": true } };
+    update_reported_pug(db, pug, update).await
 }
 
-pub async fn set_pug_channel(
+/// Set (or change) the result of a completed pug, discarding any pending report.
+///
+/// Returns the updated pug, or `None` if there is no completed pug in the thread.
+pub async fn set_result(
     db: Database,
-    channel_id: u64,
-    channel_name: Option<String>,
-    allowed_game_modes: Vec<String>,
-) -> Result<UpdateResult, Error> {
-    let collection = db.collection(PUG_CHANNELS);
-
-    let desired_pug_channel = PugChannel {
-        channel_id: channel_id as i64,
-        name: channel_name,
-        allowed_game_modes,
+    thread_channel_id: i64,
+    outcome: MatchOutcome,
+    score: Option<String>,
+    reported_by: i64,
+) -> Result<Option<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let filter = doc! {
+        "thread_channel_id": thread_channel_id,
+        "state": PugState::Completed,
     };
-
-    // since we currently only permit one pug channel at a time
-    let any = doc! {};
-    let options = ReplaceOptions::builder().upsert(true).build();
+    let update = doc! {
+        "$set": {
+            "outcome": outcome,
+            "score": score,
+            "reported_by": reported_by,
+        },
+        "$unset": { "result_report": "" },
+        "$inc": { "version": 1 },
+    };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
     collection
-        .replace_one(any, desired_pug_channel, options)
+        .find_one_and_update(filter, update, options)
         .await
 }
 
-pub async fn register_guild_command(
+/// Add a map to the map pool of a game mode.
+pub async fn add_map(
     db: Database,
-    guild_command: &Command,
-) -> Result<InsertOneResult, Error> {
-    db.collection(COMMANDS)
-        .insert_one(
-            GuildCommand {
-                command_id: guild_command.id.get() as i64,
-                name: guild_command.name.clone(),
-            },
+    game_mode_label: &str,
+    map: &str,
+) -> Result<UpdateResult, Error> {
+    db.collection::<GameMode>(GAME_MODES)
+        .update_one(
+            doc! { "label": game_mode_label },
+            doc! { "$addToSet": { "maps": map } },
             None,
         )
         .await
 }
 
-/// Delete ALL saved guild commands.
-pub async fn clear_guild_commands(db: Database) -> Result<DeleteResult, Error> {
-    let all = doc! {};
-    db.collection::<GuildCommand>(COMMANDS)
-        .delete_many(all, None)
+/// Remove a map from the map pool of a game mode.
+pub async fn remove_map(
+    db: Database,
+    game_mode_label: &str,
+    map: &str,
+) -> Result<UpdateResult, Error> {
+    db.collection::<GameMode>(GAME_MODES)
+        .update_one(
+            doc! { "label": game_mode_label },
+            doc! { "$pull": { "maps": map } },
+            None,
+        )
         .await
 }
 
-/// Delete any guild commands with names which match any in the provided iterable.
-pub async fn find_and_delete_guild_commands<S, I>(
+/// Start a map vote on a completed pug, unless it already has a map or a vote.
+///
+/// Returns the updated pug, or `None` if nothing was saved.
+pub async fn start_map_vote(
     db: Database,
-    command_names: I,
-) -> Result<DeleteResult, mongodb::error::Error>
-where
-    S: AsRef<str>,
-    I: IntoIterator<Item = S>,
-{
-    let collection: Collection<GuildCommand> = db.collection(COMMANDS);
-    
+    thread_channel_id: i64,
+    map_vote: &MapVote,
+) -> Result<Option<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
     let filter = doc! {
-        "name": {
-            "$in": command_names.into_iter().map(|s| s.as_ref().to_string()).collect::<Vec<String>>()
+        "thread_channel_id": thread_channel_id,
+        "state": PugState::Completed,
+        "map": Bson::Null,
+        "map_vote": Bson::Null,
+    };
+    let update = doc! {
+        "$set": {
+            "map_vote": mongodb::bson::to_bson(map_vote)
+                .expect("Expected a map vote to be serializable")
         }
     };
-
-    collection.delete_many(filter, None).await
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
+    collection
+        .find_one_and_update(filter, update, options)
+        .await
 }
 
-pub async fn save_guild_commands(db: Database, commands: Vec<Command>) -> Result<(), Error> {
-    for command in &commands {
-        let command_to_save = GuildCommand {
-            command_id: command.id.get() as i64,
-            name: command.name.clone(),
-        };
-
-        let filter = doc! { "command_id": command_to_save.command_id };
-        let update = doc! { "$set": { "name": command_to_save.name.clone() } };
-        let options = UpdateOptions::builder().upsert(true).build();
-
-        db.collection::<GuildCommand>(COMMANDS)
-            .update_one(filter, update, options)
-            .await?;
-    }
-
-    Ok(())
+/// Remember the message with the buttons of a map vote.
+pub async fn set_map_vote_message(
+    db: Database,
+    thread_channel_id: i64,
+    message_id: u64,
+) -> Result<UpdateResult, Error> {
+    db.collection::<Pug>(PUGS)
+        .update_one(
+            doc! {
+                "thread_channel_id": thread_channel_id,
+                "map_vote": { "$ne": Bson::Null },
+            },
+            doc! { "$set": { "map_vote.message_id": message_id as i64 } },
+            None,
+        )
+        .await
 }
 
-/// Updates a [`Player`] record to grant it captaincy.
-pub async fn set_one_captain(
+/// Record (or change) a player's vote in the running map vote of a pug.
+///
+/// Returns the updated pug, or `None` if no vote is running.
+pub async fn cast_map_vote(
     db: Database,
-    &thread_channel_id: &u64,
-    &user_id: &u64,
-    team: Team,
-) -> Result<Option<Player>, Error> {
-    let collection = db.collection(PLAYER_ROSTER);
+    thread_channel_id: i64,
+    voter: u64,
+    map: &str,
+) -> Result<Option<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
     let filter = doc! {
-        "channel_id_for_picking_session": thread_channel_id as i64,
-        "user_id": user_id as i64
+        "thread_channel_id": thread_channel_id,
+        "map_vote": { "$ne": Bson::Null },
     };
-
     let update = doc! {
-        "$set": {
-            "team": team,
-            "is_captain": true,
-            // TODO: why does setting pick_position to None not work
-            "pick_position": Bson::Null
-        }
+        "$set": { format!("map_vote.votes.{}", voter): map }
     };
-
     let options = FindOneAndUpdateOptions::builder()
-        .upsert(Some(false))
+        .return_document(Some(ReturnDocument::After))
         .build();
-
     collection
         .find_one_and_update(filter, update, options)
         .await
 }
 
-/// A struct that represents the result of the database operations to:
+/// Set the map of a completed pug, ending its map vote if one is running.
+/// A pug's map is only ever set once.
 ///
-/// Search for two particular [`Player`]s (whom are becoming blue and red team captains)
-/// and update their documents to reflect that they are now captains.
-pub struct CaptainPair {
-    pub blue: Option<Player>,
-    pub red: Option<Player>,
-}
-
-/// Updates two (for blue and red team) [`Player`] records to grant them captaincy.
-pub async fn set_both_captains(
+/// Returns the updated pug, or `None` if nothing was saved.
+pub async fn set_pug_map(
     db: Database,
-    &thread_channel_id: &u64,
-    &blue_team_captain_user_id: &u64,
-    &red_team_captain_user_id: &u64,
-) -> Result<CaptainPair, Error> {
-    // !FIXME: use sessions
-    let collection = db.collection::<Player>(PLAYER_ROSTER);
-    let blue_captain_filter = doc! {
-        "channel_id_for_picking_session": thread_channel_id as i64,
-        "user_id": blue_team_captain_user_id as i64
+    thread_channel_id: i64,
+    map: &str,
+) -> Result<Option<Pug>, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let filter = doc! {
+        "thread_channel_id": thread_channel_id,
+        "state": PugState::Completed,
+        "map": Bson::Null,
     };
+    let update = doc! {
+        "$set": { "map": map },
+        "$unset": { "map_vote": "" }
+    };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
+    collection
+        .find_one_and_update(filter, update, options)
+        .await
+}
 
-    let blue_captain_update = doc! {
+/// Clear the captains and picks of a pug, so captaining starts over.
+pub async fn reset_pug(db: Database, pug: &Pug) -> Result<Option<Pug>, Error> {
+    let now = mongodb::bson::to_bson(&Utc::now()).expect("Expected a timestamp to be serializable");
+    let update = doc! {
         "$set": {
-            "team": Team::Blue,
-            "is_captain": true,
-            "pick_position": Bson::Null
+            "blue_team_captain": Bson::Null,
+            "blue_team": [],
+            "red_team_captain": Bson::Null,
+            "red_team": [],
+            "last_reset": now,
         }
-
     };
+    update_picking_pug(db, pug, update).await
+}
 
-    let red_captain_filter = doc! {
-        "channel_id_for_picking_session": thread_channel_id as i64,
-        "user_id": red_team_captain_user_id as i64,
+/// Record the voice channels created for the teams of a completed pug.
+pub async fn set_pug_voice_chat(
+    db: Database,
+    thread_channel_id: i64,
+    voice_chat: &TeamVoiceChat,
+) -> Result<UpdateResult, Error> {
+    let collection = db.collection::<Pug>(PUGS);
+    let filter = doc! { "thread_channel_id": thread_channel_id };
+    let voice_chat =
+        mongodb::bson::to_bson(voice_chat).expect("Expected voice chat state to be serializable");
+    let update = doc! {
+        "$set": { "voice_chat": voice_chat },
+        "$inc": { "version": 1 },
     };
+    collection.update_one(filter, update, None).await
+}
 
-    let red_captain_update = doc! {
-        "$set": {
-            "team": Team::Red,
-            "is_captain": true,
-            "pick_position": Bson::Null
-        }
+pub async fn exclude_player_from_random_captaining() -> Result<(), ()> {
+    todo!();
+}
 
+pub async fn set_pug_channel(
+    db: Database,
+    channel_id: u64,
+    channel_name: Option<String>,
+    allowed_game_modes: Vec<String>,
+) -> Result<UpdateResult, Error> {
+    let collection = db.collection(PUG_CHANNELS);
+
+    let desired_pug_channel = PugChannel {
+        channel_id: channel_id as i64,
+        name: channel_name,
+        allowed_game_modes,
+        board_message_id: None,
     };
 
-    let options = FindOneAndUpdateOptions::builder()
-        .upsert(Some(false))
-        .build();
+    // since we currently only permit one pug channel at a time
+    let any = doc! {};
+    let options = ReplaceOptions::builder().upsert(true).build();
+    collection
+        .replace_one(any, desired_pug_channel, options)
+        .await
+}
 
-    let blue = collection
-        .find_one_and_update(blue_captain_filter, blue_captain_update, options.clone())
-        .await?;
+pub async fn set_queue_board_message(
+    db: Database,
+    channel_id: u64,
+    board_message_id: u64,
+) -> Result<UpdateResult, Error> {
+    let collection = db.collection::<PugChannel>(PUG_CHANNELS);
+    let filter = doc! { "channel_id": channel_id as i64 };
+    let update = doc! { "$set": { "board_message_id": board_message_id as i64 } };
+    collection.update_one(filter, update, None).await
+}
 
-    let red = collection
-        .find_one_and_update(red_captain_filter, red_captain_update, options)
+/// Make the saved guild commands exactly `commands`: records are replaced by name,
+/// and records of any other commands are deleted.
+pub async fn replace_guild_commands(
+    db: Database,
+    commands: Vec<GuildCommand>,
+) -> Result<(), Error> {
+    let collection = db.collection::<GuildCommand>(COMMANDS);
+    let names = commands
+        .iter()
+        .map(|command| command.name.clone())
+        .collect::<Vec<String>>();
+    for command in commands {
+        let filter = doc! { "name": command.name.clone() };
+        let options = ReplaceOptions::builder().upsert(true).build();
+        collection.replace_one(filter, command, options).await?;
+    }
+    collection
+        .delete_many(doc! { "name": { "$nin": names } }, None)
         .await?;
+    Ok(())
+}
 
-    Ok(CaptainPair { blue, red })
+/// Record whether `.configure` was run in the guild.
+pub async fn set_commands_configured(
+    db: Database,
+    configured: bool,
+) -> Result<UpdateResult, Error> {
+    let collection = db.collection::<GuildSettings>(GUILD_SETTINGS);
+    let update = doc! { "$set": { "commands_configured": configured } };
+    let options = UpdateOptions::builder().upsert(true).build();
+    collection.update_one(doc! {}, update, options).await
 }
 
 // !FIXME: this is horribly inefficient, but might be fine for relatively
@@ -385,7 +679,7 @@ pub async fn mark_voice_channels_deleted(
     db: Database,
     channel_ids: Vec<i64>,
 ) -> Result<UpdateResult, Error> {
-    let collection = db.collection::<CompletedPug>(COMPLETED_PUGS);
+    let collection = db.collection::<Pug>(PUGS);
 
     let query = doc! {
         "$or": [
@@ -408,10 +702,261 @@ pub async fn mark_voice_channels_deleted(
     };
 
     let update = doc! {
-        "voice_chat.category.is_deleted_from_guild_channel_list": true,
-        "voice_chat.blue_channel.is_deleted_from_guild_channel_list": true,
-        "voice_chat.red_channel.is_deleted_from_guild_channel_list": true
+        "$set": {
+            "voice_chat.category.is_deleted_from_guild_channel_list": true,
+            "voice_chat.blue_channel.is_deleted_from_guild_channel_list": true,
+            "voice_chat.red_channel.is_deleted_from_guild_channel_list": true
+        }
     };
 
     collection.update_many(query, update, None).await
 }
+
+/// Create or replace a user's queue notification subscription for a game mode.
+/// A user can only have one subscription per game mode.
+pub async fn set_queue_notification(
+    db: Database,
+    subscription: QueueNotification,
+) -> Result<UpdateResult, Error> {
+    let collection = db.collection::<QueueNotification>(QUEUE_NOTIFICATIONS);
+    let filter = doc! {
+        "user_id": subscription.user_id,
+        "game_mode_label": subscription.game_mode_label.clone(),
+    };
+    let options = ReplaceOptions::builder().upsert(true).build();
+    collection.replace_one(filter, subscription, options).await
+}
+
+pub async fn delete_queue_notification(
+    db: Database,
+    &user_id: &u64,
+    game_mode_label: &str,
+) -> Result<DeleteResult, Error> {
+    let collection = db.collection::<QueueNotification>(QUEUE_NOTIFICATIONS);
+    let filter = doc! {
+        "user_id": user_id as i64,
+        "game_mode_label": game_mode_label,
+    };
+    collection.delete_one(filter, None).await
+}
+
+/// Record that the provided users have just been alerted about a game mode's queue,
+/// so the cooldown applies from now on.
+pub async fn mark_queue_notifications_sent(
+    db: Database,
+    game_mode_label: &str,
+    user_ids: &[i64],
+) -> Result<UpdateResult, Error> {
+    let collection = db.collection::<QueueNotification>(QUEUE_NOTIFICATIONS);
+    let filter = doc! {
+        "game_mode_label": game_mode_label,
+        "user_id": {
+            "$in": user_ids
+        }
+    };
+    // Serialize the timestamp the same way serde does for the model,
+    // so it can be read back into a `DateTime<Utc>`
+    let now = mongodb::bson::to_bson(&Utc::now()).expect("Expected a timestamp to be serializable");
+    let update = doc! {
+        "$set": {
+            "last_notified": now
+        }
+    };
+    collection.update_many(filter, update, None).await
+}
+
+pub async fn start_season(db: Database, name: String) -> Result<InsertOneResult, Error> {
+    let collection = db.collection::<Season>(SEASONS);
+    let season = Season {
+        name,
+        started: Utc::now(),
+        ended: None,
+    };
+    collection.insert_one(season, None).await
+}
+
+/// Mark the active season as ended and save its final standings, in one transaction.
+///
+/// Returns the season as it was before it ended, or `None` if no season was active
+/// (in which case nothing is saved).
+pub async fn end_season(
+    client: &Client,
+    db: Database,
+    standings: Vec<SeasonStanding>,
+) -> Result<Option<Season>, Error> {
+    let seasons = db.collection::<Season>(SEASONS);
+    let archives = db.collection::<SeasonStanding>(SEASON_ARCHIVES);
+    let now = mongodb::bson::to_bson(&Utc::now()).expect("Expected a timestamp to be serializable");
+
+    let mut session = client.start_session(None).await?;
+    session
+        .with_transaction(
+            (&seasons, &archives, &standings, &now),
+            |session, (seasons, archives, standings, now)| {
+                async move {
+                    let ended = seasons
+                        .find_one_and_update_with_session(
+                            doc! { "ended": Bson::Null },
+                            doc! { "$set": { "ended": (*now).clone() } },
+                            None,
+                            session,
+                        )
+                        .await?;
+                    // insert_many rejects an empty list of documents
+                    if ended.is_some() && !standings.is_empty() {
+                        archives
+                            .insert_many_with_session(standings.iter(), None, session)
+                            .await?;
+                    }
+                    Ok(ended)
+                }
+                .boxed()
+            },
+            None,
+        )
+        .await
+}
+
+/// Record the latest migration applied to a guild database.
+/// There is only ever one schema version document.
+pub async fn set_schema_version(db: Database, version: i64) -> Result<UpdateResult, Error> {
+    let collection = db.collection::<SchemaVersion>(SCHEMA_VERSION);
+    let schema_version = SchemaVersion {
+        version,
+        updated: Utc::now(),
+    };
+    let options = ReplaceOptions::builder().upsert(true).build();
+    collection
+        .replace_one(doc! {}, schema_version, options)
+        .await
+}
+
+pub async fn insert_incident(db: Database, incident: &Incident) -> Result<InsertOneResult, Error> {
+    let collection = db.collection::<Incident>(INCIDENTS);
+    collection.insert_one(incident, None).await
+}
+
+/// Attach a note to an incident, but only if it is one the user ran into.
+///
+/// Returns whether a matching incident was found.
+pub async fn add_incident_note(
+    db: Database,
+    incident_id: &str,
+    user_id: u64,
+    note: String,
+) -> Result<bool, Error> {
+    let collection = db.collection::<Incident>(INCIDENTS);
+    let filter = doc! {
+        "incident_id": incident_id,
+        "user_id": user_id as i64,
+    };
+    let note = IncidentNote {
+        added: Utc::now(),
+        note,
+    };
+    let update = doc! {
+        "$push": {
+            "notes": mongodb::bson::to_bson(&note).expect("Expected an incident note to be serializable")
+        }
+    };
+    let result = collection.update_one(filter, update, None).await?;
+    Ok(result.matched_count > 0)
+}
+
+/// Invite a player to the party led by `leader_user_id`, creating the party if needed.
+pub async fn invite_to_party(
+    db: Database,
+    leader_user_id: u64,
+    user_id: u64,
+) -> Result<UpdateResult, Error> {
+    let collection = db.collection::<Party>(PARTIES);
+    let now = mongodb::bson::to_bson(&Utc::now()).expect("Expected a timestamp to be serializable");
+    let filter = doc! { "leader": leader_user_id as i64 };
+    let update = doc! {
+        "$setOnInsert": {
+            "members": [leader_user_id as i64],
+            "created": now,
+        },
+        "$addToSet": {
+            "invites": user_id as i64
+        }
+    };
+    let options = UpdateOptions::builder().upsert(true).build();
+    collection.update_one(filter, update, options).await
+}
+
+/// Move a player from the invites of a party to its members.
+///
+/// Returns the updated party, or `None` if the player was not invited to it
+/// (e.g. it was disbanded in the meantime).
+pub async fn accept_party_invite(
+    db: Database,
+    leader_user_id: i64,
+    user_id: u64,
+) -> Result<Option<Party>, Error> {
+    let collection = db.collection::<Party>(PARTIES);
+    let filter = doc! {
+        "leader": leader_user_id,
+        "invites": user_id as i64,
+    };
+    let update = doc! {
+        "$pull": { "invites": user_id as i64 },
+        "$push": { "members": user_id as i64 },
+    };
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(Some(ReturnDocument::After))
+        .build();
+    collection
+        .find_one_and_update(filter, update, options)
+        .await
+}
+
+/// Remove a player from their party. When the leader leaves, the longest standing member
+/// leads the party, and a party which would be left with a single member is deleted.
+///
+/// Returns the party as it was before the player left, or `None` if they were not in one
+/// (or the party changed while they were leaving).
+pub async fn leave_party(db: Database, user_id: u64) -> Result<Option<Party>, Error> {
+    let collection = db.collection::<Party>(PARTIES);
+    let user_id = user_id as i64;
+    let party = match collection
+        .find_one(doc! { "members": user_id }, None)
+        .await?
+    {
+        Some(party) => party,
+        None => return Ok(None),
+    };
+    let remaining = party
+        .members
+        .iter()
+        .filter(|&&member| member != user_id)
+        .copied()
+        .collect::<Vec<i64>>();
+    // the version of the party which was read is matched, so concurrent changes are not lost
+    let filter = doc! {
+        "leader": party.leader,
+        "members": party.members.clone(),
+    };
+    let changed = if remaining.len() < 2 {
+        collection.delete_one(filter, None).await?.deleted_count
+    } else {
+        let update = doc! {
+            "$set": {
+                "leader": remaining[0],
+                "members": remaining,
+            }
+        };
+        collection
+            .update_one(filter, update, None)
+            .await?
+            .modified_count
+    };
+    Ok(if changed > 0 { Some(party) } else { None })
+}
+
+/// Delete the party led by a player, returning it if there was one.
+pub async fn disband_party(db: Database, leader_user_id: u64) -> Result<Option<Party>, Error> {
+    let collection = db.collection::<Party>(PARTIES);
+    let filter = doc! { "leader": leader_user_id as i64 };
+    collection.find_one_and_delete(filter, None).await
+}
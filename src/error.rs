@@ -13,6 +13,12 @@ pub enum RustyError {
     Mongo(mongodb::error::Error),
     #[error("serenity returned an error")]
     Serenity(serenity::Error),
+    #[error("syncing guild commands failed")]
+    CommandSync(anyhow::Error),
+    #[error(
+        "migrations up to schema version {pending} were only dry run, the database is at {current}"
+    )]
+    PendingMigrations { current: i64, pending: i64 },
 }
 
 impl From<mongodb::error::Error> for RustyError {
@@ -33,6 +39,8 @@ pub type Error = RustyError;
 pub enum SetCaptainErr {
     #[error("User is already a captain")]
     IsCaptainAlready,
+    #[error("User is already on a team")]
+    OnTeamAlready,
     #[error("There are no captain spots available")]
     CaptainSpotsFilled,
     #[error("The captain spots availability is corrupted")]
@@ -45,9 +53,16 @@ pub enum SetCaptainErr {
     InvalidCount,
     #[error("The thread id provided did not yield a valid picking session with players")]
     NoPlayers,
+    #[error("The pug was changed by someone else while captains were being set")]
+    Conflict,
     #[error(
         "It seems captaining operations were executed without error but completed \
     but the current state is unexpected"
     )]
     Unknown,
 }
+
+/// Problems found in the configuration, all of them at once rather than one per attempt at starting the bot.
+#[derive(ThisError, Debug)]
+#[error("Invalid configuration:\n- {}", .0.join("\n- "))]
+pub struct ConfigError(pub Vec<String>);
@@ -1,8 +1,7 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use nanoid::nanoid;
 use serenity::all::ActivityData;
 use serenity::async_trait;
 use serenity::builder::{
@@ -11,16 +10,22 @@ use serenity::builder::{
 use serenity::model::application::Interaction;
 use serenity::model::channel::Message;
 use serenity::model::gateway::Ready;
-use serenity::model::guild::Guild;
-use serenity::model::id::GuildId;
+use serenity::model::guild::{Guild, UnavailableGuild};
+use serenity::model::id::{ChannelId, GuildId, MessageId};
 use serenity::prelude::*;
 use tracing::{error, info, instrument};
 
-// use crate::db::DEFAULT_MONGO_READY_MAX_WAIT;
+use crate::db::readiness;
 use crate::interaction_handlers::*;
-use crate::jobs::{clear_out_stale_joins, log_system_load, remove_stale_team_voice_channels};
+use crate::jobs::{
+    clear_out_stale_joins, end_overdue_map_votes, escalate_unconfirmed_results, log_system_load,
+    remove_stale_team_voice_channels,
+};
+use crate::utils::incident::IncidentSource;
 use crate::utils::onboarding::inspect_guild_commands;
-use crate::DbClientRef;
+use crate::utils::ops::{self, OpsEvent};
+use crate::utils::queue_board;
+use crate::utils::shutdown;
 
 #[derive(Debug)]
 pub struct Handler {
@@ -37,21 +42,65 @@ impl EventHandler for Handler {
         } else if msg_content.starts_with(".help") || msg_content.starts_with("!help") {
             meta::render_help_text()
         } else if msg_content.starts_with(".configure") {
-            if msg.guild_id.is_some() {
+            let in_flight = shutdown::get(&ctx).await.track();
+            if in_flight.is_none() {
+                shutdown::RESTARTING_RESPONSE.to_string()
+            } else if let Some(reason) = readiness::status(&ctx).await.unavailable_response() {
+                reason.to_string()
+            } else if msg.guild_id.is_some() {
                 match configure::generate_and_apply_guild_command_set(&ctx, &msg).await {
                     Ok(x) => x,
                     Err(err) => {
-                        let event_id = nanoid!(6);
-                        error!("Error Event [{}]\n{:#?}", event_id, err);
-                        format!(
-                        "Sorry, something went wrong and this incident has been logged.\nIncident ID: `{}`",
-                        event_id
-                    )
+                        let source = IncidentSource {
+                            guild_id: msg.guild_id,
+                            command: ".configure".to_string(),
+                            options: Vec::default(),
+                            user_id: msg.author.id,
+                            channel_id: msg.channel_id,
+                        };
+                        let incident_id = crate::utils::incident::record(&ctx, source, &err).await;
+                        crate::utils::incident::reply_text(&incident_id)
                     }
                 }
             } else {
                 return;
             }
+        } else if let Some(command) = prefix::parse(&msg.content) {
+            if msg.author.bot {
+                return;
+            }
+            let in_flight = shutdown::get(&ctx).await.track();
+            if in_flight.is_none() {
+                shutdown::RESTARTING_RESPONSE.to_string()
+            } else if let Some(reason) = readiness::status(&ctx).await.unavailable_response() {
+                reason.to_string()
+            } else {
+                let name = command.name();
+                let started = Instant::now();
+                let handler_result = prefix::run(&ctx, &msg, command).await;
+                crate::utils::metrics::observe_command(
+                    &ctx,
+                    name,
+                    handler_result.is_ok(),
+                    started.elapsed(),
+                )
+                .await;
+                match handler_result {
+                    Ok(Some(x)) => x,
+                    Ok(None) => return,
+                    Err(err) => {
+                        let source = IncidentSource {
+                            guild_id: msg.guild_id,
+                            command: name.to_string(),
+                            options: vec![msg.content.clone()],
+                            user_id: msg.author.id,
+                            channel_id: msg.channel_id,
+                        };
+                        let incident_id = crate::utils::incident::record(&ctx, source, &err).await;
+                        crate::utils::incident::reply_text(&incident_id)
+                    }
+                }
+            }
         } else {
             return;
         };
@@ -60,7 +109,31 @@ impl EventHandler for Handler {
         }
     }
 
+    async fn message_delete(
+        &self,
+        ctx: Context,
+        _channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        guild_id: Option<GuildId>,
+    ) {
+        if let Some(guild_id) = guild_id {
+            queue_board::handle_deleted_message(&ctx, guild_id, deleted_message_id).await;
+        }
+    }
+
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let _in_flight = match shutdown::get(&ctx).await.track() {
+            Some(in_flight) => in_flight,
+            None => {
+                reply_unavailable(&ctx, &interaction, shutdown::RESTARTING_RESPONSE).await;
+                return;
+            }
+        };
+        if let Some(reason) = readiness::status(&ctx).await.unavailable_response() {
+            reply_unavailable(&ctx, &interaction, reason).await;
+            return;
+        }
+
         if let Interaction::Command(command) = interaction {
             info!("Interaction:\n{:?}", command);
             let _working = command.channel_id.start_typing(&ctx.http);
@@ -71,9 +144,11 @@ impl EventHandler for Handler {
             let builder = CreateInteractionResponse::Message(data);
             if let Err(why) = command.create_response(&ctx.http, builder).await {
                 error!("Cannot respond to slash command: {}", why);
+                crate::utils::metrics::discord_error(&ctx, &why).await;
                 return;
             }
 
+            let started = Instant::now();
             let handler_result: anyhow::Result<String> =
                 match command.data.name.to_lowercase().as_str() {
                     "ping" => Ok("Pong!".to_string()),
@@ -82,47 +157,162 @@ impl EventHandler for Handler {
                     "setpugchannel" => pug_channel::set(&ctx, &command).await,
                     "addmod" => game_mode::create(&ctx, &command).await,
                     "delmod" => game_mode::delete(&ctx, &command).await,
+                    "editmod" => game_mode::edit(&ctx, &command).await,
                     "join" => queue::join(&ctx, &command).await,
                     "leave" => queue::leave(&ctx, &command).await,
                     "addplayer" => player::add_to_pug(&ctx, &command).await,
                     "delplayer" => player::remove_from_pug(&ctx, &command).await,
                     "list" => queue::list(&ctx, &command).await,
+                    "notify" => notify::subscribe(&ctx, &command).await,
+                    "map" => map_pool::manage(&ctx, &command).await,
+                    "party" => party::manage(&ctx, &command).await,
                     "captain" => picking_session::captain(&ctx, &command).await,
                     "autocaptain" => picking_session::auto_captain(&ctx, &command).await,
                     "pick" => picking_session::pick(&ctx, &command).await,
                     "teams" => picking_session::teams(&ctx, &command).await,
                     "reset" => picking_session::reset(&ctx, &command).await,
                     "last" => meta::pug_history(&ctx, &command).await,
+                    "report" => match_result::report(&ctx, &command).await,
+                    "setresult" => match_result::set_result(&ctx, &command).await,
+                    "stats" => stats::player_stats(&ctx, &command).await,
+                    "leaderboard" => leaderboard::show(&ctx, &command).await,
+                    "season" => season::manage(&ctx, &command).await,
+                    "export" => export::match_history(&ctx, &command).await,
+                    "incident" => incident::show(&ctx, &command).await,
                     _ => Ok("Not usable. Sorry :(".to_string()),
                 };
+            crate::utils::metrics::observe_command(
+                &ctx,
+                &command.data.name,
+                handler_result.is_ok(),
+                started.elapsed(),
+            )
+            .await;
 
-            let actual_response = handler_result.map_or_else(
-                |err| {
-                    let event_id = nanoid!(6);
-                    error!("Error Event [{}]\n{:#?}", event_id, err);
-                    format!(
-                        "Sorry, something went wrong and this incident has been logged.\nIncident ID: `{}`",
-                        event_id
-                    )
-                },
-                |response| response,
-            );
-
-            if let Err(why) = command
-                .edit_response(
-                    &ctx.http,
-                    EditInteractionResponse::new().content(actual_response),
-                )
-                .await
-            {
+            let edit = match handler_result {
+                Ok(response) => EditInteractionResponse::new().content(response),
+                Err(err) => {
+                    let source = IncidentSource {
+                        guild_id: command.guild_id,
+                        command: command.data.name.clone(),
+                        options: command
+                            .data
+                            .options
+                            .iter()
+                            .map(|option| format!("{}: {:?}", option.name, option.value))
+                            .collect(),
+                        user_id: command.user.id,
+                        channel_id: command.channel_id,
+                    };
+                    let incident_id = crate::utils::incident::record(&ctx, source, &err).await;
+                    EditInteractionResponse::new()
+                        .content(crate::utils::incident::reply_text(&incident_id))
+                        .components(vec![crate::utils::incident::note_button(&incident_id)])
+                }
+            };
+
+            if let Err(why) = command.edit_response(&ctx.http, edit).await {
                 error!("Cannot update initial interaction response: {}", why);
+                crate::utils::metrics::discord_error(&ctx, &why).await;
             }
             _working.stop();
+        } else if let Interaction::Component(component) = interaction {
+            info!("Component interaction:\n{:?}", component);
+            let custom_id = component.data.custom_id.as_str();
+            let handler_result: anyhow::Result<()> =
+                if custom_id.starts_with(crate::utils::leaderboard::BUTTON_ID_PREFIX) {
+                    leaderboard::change_page(&ctx, &component).await
+                } else if custom_id.starts_with(crate::utils::incident::NOTE_ID_PREFIX) {
+                    incident::open_note_form(&ctx, &component).await
+                } else if custom_id.starts_with(crate::utils::queue_board::BUTTON_ID_PREFIX) {
+                    queue::board_button(&ctx, &component).await
+                } else if custom_id.starts_with(crate::utils::result_report::BUTTON_ID_PREFIX) {
+                    match_result::report_button(&ctx, &component).await
+                } else if custom_id.starts_with(crate::utils::map_vote::BUTTON_ID_PREFIX) {
+                    map_pool::vote_button(&ctx, &component).await
+                } else {
+                    Ok(())
+                };
+
+            if let Err(err) = handler_result {
+                let source = IncidentSource {
+                    guild_id: component.guild_id,
+                    command: component.data.custom_id.clone(),
+                    options: Vec::default(),
+                    user_id: component.user.id,
+                    channel_id: component.channel_id,
+                };
+                let incident_id = crate::utils::incident::record(&ctx, source, &err).await;
+                let data = CreateInteractionResponseMessage::new()
+                    .content(crate::utils::incident::reply_text(&incident_id))
+                    .components(vec![crate::utils::incident::note_button(&incident_id)])
+                    .ephemeral(true);
+                if component
+                    .create_response(&ctx.http, CreateInteractionResponse::Message(data))
+                    .await
+                    .is_err()
+                {
+                    // the handler may have acknowledged the interaction before failing
+                    let edit = EditInteractionResponse::new()
+                        .content(crate::utils::incident::reply_text(&incident_id))
+                        .components(vec![crate::utils::incident::note_button(&incident_id)]);
+                    if let Err(why) = component.edit_response(&ctx.http, edit).await {
+                        error!("Cannot respond to component interaction: {}", why);
+                    }
+                }
+            }
+        } else if let Interaction::Autocomplete(autocomplete) = interaction {
+            // a failure only leaves the user without suggestions, so it is not worth an incident
+            if let Err(err) = autocomplete::suggest(&ctx, &autocomplete).await {
+                error!(
+                    "Cannot suggest choices for /{}: {:?}",
+                    autocomplete.data.name, err
+                );
+            }
+        } else if let Interaction::Modal(modal) = interaction {
+            info!("Modal submission:\n{:?}", modal);
+            let custom_id = modal.data.custom_id.as_str();
+            let handler_result: anyhow::Result<()> =
+                if custom_id.starts_with(crate::utils::incident::NOTE_ID_PREFIX) {
+                    incident::save_note(&ctx, &modal).await
+                } else {
+                    Ok(())
+                };
+
+            if let Err(err) = handler_result {
+                let source = IncidentSource {
+                    guild_id: modal.guild_id,
+                    command: modal.data.custom_id.clone(),
+                    options: Vec::default(),
+                    user_id: modal.user.id,
+                    channel_id: modal.channel_id,
+                };
+                let incident_id = crate::utils::incident::record(&ctx, source, &err).await;
+                let data = CreateInteractionResponseMessage::new()
+                    .content(crate::utils::incident::reply_text(&incident_id))
+                    .ephemeral(true);
+                if let Err(why) = modal
+                    .create_response(&ctx.http, CreateInteractionResponse::Message(data))
+                    .await
+                {
+                    error!("Cannot respond to modal submission: {}", why);
+                }
+            }
         }
     }
 
     async fn ready(&self, ctx: Context, ready: Ready) {
         info!("Connected as {}", ready.user.name);
+        ops::report(
+            &ctx,
+            OpsEvent::Startup,
+            format!(
+                "Connected as {} to {} guilds",
+                ready.user.name,
+                ready.guilds.len()
+            ),
+        )
+        .await;
         ctx.set_activity(Some(ActivityData::playing("Bugs? Message sudomann#9568")));
     }
 
@@ -145,29 +335,56 @@ impl EventHandler for Handler {
             let ctx1 = Arc::clone(&ctx);
             let ctx2 = Arc::clone(&ctx);
             let ctx3 = Arc::clone(&ctx);
+            let ctx4 = Arc::clone(&ctx);
+            let ctx5 = Arc::clone(&ctx);
             let two_minutes = 120;
-            let five_minutes = 300;
+            // Job loops stop once shutting down begins, and a run already underway
+            // counts as work in flight, so it gets to finish
+            let shutdown1 = shutdown::get(&ctx).await;
+            let shutdown2 = shutdown1.clone();
+            let shutdown3 = shutdown1.clone();
+            let shutdown4 = shutdown1.clone();
+            let shutdown5 = shutdown1.clone();
 
             tokio::spawn(async move {
-                loop {
+                while let Some(in_flight) = shutdown1.track() {
                     // We clone Context again here, because Arc is owned, so it moves to the
                     // new function.
                     log_system_load(Arc::clone(&ctx1)).await;
+                    drop(in_flight);
                     tokio::time::sleep(Duration::from_secs(two_minutes)).await;
                 }
             });
 
             tokio::spawn(async move {
-                loop {
+                while let Some(in_flight) = shutdown2.track() {
                     clear_out_stale_joins(Arc::clone(&ctx2)).await;
+                    drop(in_flight);
                     tokio::time::sleep(Duration::from_secs(two_minutes)).await;
                 }
             });
 
             tokio::spawn(async move {
-                loop {
+                while let Some(in_flight) = shutdown3.track() {
                     remove_stale_team_voice_channels(Arc::clone(&ctx3)).await;
-                    tokio::time::sleep(Duration::from_secs(10 /*five_minutes*/)).await;
+                    drop(in_flight);
+                    tokio::time::sleep(Duration::from_secs(10 /* 300, five minutes */)).await;
+                }
+            });
+
+            tokio::spawn(async move {
+                while let Some(in_flight) = shutdown4.track() {
+                    escalate_unconfirmed_results(Arc::clone(&ctx4)).await;
+                    drop(in_flight);
+                    tokio::time::sleep(Duration::from_secs(two_minutes)).await;
+                }
+            });
+
+            tokio::spawn(async move {
+                while let Some(in_flight) = shutdown5.track() {
+                    end_overdue_map_votes(Arc::clone(&ctx5)).await;
+                    drop(in_flight);
+                    tokio::time::sleep(Duration::from_secs(two_minutes)).await;
                 }
             });
 
@@ -182,10 +399,6 @@ impl EventHandler for Handler {
 
     #[instrument(skip(self, ctx))]
     async fn guild_create(&self, ctx: Context, guild: Guild, is_new: Option<bool>) {
-        // TODO: test addition of new new guild while inspect_guild_commands() is looping/waiting
-        // for Client to exist. Hopefully, out of the box, this handler never fires until cache_ready completes
-        // and a Client is guaranteed to be in storage
-
         if matches!(is_new, None | Some(false)) {
             return;
         }
@@ -196,23 +409,67 @@ impl EventHandler for Handler {
             guild.name
         );
 
-        // do onboarding for guilds added after the bot was launched
-        let db_client = {
-            let data = ctx.data.read().await;
-
-            // !FIXME: must block at laucnh while waiting for DB. Bot begins listening otherwise,
-            // and will fail on commands that need the db client
-            data.get::<DbClientRef>()
-                .expect("Expected MongoDB's `Client` to be available for use")
-                .clone()
-        };
+        ops::report(
+            &ctx,
+            OpsEvent::GuildJoin,
+            format!("{} ({})", guild.name, guild.id),
+        )
+        .await;
 
         info!("Launching onboarding task (perform an inspection) for the new guild");
 
-        tokio::spawn(crate::utils::onboarding::inspect_and_maybe_update_db(
-            Arc::new(ctx),
-            guild.id,
-            db_client.clone(),
-        ));
+        // do onboarding for guilds added after the bot was launched, once the database is ready
+        tokio::spawn(async move {
+            let db_client = readiness::client(&ctx).await;
+            if let Err(err) = crate::utils::onboarding::inspect_and_maybe_update_db(
+                Arc::new(ctx),
+                guild.id,
+                db_client,
+            )
+            .await
+            {
+                error!("Failed to onboard new guild {}: {:?}", guild.id, err);
+            }
+        });
+    }
+
+    #[instrument(skip(self, ctx, full))]
+    async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, full: Option<Guild>) {
+        // an unavailable guild is only having an outage, the bot was not removed from it
+        if incomplete.unavailable {
+            return;
+        }
+        let name = match full {
+            Some(guild) => guild.name,
+            None => "<guild_name_unavailable>".to_string(),
+        };
+        info!("Removed from guild (GuildId: {}) - {}", incomplete.id, name);
+        ops::report(
+            &ctx,
+            OpsEvent::GuildLeave,
+            format!("{} ({})", name, incomplete.id),
+        )
+        .await;
+    }
+}
+
+/// Tell the user their interaction was turned away, e.g. because the bot is shutting down.
+async fn reply_unavailable(ctx: &Context, interaction: &Interaction, reason: &str) {
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(reason)
+            .ephemeral(true),
+    );
+    let result = match interaction {
+        Interaction::Command(command) => command.create_response(&ctx.http, response).await,
+        Interaction::Component(component) => component.create_response(&ctx.http, response).await,
+        Interaction::Modal(modal) => modal.create_response(&ctx.http, response).await,
+        _ => Ok(()),
+    };
+    if let Err(why) = result {
+        error!(
+            "Cannot respond to interaction which was turned away: {}",
+            why
+        );
     }
 }
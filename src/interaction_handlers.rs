@@ -1,12 +1,23 @@
+pub mod autocomplete;
 pub mod configure;
+pub mod export;
 pub mod gambling;
 pub mod game_mode;
+pub mod incident;
+pub mod leaderboard;
+pub mod map_pool;
+pub mod match_result;
 pub mod meta;
+pub mod notify;
+pub mod party;
 pub mod picking_session;
 pub mod player;
+pub mod prefix;
 pub mod promote;
 pub mod pug_channel;
 pub mod queue;
+pub mod season;
+pub mod stats;
 
 /// Simple enum to represent whether all game modes, or
 /// a single, specific game mode should be operated upon.
@@ -16,3 +27,11 @@ pub enum IntendedGameMode {
     /// All available game modes
     All,
 }
+
+/// How much detail a handler's reply should have.
+pub enum ReplyStyle {
+    /// Replies to slash commands
+    Full,
+    /// One short line, for replies to text commands in a busy pug channel
+    Compact,
+}
@@ -0,0 +1,84 @@
+use anyhow::Context as AnyhowContext;
+use serenity::builder::{
+    AutocompleteChoice, CreateAutocompleteResponse, CreateInteractionResponse,
+};
+use serenity::client::Context;
+use serenity::model::application::CommandInteraction;
+
+use crate::db;
+use crate::utils::{fuzzy, transform};
+use crate::DbClientRef;
+
+/// Respond to an autocomplete interaction with suggestions for the option being typed in.
+///
+/// Options of any command named `game_mode` suggest game modes (matching their labels or
+/// aliases), and the `player` option of /pick suggests the players left to pick in the pug
+/// of the thread it is used in.
+pub async fn suggest(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<()> {
+    let focused = match interaction.data.autocomplete() {
+        Some(focused) => focused,
+        None => return Ok(()),
+    };
+    let guild_id = match interaction.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(()),
+    };
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let choices = match (interaction.data.name.as_str(), focused.name) {
+        ("pick", "player") => {
+            let picking_session = db::read::get_current_picking_session(db)
+                .await
+                .context("Tried to fetch current picking session (if any)")?;
+            match picking_session {
+                Some(pug) if pug.thread_channel_id as u64 == interaction.channel_id.get() => {
+                    let users = transform::players_to_users(ctx, pug.pickable_players())
+                        .await
+                        .context("Failed to convert pick list user ids to `User`s")?;
+                    fuzzy::rank(focused.value, users, |user| vec![user.name.as_str()])
+                        .into_iter()
+                        .map(|user| AutocompleteChoice::new(user.name, user.id.get().to_string()))
+                        .collect()
+                }
+                _ => Vec::default(),
+            }
+        }
+        (command_name, "game_mode") => {
+            let mut game_modes = db::read::get_game_modes(db)
+                .await
+                .context("Failed to read game modes")?;
+            // disabled game modes cannot be joined, but can still be managed and looked up
+            if ["join", "addplayer", "notify"].contains(&command_name) {
+                game_modes.retain(|game_mode| !game_mode.disabled);
+            }
+            fuzzy::rank(focused.value, game_modes, |game_mode| {
+                std::iter::once(&game_mode.label)
+                    .chain(game_mode.aliases.iter())
+                    .map(String::as_str)
+                    .collect()
+            })
+            .into_iter()
+            .map(|game_mode| AutocompleteChoice::new(game_mode.display_name(), game_mode.label))
+            .collect()
+        }
+        _ => Vec::default(),
+    };
+
+    interaction
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Autocomplete(
+                CreateAutocompleteResponse::new().set_choices(choices),
+            ),
+        )
+        .await
+        .context("Failed to send autocomplete suggestions")?;
+    Ok(())
+}
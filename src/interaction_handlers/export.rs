@@ -0,0 +1,127 @@
+use anyhow::Context as AnyhowContext;
+use chrono::NaiveDate;
+use serenity::builder::{CreateAttachment, EditInteractionResponse};
+use serenity::client::Context;
+use serenity::model::application::CommandInteraction;
+
+use super::IntendedGameMode;
+use crate::db;
+use crate::utils::export::{export_match_history, ExportFormat};
+use crate::DbClientRef;
+
+/// Export match history between two dates, uploaded as a file attachment.
+///
+/// Expects fields `from`, `to` and `format`, with optional `game_mode` (defaults to all).
+pub async fn match_history(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let target_game_modes = match interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("game_mode"))
+        .and_then(|option| option.value.as_str())
+    {
+        Some(label) => IntendedGameMode::Single(label.to_string()),
+        None => IntendedGameMode::All,
+    };
+
+    let format_value = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("format"))
+        .context("The `format` option is missing")?
+        .value
+        .as_str()
+        .context("Somehow, the value of the `format` option is not a string")?;
+    let format = ExportFormat::from_value(format_value)
+        .context(format!("Unrecognized export format: {}", format_value))?;
+
+    let mut dates = Vec::default();
+    for option_name in ["from", "to"] {
+        let value = interaction
+            .data
+            .options
+            .iter()
+            .find(|option| option.name.eq(option_name))
+            .context(format!("The `{}` option is missing", option_name))?
+            .value
+            .as_str()
+            .context(format!(
+                "Somehow, the value of the `{}` option is not a string",
+                option_name
+            ))?;
+        match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            Ok(date) => dates.push(date),
+            Err(_) => {
+                return Ok(format!(
+                    "`{}` must be a date formatted as YYYY-MM-DD",
+                    option_name
+                ))
+            }
+        }
+    }
+    let (from, to) = (dates[0], dates[1]);
+    if from > to {
+        return Ok("`from` cannot be later than `to`".to_string());
+    }
+
+    // the label may have been given as an alias
+    let game_mode_label = match &target_game_modes {
+        IntendedGameMode::Single(label) => match db::read::find_game_mode(db.clone(), label).await?
+        {
+            Some(game_mode) => Some(game_mode.label),
+            None => return Ok("No game mode found with this name".to_string()),
+        },
+        IntendedGameMode::All => None,
+    };
+
+    let mut file = Vec::default();
+    let count = export_match_history(
+        db,
+        ctx,
+        game_mode_label.as_deref(),
+        Some(from),
+        Some(to),
+        format,
+        &mut file,
+    )
+    .await
+    .context("Failed to export match history")?;
+
+    if count == 0 {
+        return Ok("No pugs were played in this period".to_string());
+    }
+
+    let file_name = format!(
+        "pugs_{}_{}_{}.{}",
+        game_mode_label.as_deref().unwrap_or("all"),
+        from,
+        to,
+        format.extension()
+    );
+    interaction
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new().new_attachment(CreateAttachment::bytes(file, file_name)),
+        )
+        .await
+        .context("Failed to upload match history export")?;
+
+    Ok(format!(
+        "Exported {} pugs played from {} to {}",
+        count, from, to
+    ))
+}
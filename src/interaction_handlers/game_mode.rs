@@ -4,7 +4,7 @@ use serenity::model::application::CommandInteraction;
 
 use crate::db;
 use crate::db::model::GameMode;
-use crate::utils::application_commands::refresh_commands_with_game_mode_option;
+use crate::utils::{leaderboard, queue_board, queue_lock};
 use crate::DbClientRef;
 
 /// Register a game mode
@@ -42,37 +42,163 @@ pub async fn create(ctx: &Context, interaction: &CommandInteraction) -> anyhow::
         .context("Failed to extract the value of the `player_count` option as a `i64`")?;
 
     // read existing game modes from db
-    let mut game_modes = db::read::get_game_modes(db.clone()).await?;
+    let game_modes = db::read::get_game_modes(db.clone()).await?;
 
     // check for conflict/existing
-    if game_modes.iter().any(|g| g.label.eq(label)) {
+    if GameMode::resolve(&game_modes, label).is_some() {
         return Ok("A game mode with this label already exists".to_string());
     }
 
     // save new game mode
     db::write::write_new_game_mode(db.clone(), label.to_string(), *player_count as u64).await?;
+    queue_board::refresh(ctx, guild_id).await;
 
-    // Must add the desired game mode to the list since it the list only contains
-    // game modes that existed before
-    game_modes.push(GameMode {
-        label: label.to_string(),
-        player_count: *player_count as i64,
-    });
+    Ok(format!("Added new game mode {} successfully", label))
+}
+
+/// Edit a registered game mode. Options which were left out are not changed.
+///
+/// Renaming a game mode updates its queue, history, alerts and season standings as well.
+/// The player count cannot be lowered to (or below) the number of players in its queue,
+/// since the queue would then be full without a pug having started.
+pub async fn edit(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let option = |name: &str| {
+        interaction
+            .data
+            .options
+            .iter()
+            .find(|option| option.name.eq(name))
+            .map(|option| &option.value)
+    };
+    // `-` clears text options
+    let text_option = |name: &str| {
+        option(name)
+            .and_then(|value| value.as_str())
+            .map(str::trim)
+            .map(|text| (text != "-" && !text.is_empty()).then(|| text.to_string()))
+    };
+
+    let game_mode_label = option("game_mode")
+        .context("The `game_mode` option is missing")?
+        .as_str()
+        .context("Somehow, the value of the `game_mode` option is not a string")?
+        .to_string();
+
+    // held until the edit is saved, so no player can join while the player count is checked
+    let _queue_lock = queue_lock::lock(ctx, guild_id).await;
+
+    let game_modes = db::read::get_game_modes(db.clone()).await?;
+    let original = match GameMode::resolve(&game_modes, &game_mode_label) {
+        Some(game_mode) => game_mode.clone(),
+        None => {
+            return Ok(format!(
+                "No game mode called **{}** was found",
+                game_mode_label
+            ))
+        }
+    };
+    // labels and aliases of the other game modes, which must not be reused
+    let names_taken = game_modes
+        .iter()
+        .filter(|g| g.label != original.label)
+        .flat_map(|g| std::iter::once(&g.label).chain(g.aliases.iter()))
+        .map(|name| name.to_lowercase())
+        .collect::<Vec<String>>();
+
+    let mut edited = original.clone();
 
-    // Finally, update commands which require an up-to-date game mode list
-    refresh_commands_with_game_mode_option(&ctx, guild_id, db, game_modes)
+    if let Some(label) = text_option("label") {
+        match label {
+            Some(label) if !names_taken.contains(&label.to_lowercase()) => edited.label = label,
+            Some(label) => return Ok(format!("Another game mode is already called **{}**", label)),
+            None => return Ok("A game mode cannot be left without a label".to_string()),
+        }
+    }
+
+    if let Some(player_count) = option("player_count").and_then(|value| value.as_i64()) {
+        if player_count % 2 != 0 {
+            return Ok("The player count must be even".to_string());
+        }
+        let queue = db::read::get_game_mode_queue(db.clone(), &original.label).await?;
+        if queue.len() as i64 >= player_count {
+            return Ok(format!(
+                "{} players are in the queue for **{}**, so it needs more than {} players",
+                queue.len(),
+                original.label,
+                queue.len()
+            ));
+        }
+        edited.player_count = player_count;
+    }
+
+    if let Some(description) = text_option("description") {
+        edited.description = description;
+    }
+
+    if let Some(aliases) = text_option("aliases") {
+        let mut new_aliases: Vec<String> = Vec::default();
+        for alias in aliases.iter().flat_map(|aliases| aliases.split(',')) {
+            let alias = alias.trim();
+            if alias.is_empty() || new_aliases.iter().any(|a| a.eq_ignore_ascii_case(alias)) {
+                continue;
+            }
+            if names_taken.contains(&alias.to_lowercase()) {
+                return Ok(format!("Another game mode is already called **{}**", alias));
+            }
+            new_aliases.push(alias.to_string());
+        }
+        edited.aliases = new_aliases;
+    }
+    edited
+        .aliases
+        .retain(|alias| !alias.eq_ignore_ascii_case(&edited.label));
+
+    if let Some(emoji) = text_option("emoji") {
+        edited.emoji = emoji;
+    }
+
+    if let Some(hours) = option("max_queue_hours").and_then(|value| value.as_i64()) {
+        edited.max_queue_hours = (hours > 0).then_some(hours);
+    }
+
+    if let Some(size) = option("max_party_size").and_then(|value| value.as_i64()) {
+        edited.max_party_size = (size > 0).then_some(size);
+    }
+
+    if let Some(disabled) = option("disabled").and_then(|value| value.as_bool()) {
+        edited.disabled = disabled;
+    }
+
+    if edited == original {
+        return Ok(format!("Nothing to change for **{}**", original.label));
+    }
+
+    db::write::edit_game_mode(&client, db, &original.label, &edited)
         .await
-        .context(
-            "Attempted to update relevant commands with an \
-            up to date list of game mode choices",
-        )?;
+        .context(format!(
+            "Failed to save the edits of **{}**",
+            original.label
+        ))?;
+    if edited.label != original.label {
+        // leaderboards are cached by game mode label
+        leaderboard::invalidate(ctx, guild_id).await;
+    }
+    queue_board::refresh(ctx, guild_id).await;
 
-    Ok(format!("Added new game mode {} successfully", label))
+    Ok(format!("Updated **{}**", edited.display_name()))
 }
 
 /// Delete a registered game mode.
-///
-/// This updates the set of commands which require an up-to-date list of game modes to show as choices.
 pub async fn delete(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
     let guild_id = interaction.guild_id.unwrap();
 
@@ -84,7 +210,7 @@ pub async fn delete(ctx: &Context, interaction: &CommandInteraction) -> anyhow::
     };
     let db = client.database(&guild_id.to_string());
 
-    let game_mode_label = &interaction
+    let game_mode_name = interaction
         .data
         .options
         .iter()
@@ -94,24 +220,24 @@ pub async fn delete(ctx: &Context, interaction: &CommandInteraction) -> anyhow::
         .as_str()
         .context("Somehow, the value of the `game_mode` option is not a string")?;
 
-    // read existing game modes from db
-    let mut game_modes = db::read::get_game_modes(db.clone()).await?;
-    // Remove matching command
-    let match_position = match game_modes.iter().position(|g| g.label.eq(game_mode_label)) {
-        Some(position) => position,
+    // held until the game mode is deleted, so no player can join its queue in the meantime
+    let _queue_lock = queue_lock::lock(ctx, guild_id).await;
+
+    // the game mode may have been given by an alias
+    let game_mode_label = match db::read::find_game_mode(db.clone(), game_mode_name).await? {
+        Some(game_mode) => game_mode.label,
         None => {
             return Ok(format!(
                 "No game mode called **{}** was found",
-                game_mode_label
+                game_mode_name
             ))
         }
     };
-    game_modes.remove(match_position);
 
     // if the queue for the game mode is not empty,
     // instruct caller to remove all queued players,
     // then try to delete again
-    let queue = db::read::get_game_mode_queue(db.clone(), &game_mode_label.to_string()).await?;
+    let queue = db::read::get_game_mode_queue(db.clone(), &game_mode_label).await?;
     if !queue.is_empty() {
         return Ok(format!(
             "The queue for **{}** is not empty. Remove any players who joined and try again.",
@@ -132,22 +258,15 @@ pub async fn delete(ctx: &Context, interaction: &CommandInteraction) -> anyhow::
     };
 
     // Remove game mode's record from db
-    let result = db::write::delete_game_mode(db.clone(), game_mode_label.to_string()).await?;
+    let result = db::write::delete_game_mode(db.clone(), game_mode_label.clone()).await?;
     match result.deleted_count {
         0 => anyhow::bail!("Unable to delete the {} game mode", game_mode_label),
-        1 => {
-            // Update game mode choices
-            refresh_commands_with_game_mode_option(&ctx, guild_id, db, game_modes)
-                .await
-                .context(
-                    "Attempted to update relevant commands with an \
-                    up to date list of game mode choices",
-                )?;
-        }
+        1 => {}
         _ => anyhow::bail!(
             "DB function to delete a game mode seems buggy - it deleted more than 1 record."
         ),
     }
+    queue_board::refresh(ctx, guild_id).await;
 
     Ok(format!("Deleted **{}** successfully", game_mode_label))
 }
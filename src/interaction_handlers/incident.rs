@@ -0,0 +1,151 @@
+use anyhow::Context as AnyhowContext;
+use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseMessage};
+use serenity::client::Context;
+use serenity::model::application::{
+    ActionRowComponent, CommandInteraction, ComponentInteraction, ModalInteraction,
+};
+use serenity::model::id::{ChannelId, UserId};
+use serenity::utils::MessageBuilder;
+
+use crate::db;
+use crate::utils::incident::{incident_id_from_custom_id, note_modal};
+use crate::{CrucialIdsRef, DbClientRef};
+
+/// Show the details of an incident. Only superusers can use this.
+///
+/// Expects field `id`.
+pub async fn show(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let is_superuser = {
+        let data = ctx.data.read().await;
+        data.get::<CrucialIdsRef>()
+            .expect("Expected crucial ids to be available for use")
+            .get_superusers()
+            .contains(&interaction.user.id)
+    };
+    if !is_superuser {
+        return Ok("Only superusers can view incidents".to_string());
+    }
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let incident_id = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("id"))
+        .context("The `id` option is missing")?
+        .value
+        .as_str()
+        .context("Somehow, the value of the `id` option is not a string")?
+        .trim_matches('`');
+
+    let incident = match db::read::find_incident(db, incident_id).await? {
+        Some(incident) => incident,
+        None => {
+            return Ok(format!(
+                "No incident found with ID `{}`. It may have expired",
+                incident_id
+            ))
+        }
+    };
+
+    let mut response = MessageBuilder::default();
+    response
+        .push_bold_line(format!("Incident {}", incident.incident_id))
+        .push_line(format!(
+            "When: {}",
+            incident.created.format("%Y-%m-%d %H:%M:%S UTC")
+        ))
+        .push(format!("Command: `{}` ", incident.command))
+        .push_line(incident.options.join(", "))
+        .push("User: ")
+        .mention(&UserId::from(incident.user_id as u64))
+        .push(" in ")
+        .mention(&ChannelId::from(incident.channel_id as u64))
+        .push_line("")
+        .push_line("Error:");
+    // causes are shortened so the details fit in a single message
+    for cause in incident.error_chain.iter() {
+        response.push_codeblock_safe(cause.chars().take(300).collect::<String>(), None);
+    }
+    if !incident.notes.is_empty() {
+        response.push_line("Notes:");
+        for note in incident.notes.iter() {
+            response
+                .push(format!("{}: ", note.added.format("%Y-%m-%d %H:%M")))
+                .push_line_safe(&note.note);
+        }
+    }
+
+    Ok(response.build())
+}
+
+/// Open the form for adding a note to an incident, from the button on an error reply.
+pub async fn open_note_form(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+) -> anyhow::Result<()> {
+    let incident_id = incident_id_from_custom_id(&interaction.data.custom_id)
+        .context("The custom id of an incident note button has no incident id")?;
+    interaction
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Modal(note_modal(incident_id)),
+        )
+        .await
+        .context("Failed to open incident note form")
+}
+
+/// Attach the note submitted through the incident note form.
+pub async fn save_note(ctx: &Context, interaction: &ModalInteraction) -> anyhow::Result<()> {
+    let guild_id = interaction
+        .guild_id
+        .context("Incident notes can only be added in a guild")?;
+    let incident_id = incident_id_from_custom_id(&interaction.data.custom_id)
+        .context("The custom id of an incident note form has no incident id")?;
+
+    let note = interaction
+        .data
+        .components
+        .iter()
+        .flat_map(|row| row.components.iter())
+        .find_map(|component| match component {
+            ActionRowComponent::InputText(input) => input.value.clone(),
+            _ => None,
+        })
+        .context("The incident note form was submitted without a note")?;
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let content =
+        if db::write::add_incident_note(db, incident_id, interaction.user.id.get(), note).await? {
+            "Thanks, your note was added to the incident"
+        } else {
+            "Notes can only be added to your own incidents, and only until they expire"
+        };
+    interaction
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await
+        .context("Failed to respond to incident note submission")
+}
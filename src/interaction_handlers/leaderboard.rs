@@ -0,0 +1,226 @@
+use anyhow::Context as AnyhowContext;
+use chrono::NaiveDate;
+use serenity::builder::{
+    CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse,
+};
+use serenity::client::Context;
+use serenity::model::application::{CommandInteraction, ComponentInteraction};
+use serenity::model::id::{GuildId, UserId};
+use serenity::utils::MessageBuilder;
+
+use crate::db;
+use crate::utils::leaderboard::{
+    get_ranked_entries, page_buttons, LeaderboardMetric, LeaderboardQuery, PAGE_SIZE,
+    WIN_RATE_MIN_GAMES,
+};
+use crate::DbClientRef;
+
+/// Show a page of the leaderboard for a game mode, along with buttons to navigate pages.
+///
+/// Expects field `game_mode`, with optional `metric`, `page`, `season`, `from` and `to`.
+pub async fn show(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let game_mode_label = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("game_mode"))
+        .context("The `game_mode` option is missing")?
+        .value
+        .as_str()
+        .context("Somehow, the value of the `game_mode` option is not a string")?
+        .to_string();
+
+    let metric = match interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("metric"))
+        .and_then(|option| option.value.as_str())
+    {
+        Some(value) => LeaderboardMetric::from_value(value)
+            .context(format!("Unrecognized leaderboard metric: {}", value))?,
+        None => LeaderboardMetric::GamesPlayed,
+    };
+
+    // pages are 1-based for users
+    let page = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("page"))
+        .and_then(|option| option.value.as_i64())
+        .map_or(0, |page| (page.max(1) - 1) as usize);
+
+    let season = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("season"))
+        .and_then(|option| option.value.as_str())
+        .map(|season| season.to_string());
+
+    let mut dates = Vec::default();
+    for option_name in ["from", "to"] {
+        let date = match interaction
+            .data
+            .options
+            .iter()
+            .find(|option| option.name.eq(option_name))
+            .and_then(|option| option.value.as_str())
+        {
+            Some(value) => match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+                Ok(date) => Some(date),
+                Err(_) => {
+                    return Ok(format!(
+                        "`{}` must be a date formatted as YYYY-MM-DD",
+                        option_name
+                    ))
+                }
+            },
+            None => None,
+        };
+        dates.push(date);
+    }
+
+    // the label may have been given as an alias
+    let game_mode_label = match db::read::find_game_mode(db.clone(), &game_mode_label).await? {
+        Some(game_mode) => game_mode.label,
+        None => return Ok("No game mode found with this name".to_string()),
+    };
+
+    let mut query = LeaderboardQuery {
+        game_mode_label,
+        metric,
+        page,
+        season,
+        from: dates[0],
+        to: dates[1],
+    };
+
+    let (response, page_count) = render(ctx, db, guild_id, &mut query).await?;
+
+    let buttons = page_buttons(&query, page_count);
+    if page_count > 1 && !buttons.is_empty() {
+        interaction
+            .edit_response(
+                &ctx.http,
+                EditInteractionResponse::new().components(buttons),
+            )
+            .await
+            .context("Failed to attach page buttons to leaderboard")?;
+    }
+
+    Ok(response)
+}
+
+/// Handle a press of one of the page buttons attached to a leaderboard.
+pub async fn change_page(ctx: &Context, interaction: &ComponentInteraction) -> anyhow::Result<()> {
+    let guild_id = interaction
+        .guild_id
+        .context("Leaderboard buttons are only used in guilds")?;
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let mut query =
+        LeaderboardQuery::from_custom_id(&interaction.data.custom_id).context(format!(
+            "Malformed leaderboard button custom id: {}",
+            interaction.data.custom_id
+        ))?;
+
+    let (response, page_count) = render(ctx, db, guild_id, &mut query).await?;
+
+    let message = CreateInteractionResponseMessage::new()
+        .content(response)
+        .components(page_buttons(&query, page_count));
+    interaction
+        .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(message))
+        .await
+        .context("Failed to update leaderboard message")?;
+
+    Ok(())
+}
+
+/// Render a page of a leaderboard, returning it along with the total number of pages.
+///
+/// The page of the query is clamped to the last page, if it is beyond it.
+async fn render(
+    ctx: &Context,
+    db: mongodb::Database,
+    guild_id: GuildId,
+    query: &mut LeaderboardQuery,
+) -> anyhow::Result<(String, usize)> {
+    let entries = get_ranked_entries(ctx, db, guild_id, query)
+        .await
+        .context("Failed to aggregate leaderboard entries")?;
+
+    let page_count = entries.len().div_ceil(PAGE_SIZE).max(1);
+    query.page = query.page.min(page_count - 1);
+    let page = query.page;
+
+    let date_range = match (query.from, query.to) {
+        (Some(from), Some(to)) => format!(" ({} to {})", from, to),
+        (Some(from), None) => format!(" (since {})", from),
+        (None, Some(to)) => format!(" (until {})", to),
+        (None, None) => String::new(),
+    };
+    let season = match &query.season {
+        Some(season) => format!(" [{}]", season),
+        None => String::new(),
+    };
+
+    let mut response = MessageBuilder::default();
+    response
+        .push_bold(&query.game_mode_label)
+        .push(format!(" leaderboard - {}", query.metric.title()))
+        .push(season)
+        .push_line(date_range);
+
+    if entries.is_empty() {
+        if query.metric == LeaderboardMetric::WinRate {
+            response.push_line(format!(
+                "Nobody has played at least {} games with a recorded result yet",
+                WIN_RATE_MIN_GAMES
+            ));
+        } else {
+            response.push_line("No completed pugs yet");
+        }
+        return Ok((response.build(), page_count));
+    }
+
+    for (position, entry) in entries
+        .iter()
+        .enumerate()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+    {
+        let name = match UserId::from(entry.user_id as u64).to_user(ctx).await {
+            Ok(user) => user.name,
+            Err(_) => format!("Unknown user ({})", entry.user_id),
+        };
+        response.push_line(format!(
+            "`{:>3}.` {} - {}",
+            position + 1,
+            name,
+            query.metric.format_value(entry)
+        ));
+    }
+    response.push_italic(format!("Page {}/{}", page + 1, page_count));
+
+    Ok((response.build(), page_count))
+}
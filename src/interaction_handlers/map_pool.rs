@@ -0,0 +1,246 @@
+use anyhow::Context as AnyhowContext;
+use chrono::Utc;
+use itertools::Itertools;
+use mongodb::Database;
+use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseMessage};
+use serenity::client::Context;
+use serenity::model::application::{
+    CommandDataOption, CommandDataOptionValue, CommandInteraction, ComponentInteraction,
+};
+use serenity::utils::MessageBuilder;
+
+use crate::db;
+use crate::db::model::GameMode;
+use crate::utils::map_vote;
+use crate::DbClientRef;
+
+/// Command handler for /map, which dispatches to its `add`, `remove` and `list` subcommands.
+///
+/// Map names are compared ignoring case, so a map cannot be added twice.
+pub async fn manage(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let subcommand = interaction
+        .data
+        .options
+        .first()
+        .context("The /map command was used without a subcommand")?;
+
+    let sub_options = match &subcommand.value {
+        CommandDataOptionValue::SubCommand(sub_options) => sub_options,
+        _ => anyhow::bail!("The first option of /map is expected to be a subcommand"),
+    };
+
+    if subcommand.name == "list" {
+        return list(db, string_option(sub_options, "game_mode")).await;
+    }
+
+    let game_mode_label =
+        string_option(sub_options, "game_mode").context("The `game_mode` option is missing")?;
+    let game_mode = match db::read::find_game_mode(db.clone(), game_mode_label)
+        .await
+        .context("Failed to read the game mode of a map")?
+    {
+        Some(game_mode) => game_mode,
+        None => {
+            return Ok(format!(
+                "No game mode called **{}** was found",
+                game_mode_label
+            ))
+        }
+    };
+    let map = string_option(sub_options, "map")
+        .context("The `map` option is missing")?
+        .trim();
+    if map.is_empty() {
+        return Ok("A map needs a name".to_string());
+    }
+    let existing = game_mode
+        .maps
+        .iter()
+        .find(|existing| existing.eq_ignore_ascii_case(map));
+
+    match subcommand.name.as_str() {
+        "add" => {
+            if let Some(existing) = existing {
+                return Ok(format!(
+                    "**{}** is already in the map pool of **{}**",
+                    existing, game_mode.label
+                ));
+            }
+            db::write::add_map(db, &game_mode.label, map)
+                .await
+                .context("Failed to add a map to a map pool")?;
+            Ok(format!(
+                "Added **{}** to the map pool of **{}** ({} maps)",
+                map,
+                game_mode.label,
+                game_mode.maps.len() + 1
+            ))
+        }
+        "remove" => {
+            let existing = match existing {
+                Some(existing) => existing,
+                None => {
+                    return Ok(format!(
+                        "**{}** is not in the map pool of **{}**",
+                        map, game_mode.label
+                    ))
+                }
+            };
+            db::write::remove_map(db, &game_mode.label, existing)
+                .await
+                .context("Failed to remove a map from a map pool")?;
+            Ok(format!(
+                "Removed **{}** from the map pool of **{}**",
+                existing, game_mode.label
+            ))
+        }
+        other => anyhow::bail!("Unrecognized /map subcommand: {}", other),
+    }
+}
+
+fn string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|option| option.name.eq(name))
+        .and_then(|option| option.value.as_str())
+}
+
+async fn list(db: Database, game_mode_label: Option<&str>) -> anyhow::Result<String> {
+    let game_modes = match game_mode_label {
+        Some(label) => match db::read::find_game_mode(db, label)
+            .await
+            .context("Failed to read a game mode")?
+        {
+            Some(game_mode) => vec![game_mode],
+            None => return Ok(format!("No game mode called **{}** was found", label)),
+        },
+        None => db::read::get_game_modes(db)
+            .await
+            .context("Failed to read game modes")?
+            .into_iter()
+            .filter(|game_mode| !game_mode.maps.is_empty())
+            .sorted_by(|a, b| a.label.cmp(&b.label))
+            .collect::<Vec<GameMode>>(),
+    };
+
+    if game_modes.is_empty() {
+        return Ok("No game mode has a map pool yet. Add maps with `/map add`".to_string());
+    }
+    let mut response = MessageBuilder::default();
+    for game_mode in game_modes {
+        response.push_bold(game_mode.display_name());
+        if game_mode.maps.is_empty() {
+            response.push_line(" has no maps, so no map is voted on");
+        } else {
+            response.push_line(format!(": {}", game_mode.maps.join(", ")));
+        }
+    }
+    Ok(response.build())
+}
+
+/// Handle a vote for a map, from the buttons of a pug's map vote. Players can change their
+/// vote until the vote ends.
+pub async fn vote_button(ctx: &Context, interaction: &ComponentInteraction) -> anyhow::Result<()> {
+    let guild_id = interaction
+        .guild_id
+        .context("Map vote buttons are only used in guilds")?;
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let candidate_index =
+        map_vote::from_custom_id(&interaction.data.custom_id).context(format!(
+            "Malformed map vote button custom id: {}",
+            interaction.data.custom_id
+        ))?;
+
+    let reply_privately = |content: &str| {
+        CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(content)
+                .ephemeral(true),
+        )
+    };
+
+    let thread_channel_id = interaction.channel_id.get() as i64;
+    let pug = db::read::find_pug(db.clone(), thread_channel_id)
+        .await
+        .context("Tried to fetch the pug of a map vote")?;
+    let (pug, vote) = match pug {
+        Some(pug) => match pug.map_vote.clone() {
+            Some(vote) if Utc::now() < vote.ends => (pug, vote),
+            _ => {
+                interaction
+                    .create_response(&ctx.http, reply_privately("This map vote has ended"))
+                    .await
+                    .context("Failed to reply to a map vote button")?;
+                return Ok(());
+            }
+        },
+        None => {
+            interaction
+                .create_response(&ctx.http, reply_privately("This map vote has ended"))
+                .await
+                .context("Failed to reply to a map vote button")?;
+            return Ok(());
+        }
+    };
+
+    if !pug.players.contains(&(interaction.user.id.get() as i64)) {
+        interaction
+            .create_response(
+                &ctx.http,
+                reply_privately("Only the players of this pug can vote for its map"),
+            )
+            .await
+            .context("Failed to reply to a map vote button")?;
+        return Ok(());
+    }
+
+    let map = vote
+        .candidates
+        .get(candidate_index)
+        .context("A map vote button refers to a map which is not in the vote")?;
+    let updated_vote =
+        db::write::cast_map_vote(db, thread_channel_id, interaction.user.id.get(), map)
+            .await
+            .context("Failed to save a map vote")?
+            .and_then(|pug| pug.map_vote);
+    let updated_vote = match updated_vote {
+        Some(updated_vote) => updated_vote,
+        None => {
+            interaction
+                .create_response(&ctx.http, reply_privately("This map vote has ended"))
+                .await
+                .context("Failed to reply to a map vote button")?;
+            return Ok(());
+        }
+    };
+
+    // the buttons are left as they are
+    let message = CreateInteractionResponseMessage::new().content(map_vote::render(
+        &pug.game_mode,
+        &updated_vote,
+        None,
+    ));
+    interaction
+        .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(message))
+        .await
+        .context("Failed to show the votes of a map vote")?;
+    Ok(())
+}
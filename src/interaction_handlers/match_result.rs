@@ -0,0 +1,355 @@
+use anyhow::Context as AnyhowContext;
+use chrono::Utc;
+use serenity::builder::{
+    CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse,
+};
+use serenity::client::Context;
+use serenity::model::application::{CommandInteraction, ComponentInteraction};
+use serenity::model::id::{ChannelId, UserId};
+use serenity::utils::MessageBuilder;
+
+use crate::db;
+use crate::db::model::{MatchOutcome, PugState, ResultReport, Team};
+use crate::utils::leaderboard;
+use crate::utils::result_report::{self, ReportAction};
+use crate::DbClientRef;
+
+/// Response for when a report changed between reading and updating it.
+const CONCURRENT_UPDATE_RESPONSE: &str =
+    "Someone else reported a result at the same time. Please try again";
+
+/// Response for a report button pressed after the report was settled or escalated.
+const NOT_PENDING_RESPONSE: &str = "This result is no longer waiting for confirmation";
+
+fn string_option<'a>(interaction: &'a CommandInteraction, name: &str) -> Option<&'a str> {
+    interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq(name))
+        .and_then(|option| option.value.as_str())
+}
+
+/// The score option, if one was given.
+fn score_option(interaction: &CommandInteraction) -> Option<String> {
+    string_option(interaction, "score")
+        .map(str::trim)
+        .filter(|score| !score.is_empty())
+        .map(str::to_string)
+}
+
+/// Command handler for /report, used by a captain in the thread of a completed pug.
+///
+/// The first report waits for the other captain to confirm it with a button. If the other
+/// captain reports the same result instead, it is confirmed, and if they report a different
+/// one, it is left for moderators to settle.
+pub async fn report(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let thread_channel_id = interaction.channel_id.get() as i64;
+    let pug = match db::read::find_pug(db.clone(), thread_channel_id)
+        .await
+        .context("Tried to fetch the pug of this thread")?
+    {
+        Some(pug) if pug.state == PugState::Completed => pug,
+        Some(_) => return Ok("Results can only be reported once picking is complete".to_string()),
+        None => return Ok("Use this command in the thread of the pug you played".to_string()),
+    };
+
+    let user_id = interaction.user.id.get() as i64;
+    let team = match pug.team_of(user_id) {
+        Some(team) if pug.is_captain(user_id) => team,
+        _ => return Ok("Only the captains of this pug can report its result".to_string()),
+    };
+
+    if let Some(outcome) = pug.outcome {
+        return Ok(format!(
+            "The result was already recorded: {}. Only moderators can change it, with /setresult",
+            result_report::describe(outcome, pug.score.as_deref())
+        ));
+    }
+
+    let outcome = match (
+        string_option(interaction, "result").context("The `result` option is missing")?,
+        team,
+    ) {
+        ("draw", _) => MatchOutcome::Draw,
+        ("win", Team::Red) | ("loss", Team::Blue) => MatchOutcome::RedWin,
+        ("win", Team::Blue) | ("loss", Team::Red) => MatchOutcome::BlueWin,
+        (other, _) => anyhow::bail!("Unrecognized /report result: {}", other),
+    };
+    let score = score_option(interaction);
+
+    if let Some(pending) = &pug.result_report {
+        if pending.escalated {
+            return Ok("A reported result is already waiting for a moderator".to_string());
+        }
+        if pending.reporter == user_id {
+            return Ok(format!(
+                "You already reported: {}. Waiting for the other captain to confirm it",
+                result_report::describe(pending.outcome, pending.score.as_deref())
+            ));
+        }
+
+        // both captains reported, so they either agree or a moderator decides
+        if pending.outcome == outcome {
+            return match db::write::confirm_result(db, &pug)
+                .await
+                .context("Failed to record a confirmed result")?
+            {
+                Some(pug) => {
+                    leaderboard::invalidate(ctx, guild_id).await;
+                    Ok(format!(
+                        "Both captains reported the same result. Recorded: {}",
+                        result_report::describe(
+                            pug.outcome.unwrap_or(outcome),
+                            pug.score.as_deref()
+                        )
+                    ))
+                }
+                None => Ok(CONCURRENT_UPDATE_RESPONSE.to_string()),
+            };
+        }
+        let reason = format!(
+            "the captains reported different results ({} and {})",
+            result_report::describe(pending.outcome, pending.score.as_deref()),
+            result_report::describe(outcome, score.as_deref())
+        );
+        if !result_report::escalate(ctx, db, &pug, &reason).await? {
+            return Ok(CONCURRENT_UPDATE_RESPONSE.to_string());
+        }
+        return Ok(
+            "Your report conflicts with the other captain's, so moderators have been asked to settle it"
+                .to_string(),
+        );
+    }
+
+    let report = ResultReport {
+        reporter: user_id,
+        outcome,
+        score,
+        reported: Utc::now(),
+        escalated: false,
+    };
+    if db::write::report_result(db, thread_channel_id, &report)
+        .await
+        .context("Failed to save a reported result")?
+        .is_none()
+    {
+        return Ok(CONCURRENT_UPDATE_RESPONSE.to_string());
+    }
+
+    let confirm_mins = crate::config::get(ctx).await.pugs.result_confirm_mins;
+    let mut response = MessageBuilder::default();
+    response.mention(&interaction.user).push_line(format!(
+        " reported: {}",
+        result_report::describe(report.outcome, report.score.as_deref())
+    ));
+    if let Some(opposing_captain) = pug.opposing_captain(user_id) {
+        response.mention(&UserId::from(opposing_captain as u64));
+    }
+    response.push(format!(
+        ", confirm or dispute it within {} minutes, or moderators will be asked to settle it",
+        confirm_mins
+    ));
+
+    interaction
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new().components(vec![result_report::buttons()]),
+        )
+        .await
+        .context("Failed to attach confirmation buttons to a reported result")?;
+    Ok(response.build())
+}
+
+/// Handle a press of the confirm or dispute button of a reported result.
+pub async fn report_button(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+) -> anyhow::Result<()> {
+    let guild_id = interaction
+        .guild_id
+        .context("Result report buttons are only used in guilds")?;
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let action = result_report::from_custom_id(&interaction.data.custom_id).context(format!(
+        "Malformed result report button custom id: {}",
+        interaction.data.custom_id
+    ))?;
+
+    let reply_privately = |content: &str| {
+        CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(content)
+                .ephemeral(true),
+        )
+    };
+
+    let thread_channel_id = interaction.channel_id.get() as i64;
+    let pug = db::read::find_pug(db.clone(), thread_channel_id)
+        .await
+        .context("Tried to fetch the pug of a result report")?;
+    let (pug, report) = match pug {
+        Some(pug) if pug.outcome.is_none() => match pug.result_report.clone() {
+            Some(report) if !report.escalated => (pug, report),
+            _ => {
+                interaction
+                    .create_response(&ctx.http, reply_privately(NOT_PENDING_RESPONSE))
+                    .await
+                    .context("Failed to reply to a result report button")?;
+                return Ok(());
+            }
+        },
+        _ => {
+            interaction
+                .create_response(&ctx.http, reply_privately(NOT_PENDING_RESPONSE))
+                .await
+                .context("Failed to reply to a result report button")?;
+            return Ok(());
+        }
+    };
+
+    if pug.opposing_captain(report.reporter) != Some(interaction.user.id.get() as i64) {
+        interaction
+            .create_response(
+                &ctx.http,
+                reply_privately(
+                    "Only the captain of the other team can confirm or dispute this result",
+                ),
+            )
+            .await
+            .context("Failed to reply to a result report button")?;
+        return Ok(());
+    }
+
+    let description = result_report::describe(report.outcome, report.score.as_deref());
+    match action {
+        ReportAction::Confirm => {
+            if db::write::confirm_result(db, &pug)
+                .await
+                .context("Failed to record a confirmed result")?
+                .is_none()
+            {
+                interaction
+                    .create_response(&ctx.http, reply_privately(NOT_PENDING_RESPONSE))
+                    .await
+                    .context("Failed to reply to a result report button")?;
+                return Ok(());
+            }
+            leaderboard::invalidate(ctx, guild_id).await;
+
+            let content = MessageBuilder::default()
+                .push(format!("Result recorded: {}. Confirmed by ", description))
+                .mention(&interaction.user)
+                .build();
+            let message = CreateInteractionResponseMessage::new()
+                .content(content)
+                .components(Vec::default());
+            interaction
+                .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(message))
+                .await
+                .context("Failed to update a confirmed result report")?;
+        }
+        ReportAction::Dispute => {
+            // escalating posts to the thread and the pug channel, which can take a while
+            interaction
+                .defer(&ctx.http)
+                .await
+                .context("Failed to acknowledge a result report button")?;
+
+            let reason = format!("{} disputed the reported result", interaction.user.name);
+            let content = if result_report::escalate(ctx, db, &pug, &reason).await? {
+                MessageBuilder::default()
+                    .push(format!("{}. Disputed by ", description))
+                    .mention(&interaction.user)
+                    .push(", so moderators have been asked to settle it")
+                    .build()
+            } else {
+                NOT_PENDING_RESPONSE.to_string()
+            };
+            interaction
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content(content)
+                        .components(Vec::default()),
+                )
+                .await
+                .context("Failed to update a disputed result report")?;
+        }
+    }
+    Ok(())
+}
+
+/// Command handler for /setresult, with which moderators settle escalated reports, or change
+/// a recorded result. Used in a pug's thread, or anywhere with its `thread` option.
+pub async fn set_result(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let thread_channel_id = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("thread"))
+        .and_then(|option| option.value.as_channel_id())
+        .unwrap_or(interaction.channel_id);
+
+    let outcome =
+        match string_option(interaction, "result").context("The `result` option is missing")? {
+            "red" => MatchOutcome::RedWin,
+            "blue" => MatchOutcome::BlueWin,
+            "draw" => MatchOutcome::Draw,
+            other => anyhow::bail!("Unrecognized /setresult result: {}", other),
+        };
+
+    let pug = match db::write::set_result(
+        db,
+        thread_channel_id.get() as i64,
+        outcome,
+        score_option(interaction),
+        interaction.user.id.get() as i64,
+    )
+    .await
+    .context("Failed to set the result of a pug")?
+    {
+        Some(pug) => pug,
+        None => return Ok("No completed pug was found in that thread".to_string()),
+    };
+    leaderboard::invalidate(ctx, guild_id).await;
+
+    let response = MessageBuilder::default()
+        .push("Result of ")
+        .push_bold(&pug.game_mode)
+        .push(" in ")
+        .mention(&ChannelId::from(pug.thread_channel_id as u64))
+        .push(format!(
+            " set to: {}",
+            result_report::describe(outcome, pug.score.as_deref())
+        ))
+        .build();
+    Ok(response)
+}
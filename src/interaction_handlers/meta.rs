@@ -1,7 +1,13 @@
+use anyhow::Context as AnyhowContext;
 use serenity::client::Context;
 use serenity::model::application::CommandInteraction;
+use serenity::model::id::UserId;
 use serenity::utils::MessageBuilder;
 
+use crate::db;
+use crate::utils::time::{Accuracy, HumanTime, Tense};
+use crate::DbClientRef;
+
 pub fn render_help_text() -> String {
     let mut response = MessageBuilder::new();
     response
@@ -10,18 +16,99 @@ pub fn render_help_text() -> String {
     response.to_string()
 }
 
+/// Display a previous pug from match history.
+///
+/// Accepts optional fields `match_age` (defaults to the most recent pug), `game_mode` and `season`.
 pub async fn pug_history(
-    _ctx: &Context,
-    _interaction: &CommandInteraction,
+    ctx: &Context,
+    interaction: &CommandInteraction,
 ) -> anyhow::Result<String> {
-    // validate this channel is a GuildChannel
-    // with kind PublicThread
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let match_age = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("match_age"))
+        .and_then(|option| option.value.as_i64())
+        .map_or(0, |match_age| match_age.max(0) as u64);
+
+    // the game mode may have been given as an alias
+    let game_mode_label = match interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("game_mode"))
+        .and_then(|option| option.value.as_str())
+    {
+        Some(name) => match db::read::find_game_mode(db.clone(), name).await? {
+            Some(game_mode) => Some(game_mode.label),
+            None => return Ok("No game mode found with this name".to_string()),
+        },
+        None => None,
+    };
+    let game_mode_label = game_mode_label.as_deref();
 
-    // check for an active pug
+    let season = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("season"))
+        .and_then(|option| option.value.as_str());
 
-    // Clear all captains and picks
+    let completed_pug = match db::read::get_completed_pug(db, game_mode_label, season, match_age)
+        .await
+        .context("Failed to read match history")?
+    {
+        Some(completed_pug) => completed_pug,
+        None => return Ok("No matching pug found in match history".to_string()),
+    };
 
-    // Restart autocap timer
+    let red_captain = user_names(ctx, completed_pug.red_team_captain.as_slice()).await?;
+    let red_team = user_names(ctx, &completed_pug.red_team).await?;
+    let blue_captain = user_names(ctx, completed_pug.blue_team_captain.as_slice()).await?;
+    let blue_team = user_names(ctx, &completed_pug.blue_team).await?;
+
+    let ht = HumanTime::from(completed_pug.created);
+    let mut response = MessageBuilder::default();
+    response.push_bold(&completed_pug.game_mode).push(format!(
+        " - {}",
+        ht.to_text_en(Accuracy::Rough, Tense::Past)
+    ));
+    if let Some(season) = &completed_pug.season {
+        response.push(format!(" [{}]", season));
+    }
+    response.push_line("");
+    if let Some(map) = &completed_pug.map {
+        response.push("Map: ").push_bold_line(map);
+    }
+    response
+        .push("Red Team 🔴: ")
+        .push_bold(format!("{} ", red_captain))
+        .push_line(red_team)
+        .push("Blue Team 🔵: ")
+        .push_bold(format!("{} ", blue_captain))
+        .push_line(blue_team);
+
+    Ok(response.build())
+}
 
-    Ok("Sorry, this feature is incomplete".to_string())
+async fn user_names(ctx: &Context, user_ids: &[i64]) -> anyhow::Result<String> {
+    let mut names = Vec::default();
+    for user_id in user_ids {
+        let user = UserId::from(*user_id as u64)
+            .to_user(ctx)
+            .await
+            .context("Error encountered while converting `UserId` to `User`")?;
+        names.push(user.name);
+    }
+    Ok(names.join(", "))
 }
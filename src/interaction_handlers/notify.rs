@@ -0,0 +1,133 @@
+use anyhow::Context as AnyhowContext;
+use serenity::client::Context;
+use serenity::model::application::CommandInteraction;
+use serenity::utils::MessageBuilder;
+
+use crate::db;
+use crate::db::model::{NotificationDelivery, QueueNotification};
+use crate::DbClientRef;
+
+/// Subscribe (or unsubscribe) the caller to alerts for when the
+/// queue of a game mode reaches a certain number of players.
+///
+/// Expects field `game_mode`, with optional `threshold`, `delivery`,
+/// `quiet_start` and `quiet_end`. Without a threshold, the caller is unsubscribed.
+pub async fn subscribe(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let game_mode_label = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("game_mode"))
+        .context("The `game_mode` option is missing")?
+        .value
+        .as_str()
+        .context("Somehow, the value of the `game_mode` option is not a string")?
+        .to_string();
+
+    let threshold = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("threshold"))
+        .and_then(|option| option.value.as_i64());
+
+    let delivery = match interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("delivery"))
+        .and_then(|option| option.value.as_str())
+    {
+        Some("mention") => NotificationDelivery::ChannelMention,
+        _ => NotificationDelivery::DirectMessage,
+    };
+
+    let quiet_hours_start = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("quiet_start"))
+        .and_then(|option| option.value.as_i64());
+
+    let quiet_hours_end = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("quiet_end"))
+        .and_then(|option| option.value.as_i64());
+
+    if quiet_hours_start.is_some() != quiet_hours_end.is_some() {
+        return Ok("Provide both `quiet_start` and `quiet_end` to set quiet hours".to_string());
+    }
+
+    let game_mode = match db::read::find_game_mode(db.clone(), &game_mode_label).await? {
+        Some(game_mode) => game_mode,
+        None => return Ok("No game mode found with this name".to_string()),
+    };
+
+    let user_id = interaction.user.id.get();
+
+    let threshold = match threshold {
+        Some(threshold) => threshold,
+        None => {
+            let result = db::write::delete_queue_notification(db, &user_id, &game_mode.label)
+                .await
+                .context("Failed to delete queue notification subscription")?;
+            return match result.deleted_count {
+                0 => Ok(format!(
+                    "You were not subscribed to alerts for **{}**",
+                    game_mode.label
+                )),
+                _ => Ok(format!(
+                    "You will no longer be alerted about **{}**",
+                    game_mode.label
+                )),
+            };
+        }
+    };
+
+    if threshold >= game_mode.player_count {
+        return Ok(format!(
+            "**{}** fills at {} players, so the threshold must be less than that",
+            game_mode.label, game_mode.player_count
+        ));
+    }
+
+    db::write::set_queue_notification(
+        db,
+        QueueNotification {
+            user_id: user_id as i64,
+            game_mode_label: game_mode.label.clone(),
+            threshold,
+            delivery,
+            quiet_hours_start,
+            quiet_hours_end,
+            last_notified: None,
+        },
+    )
+    .await
+    .context("Failed to save queue notification subscription")?;
+
+    let mut response = MessageBuilder::default();
+    response.push(format!(
+        "You will be alerted when **{}** reaches {}/{}",
+        game_mode.label, threshold, game_mode.player_count
+    ));
+    if let (Some(start), Some(end)) = (quiet_hours_start, quiet_hours_end) {
+        response.push(format!(
+            ", except between {:02}:00 and {:02}:00 UTC",
+            start, end
+        ));
+    }
+    Ok(response.build())
+}
@@ -0,0 +1,192 @@
+use anyhow::Context as AnyhowContext;
+use mongodb::Database;
+use serenity::client::Context;
+use serenity::model::application::{CommandDataOption, CommandDataOptionValue, CommandInteraction};
+use serenity::model::id::UserId;
+use serenity::model::user::User;
+use serenity::utils::MessageBuilder;
+
+use crate::db;
+use crate::utils::queue_board;
+use crate::DbClientRef;
+
+/// Command handler for /party, which dispatches to its `invite`, `accept`, `leave`
+/// and `disband` subcommands.
+///
+/// Party size limits are checked when a party joins a queue, since they depend on the game mode.
+pub async fn manage(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let subcommand = interaction
+        .data
+        .options
+        .first()
+        .context("The /party command was used without a subcommand")?;
+
+    let sub_options = match &subcommand.value {
+        CommandDataOptionValue::SubCommand(sub_options) => sub_options,
+        _ => anyhow::bail!("The first option of /party is expected to be a subcommand"),
+    };
+
+    let response = match subcommand.name.as_str() {
+        "invite" => {
+            let invitee = user_option(interaction, sub_options, "user")
+                .context("The `user` option is missing")?;
+            invite(db, &interaction.user, invitee).await?
+        }
+        "accept" => {
+            let inviter = user_option(interaction, sub_options, "from");
+            accept(db, &interaction.user, inviter).await?
+        }
+        "leave" => leave(db, &interaction.user).await?,
+        "disband" => disband(db, &interaction.user).await?,
+        other => anyhow::bail!("Unrecognized /party subcommand: {}", other),
+    };
+
+    // queued parties are shown together
+    queue_board::refresh(ctx, guild_id).await;
+    Ok(response)
+}
+
+/// Get the resolved user of a `User` option.
+fn user_option<'a>(
+    interaction: &'a CommandInteraction,
+    options: &[CommandDataOption],
+    name: &str,
+) -> Option<&'a User> {
+    let user_id = options
+        .iter()
+        .find(|option| option.name.eq(name))?
+        .value
+        .as_user_id()?;
+    interaction.data.resolved.users.get(&user_id)
+}
+
+async fn invite(db: Database, leader: &User, invitee: &User) -> anyhow::Result<String> {
+    if invitee.id == leader.id {
+        return Ok("You cannot invite yourself".to_string());
+    }
+    if invitee.bot {
+        return Ok("Bots cannot join parties".to_string());
+    }
+
+    if let Some(party) = db::read::find_party_of(db.clone(), leader.id.get()).await? {
+        if party.leader != leader.id.get() as i64 {
+            return Ok("Only the leader of your party can invite players".to_string());
+        }
+        if party.members.contains(&(invitee.id.get() as i64)) {
+            return Ok(format!("{} is already in your party", invitee.name));
+        }
+    }
+    if let Some(party) = db::read::find_party_of(db.clone(), invitee.id.get()).await? {
+        // someone whose invites are all still pending is not in a party yet
+        if party.members.len() > 1 {
+            return Ok(format!("{} is already in a party", invitee.name));
+        }
+    }
+
+    db::write::invite_to_party(db, leader.id.get(), invitee.id.get())
+        .await
+        .context("Failed to save a party invite")?;
+
+    let response = MessageBuilder::default()
+        .mention(invitee)
+        .push(format!(
+            ", {} invited you to their party. Use `/party accept` to join it",
+            leader.name
+        ))
+        .build();
+    Ok(response)
+}
+
+async fn accept(db: Database, user: &User, inviter: Option<&User>) -> anyhow::Result<String> {
+    if let Some(party) = db::read::find_party_of(db.clone(), user.id.get()).await? {
+        if party.members.len() > 1 {
+            return Ok("You are already in a party. Use `/party leave` first".to_string());
+        }
+    }
+
+    let invites = db::read::get_party_invites(db.clone(), user.id.get())
+        .await
+        .context("Failed to read party invites")?;
+    let party = match inviter {
+        Some(inviter) => match invites
+            .iter()
+            .find(|party| party.leader == inviter.id.get() as i64)
+        {
+            Some(party) => party,
+            None => return Ok(format!("{} has not invited you to a party", inviter.name)),
+        },
+        None => match invites.as_slice() {
+            [] => return Ok("You have no party invites".to_string()),
+            [party] => party,
+            _ => {
+                let mut response = MessageBuilder::default();
+                response.push("You have been invited by ");
+                for (index, party) in invites.iter().enumerate() {
+                    if index > 0 {
+                        response.push(", ");
+                    }
+                    response.mention(&UserId::from(party.leader as u64));
+                }
+                response.push(". Use the `from` option to choose a party");
+                return Ok(response.build());
+            }
+        },
+    };
+
+    // a party which never had anyone accept its invites is given up for this one
+    db::write::disband_party(db.clone(), user.id.get())
+        .await
+        .context("Failed to disband the empty party of a player accepting an invite")?;
+
+    let party = match db::write::accept_party_invite(db, party.leader, user.id.get())
+        .await
+        .context("Failed to accept a party invite")?
+    {
+        Some(party) => party,
+        None => return Ok("That invite is no longer available".to_string()),
+    };
+
+    let mut response = MessageBuilder::default();
+    response.push(format!("{} joined the party of ", user.name));
+    response.mention(&UserId::from(party.leader as u64));
+    response.push(format!(" ({} players)", party.members.len()));
+    Ok(response.build())
+}
+
+async fn leave(db: Database, user: &User) -> anyhow::Result<String> {
+    let party = match db::write::leave_party(db, user.id.get())
+        .await
+        .context("Failed to leave a party")?
+    {
+        Some(party) => party,
+        None => return Ok("You are not in a party".to_string()),
+    };
+    if party.members.len() <= 2 {
+        return Ok(format!("{} left the party, so it was disbanded", user.name));
+    }
+    Ok(format!("{} left the party", user.name))
+}
+
+async fn disband(db: Database, user: &User) -> anyhow::Result<String> {
+    if db::write::disband_party(db.clone(), user.id.get())
+        .await
+        .context("Failed to disband a party")?
+        .is_some()
+    {
+        return Ok(format!("{} disbanded their party", user.name));
+    }
+    match db::read::find_party_of(db, user.id.get()).await? {
+        Some(_) => Ok("Only the leader of your party can disband it".to_string()),
+        None => Ok("You are not leading a party".to_string()),
+    }
+}
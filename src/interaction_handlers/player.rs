@@ -3,7 +3,7 @@ use serenity::client::Context;
 use serenity::model::application::CommandInteraction;
 use serenity::model::channel::{Channel, ChannelType};
 
-use super::IntendedGameMode;
+use super::{IntendedGameMode, ReplyStyle};
 
 // !TODO: validate that this command is sent from either the registered pug channel,
 // or a pug thread for an active picking session
@@ -55,12 +55,13 @@ pub async fn add_to_pug(ctx: &Context, interaction: &CommandInteraction) -> anyh
     let game_mode_arg = arg.to_string();
 
     super::queue::join_helper(
-        &ctx,
+        ctx,
         guild_id,
         guild_channel,
         db,
         IntendedGameMode::Single(game_mode_arg),
         target_user_id.get(),
+        ReplyStyle::Full,
     )
     .await
 }
@@ -114,7 +115,7 @@ pub async fn remove_from_pug(
     let game_mode_arg = arg.to_string();
 
     super::queue::leave_helper(
-        &ctx,
+        ctx,
         guild_id,
         guild_channel,
         db,
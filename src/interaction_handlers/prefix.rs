@@ -0,0 +1,234 @@
+//! Text commands for joining and leaving queues, as classic pug bots have them.
+//!
+//! In the pug channel, `++ctf`, `++ctf tdm`, `!j ctf tdm`, `!add ctf` join queues and
+//! `--ctf`, `!l ctf`, `!remove ctf` leave them. Without a game mode, `--` leaves every queue
+//! (and `++` is treated like /join without one). Game modes can be given by label or alias.
+
+use anyhow::Context as AnyhowContext;
+use serenity::client::Context;
+use serenity::model::channel::{Channel, Message};
+
+use super::queue::{join_helper, leave_helper};
+use super::{IntendedGameMode, ReplyStyle};
+use crate::db;
+use crate::DbClientRef;
+
+const JOIN_WORDS: [&str; 3] = ["!j", "!join", "!add"];
+const LEAVE_WORDS: [&str; 4] = ["!l", "!leave", "!remove", "!del"];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TextCommand {
+    Join(Vec<String>),
+    Leave(Vec<String>),
+}
+
+impl TextCommand {
+    /// The name recorded in metrics and incidents.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextCommand::Join(_) => "++",
+            TextCommand::Leave(_) => "--",
+        }
+    }
+}
+
+/// Read a text command from the start of a message, if it has one.
+pub fn parse(content: &str) -> Option<TextCommand> {
+    let mut words = content.split_whitespace();
+    let first = words.next()?;
+    let lowercase_first = first.to_lowercase();
+
+    let (is_join, first_game_mode) = if let Some(rest) = first.strip_prefix("++") {
+        (true, rest)
+    } else if let Some(rest) = first.strip_prefix("--") {
+        (false, rest)
+    } else if JOIN_WORDS.contains(&lowercase_first.as_str()) {
+        (true, "")
+    } else if LEAVE_WORDS.contains(&lowercase_first.as_str()) {
+        (false, "")
+    } else {
+        return None;
+    };
+    let marker = if is_join { "++" } else { "--" };
+    // a line of dashes (or pluses) is not a command
+    if first_game_mode.starts_with(['+', '-']) {
+        return None;
+    }
+
+    // `++ctf ++tdm` is read the same as `++ctf tdm`
+    let mut game_modes: Vec<String> = Vec::default();
+    for game_mode in std::iter::once(first_game_mode).chain(words) {
+        let game_mode = game_mode.strip_prefix(marker).unwrap_or(game_mode);
+        if game_mode.is_empty() || game_modes.iter().any(|g| g.eq_ignore_ascii_case(game_mode)) {
+            continue;
+        }
+        game_modes.push(game_mode.to_string());
+    }
+
+    Some(if is_join {
+        TextCommand::Join(game_modes)
+    } else {
+        TextCommand::Leave(game_modes)
+    })
+}
+
+/// Run a text command through the same code as /join and /leave, one game mode at a time.
+///
+/// Returns `None` when the message was not sent in the guild's pug channel, so ordinary
+/// chat elsewhere which happens to look like a text command is left alone.
+pub async fn run(
+    ctx: &Context,
+    msg: &Message,
+    command: TextCommand,
+) -> anyhow::Result<Option<String>> {
+    let guild_id = match msg.guild_id {
+        Some(guild_id) => guild_id,
+        None => return Ok(None),
+    };
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let pug_channel = db::read::get_pug_channel(db.clone())
+        .await
+        .context("Failed to read the pug channel")?;
+    match pug_channel {
+        Some(pug_channel) if pug_channel.channel_id as u64 == msg.channel_id.get() => {}
+        _ => return Ok(None),
+    }
+
+    let guild_channel = match msg
+        .channel(&ctx)
+        .await
+        .context("Tried to obtain `Channel` from a ChannelId")?
+    {
+        Channel::Guild(channel) => channel,
+        _ => return Ok(None),
+    };
+
+    let (is_join, game_modes) = match command {
+        TextCommand::Join(game_modes) => (true, game_modes),
+        TextCommand::Leave(game_modes) => (false, game_modes),
+    };
+    let targets = if game_modes.is_empty() {
+        vec![IntendedGameMode::All]
+    } else {
+        game_modes
+            .into_iter()
+            .map(IntendedGameMode::Single)
+            .collect()
+    };
+
+    let user_id = msg.author.id.get();
+    let mut replies = Vec::default();
+    for target in targets {
+        let reply = if is_join {
+            join_helper(
+                ctx,
+                guild_id,
+                guild_channel.clone(),
+                db.clone(),
+                target,
+                user_id,
+                ReplyStyle::Compact,
+            )
+            .await?
+        } else {
+            leave_helper(
+                ctx,
+                guild_id,
+                guild_channel.clone(),
+                db.clone(),
+                target,
+                user_id,
+            )
+            .await?
+        };
+        replies.push(reply);
+    }
+
+    Ok(Some(replies.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join(game_modes: &[&str]) -> Option<TextCommand> {
+        Some(TextCommand::Join(
+            game_modes.iter().map(|g| g.to_string()).collect(),
+        ))
+    }
+
+    fn leave(game_modes: &[&str]) -> Option<TextCommand> {
+        Some(TextCommand::Leave(
+            game_modes.iter().map(|g| g.to_string()).collect(),
+        ))
+    }
+
+    #[test]
+    fn joining_is_read_the_same_however_it_is_written() {
+        for content in [
+            "++ctf tdm",
+            "++ctf ++tdm",
+            "!j ctf tdm",
+            "!JOIN ctf tdm",
+            "!add ctf\ntdm",
+        ] {
+            assert_eq!(parse(content), join(&["ctf", "tdm"]), "{}", content);
+        }
+    }
+
+    #[test]
+    fn leaving_is_read_the_same_however_it_is_written() {
+        for content in [
+            "--ctf tdm",
+            "--ctf --tdm",
+            "!l ctf tdm",
+            "!Leave ctf tdm",
+            "!del ctf tdm",
+        ] {
+            assert_eq!(parse(content), leave(&["ctf", "tdm"]), "{}", content);
+        }
+    }
+
+    #[test]
+    fn without_game_modes() {
+        assert_eq!(parse("++"), join(&[]));
+        assert_eq!(parse("!join"), join(&[]));
+        assert_eq!(parse("--"), leave(&[]));
+        assert_eq!(parse("!remove"), leave(&[]));
+    }
+
+    #[test]
+    fn repeated_game_modes_are_ignored() {
+        assert_eq!(parse("++ctf ctf ++CTF tdm"), join(&["ctf", "tdm"]));
+    }
+
+    #[test]
+    fn game_modes_are_kept_as_typed() {
+        // aliases and case are resolved when the command is run
+        assert_eq!(parse("--CTF capture"), leave(&["CTF", "capture"]));
+    }
+
+    #[test]
+    fn lines_of_dashes_and_pluses_are_not_commands() {
+        assert_eq!(parse("-----"), None);
+        assert_eq!(parse("+++"), None);
+        assert_eq!(parse("--- ctf"), None);
+    }
+
+    #[test]
+    fn other_messages_are_not_commands() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("   "), None);
+        assert_eq!(parse("gg ++ctf"), None);
+        assert_eq!(parse("!jctf"), None);
+        assert_eq!(parse("j ctf"), None);
+    }
+}
@@ -1,12 +1,17 @@
 use anyhow::Context as AnyhowContext;
 use serenity::client::Context;
 use serenity::model::application::CommandInteraction;
+use serenity::model::id::{ChannelId, MessageId};
 use serenity::utils::MessageBuilder;
+use tracing::warn;
 
+use crate::db::read::get_pug_channel;
 use crate::db::write::set_pug_channel;
+use crate::utils::queue_board;
 use crate::DbClientRef;
 
-/// Declare a text channel in a guild as the designated pug channel
+/// Declare a text channel in a guild as the designated pug channel.
+/// The queue board moves to it from the previous pug channel, if any.
 pub async fn set(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
     let client = {
         let data = ctx.data.read().await;
@@ -24,8 +29,24 @@ pub async fn set(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Res
         .await
         .context("Failed to fetch channel name")?;
 
+    let previous_pug_channel = get_pug_channel(db.clone())
+        .await
+        .context("Failed to read the previous pug channel")?;
     set_pug_channel(db, channel_id.get(), Some(channel_name), Vec::new()).await?;
 
+    if let Some(previous_pug_channel) = previous_pug_channel {
+        if let Some(board_message_id) = previous_pug_channel.board_message_id {
+            if let Err(err) = ChannelId::new(previous_pug_channel.channel_id as u64)
+                .delete_message(&ctx.http, MessageId::new(board_message_id as u64))
+                .await
+            {
+                warn!("Failed to delete the previous queue board: {}", err);
+            }
+        }
+    }
+    queue_board::forget(ctx, guild_id).await;
+    queue_board::refresh(ctx, guild_id).await;
+
     let response = MessageBuilder::new()
         .mention(&channel_id)
         .push(" is now the designated pug channel")
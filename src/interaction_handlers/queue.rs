@@ -2,25 +2,29 @@ use anyhow::Context as AnyhowContext;
 use chrono::Datelike;
 use chrono::Utc;
 use itertools::Itertools;
-use mongodb::Database;
+use mongodb::{Client, Database};
 use serenity::all::CommandInteraction;
+use serenity::all::ComponentInteraction;
 use serenity::all::CreateThread;
+use serenity::async_trait;
+use serenity::builder::EditInteractionResponse;
 use serenity::client::Context;
 use serenity::model::channel::{Channel, ChannelType, GuildChannel};
-use serenity::model::id::{GuildId, UserId};
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
 use serenity::utils::MessageBuilder;
-use std::collections::HashMap;
+use tracing::error;
 
-use crate::command_builder::{build_autocaptain, build_captain, build_nocaptain, build_reset};
-use crate::db::model::{GameMode, GameModeJoin, PickingSession};
-use crate::db::read::{find_game_mode, get_game_mode_queue};
-use crate::db::write::{
-    add_player_to_game_mode_queue, register_picking_session, save_guild_commands,
+use crate::db::model::{GameMode, GameModeJoin, Party, Pug, PugState};
+use crate::db::read::find_game_mode;
+use crate::db::write::{add_player_to_game_mode_queue, register_filled_pug, unregister_filled_pug};
+use crate::utils::presentation::{self, QueueView};
+use crate::utils::{
+    application_commands, captain, map_vote, notifications, party, queue_board, queue_lock,
+    transform,
 };
-use crate::utils::{captain, transform};
 use crate::{db, DbClientRef};
 
-use super::IntendedGameMode;
+use super::{IntendedGameMode, ReplyStyle};
 
 // FIXME: add anyhow context to all ? operator usage
 // !TODO: lots of duplicate code in this whole module
@@ -72,12 +76,13 @@ pub async fn join(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Re
     };
 
     join_helper(
-        &ctx,
+        ctx,
         guild_id,
         guild_channel,
         db,
         game_mode_target,
         *current_user_id,
+        ReplyStyle::Full,
     )
     .await
 }
@@ -89,6 +94,7 @@ pub async fn join_helper(
     db: Database,
     target_game_modes: IntendedGameMode,
     user_to_add: u64,
+    reply_style: ReplyStyle,
 ) -> anyhow::Result<String> {
     let game_mode_label = match target_game_modes {
         IntendedGameMode::Single(desired_game_mode) => desired_game_mode,
@@ -105,16 +111,38 @@ pub async fn join_helper(
         }
     };
 
-    let maybe_game_mode = find_game_mode(db.clone(), &game_mode_label).await?;
-    if maybe_game_mode.is_none() {
-        return Ok("No game mode found with this name".to_string());
+    // held until the player is added, or the filled pug has been started,
+    // so no other join can see the queue before then
+    let _queue_lock = queue_lock::lock(ctx, guild_id).await;
+
+    // read with the lock held, so the game mode cannot be deleted or edited (by commands
+    // which take the lock) before its queue is
+    let game_mode = match find_game_mode(db.clone(), &game_mode_label).await? {
+        Some(game_mode) => game_mode,
+        None => return Ok("No game mode found with this name".to_string()),
+    };
+    if game_mode.disabled {
+        return Ok(format!("**{}** is disabled", game_mode.label));
     }
-    let game_mode = maybe_game_mode.unwrap();
 
-    let mut all_queues = db::read::get_all_queues(db.clone()).await?;
-    let queue = all_queues.get_mut(&game_mode).unwrap();
+    // a player in a party joins along with the rest of it
+    let party_members = match db::read::find_party_of(db.clone(), user_to_add)
+        .await
+        .context("Failed to look up the party of a joining player")?
+    {
+        Some(party) => party.members,
+        None => vec![user_to_add as i64],
+    };
+    let party_size_limit =
+        game_mode.party_size_limit(crate::config::get(ctx).await.pugs.max_party_size);
+    if party_members.len() > party_size_limit {
+        return Ok(format!(
+            "Parties of more than {} cannot join **{}**",
+            party_size_limit, game_mode.label
+        ));
+    }
 
-    // let mut queue = get_game_mode_queue(db.clone(), &game_mode.label).await?;
+    let mut queue = db::read::get_game_mode_queue(db.clone(), &game_mode.label).await?;
     let user_is_in_queue = queue
         .iter()
         .any(|join_record| join_record.player_user_id as u64 == user_to_add);
@@ -123,31 +151,76 @@ pub async fn join_helper(
         return Ok("User is already in the queue".to_string());
     }
 
-    let queue_not_yet_filled = queue.len() as u64 + 1 < game_mode.player_count as u64;
+    // party members who are in the queue already keep their place
+    let joining = std::iter::once(user_to_add)
+        .chain(
+            party_members
+                .iter()
+                .map(|member| *member as u64)
+                .filter(|member| *member != user_to_add)
+                .filter(|member| {
+                    !queue
+                        .iter()
+                        .any(|join_record| join_record.player_user_id as u64 == *member)
+                }),
+        )
+        .collect::<Vec<u64>>();
+
+    // a party only fills a queue as a unit
+    if queue.len() + joining.len() > game_mode.player_count as usize {
+        return Ok(format!(
+            "Not enough slots left in **{}** for your party of {}",
+            game_mode.label,
+            joining.len()
+        ));
+    }
+
+    let queue_not_yet_filled = queue.len() + joining.len() < game_mode.player_count as usize;
 
     if queue_not_yet_filled {
-        // add player to game mode queue and exit
-        queue.push(
-            add_player_to_game_mode_queue(db.clone(), &game_mode.label, &user_to_add)
-                .await
-                .context(format!(
-                    "Failed to add user {} to {} game mode",
-                    &user_to_add, &game_mode.label
-                ))?
-                .unwrap(),
-        );
+        let previous_size = queue.len();
+        // add players to game mode queue and exit
+        for user_id in joining.iter() {
+            queue.push(
+                add_player_to_game_mode_queue(db.clone(), &game_mode.label, user_id)
+                    .await
+                    .context(format!(
+                        "Failed to add user {} to {} game mode",
+                        user_id, &game_mode.label
+                    ))?
+                    .unwrap(),
+            );
+        }
+
+        // alert anyone waiting for this queue to reach its current size
+        tokio::spawn(notifications::notify_queue_subscribers(
+            ctx.clone(),
+            db.clone(),
+            guild_channel.clone(),
+            game_mode.clone(),
+            previous_size,
+            queue.iter().map(|j| j.player_user_id).collect(),
+        ));
+        queue_board::refresh(ctx, guild_id).await;
 
         let mut users_in_queue = Vec::default();
         for join_record in queue.iter() {
-            users_in_queue.push(transform::join_record_to_player_info(&ctx, join_record).await?);
+            users_in_queue.push(transform::join_record_to_player_info(ctx, join_record).await?);
         }
 
         let queue_names = users_in_queue
             .iter()
             .format_with(" :small_blue_diamond: ", |player_info, f| f(player_info));
 
-        let response = MessageBuilder::new()
-            .push_line("Successfully added to the waiting queue")
+        let mut response = MessageBuilder::new();
+        if let ReplyStyle::Full = reply_style {
+            if joining.len() > 1 {
+                response.push_line("Successfully added your party to the waiting queue");
+            } else {
+                response.push_line("Successfully added to the waiting queue");
+            }
+        }
+        let response = response
             .push_bold(game_mode.label)
             .push(format!(" ({}/{}) ", queue.len(), game_mode.player_count))
             .push(queue_names.to_string())
@@ -161,57 +234,326 @@ pub async fn join_helper(
         .iter_mut()
         .map(|j| j.player_user_id as u64)
         .collect::<Vec<u64>>();
-    // no need to insert this user into the queue
+    // no need to insert these users into the queue
     // at the database level as it'll soon be cleared
-    players.push(user_to_add.clone());
+    players.extend(joining);
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+
+    let effects = GuildFillEffects {
+        ctx,
+        client: &client,
+        db,
+        guild_id,
+        guild_channel: &guild_channel,
+    };
+    let mut compensation = FillCompensation::default();
+    if let Err(err) = start_filled_pug(
+        &effects,
+        &game_mode,
+        &players,
+        party_size_limit,
+        &mut compensation,
+    )
+    .await
+    {
+        error!(
+            "Failed to start a pug for {} after its queue filled, undoing what was done so far: {:?}",
+            game_mode.label, err
+        );
+        compensation.run(&effects).await;
+        queue_board::refresh(ctx, guild_id).await;
+        return Err(err.context(format!(
+            "The {} queue filled, but its pug could not be started",
+            game_mode.label
+        )));
+    }
+
+    crate::utils::metrics::pug_formed(ctx, guild_id, &game_mode.label).await;
+    queue_board::refresh(ctx, guild_id).await;
+
+    if let ReplyStyle::Compact = reply_style {
+        // the players have already been mentioned in the announcement of the pug
+        return Ok(format!("**{}** filled", game_mode.label));
+    }
+
+    // TODO: announce participants' removal from queues
+    // let mut announcement = MessageBuilder::default();
+
+    Ok(
+        "If any of the following users were in the queue of any other game mode, \
+    you have been removed"
+            .to_string(),
+    )
+}
+
+/// Changes made while starting a pug for a filled queue.
+///
+/// If a step fails, [`FillCompensation::run`] undoes the changes recorded so far, so players
+/// are not left removed from queues without a pug, or a pug left without its thread and commands.
+#[derive(Default)]
+struct FillCompensation {
+    /// The channel and id of the message announcing the filled queue
+    announcement: Option<(ChannelId, MessageId)>,
+    thread: Option<ChannelId>,
+    /// Join records removed when the pug was saved
+    removed_joins: Option<Vec<GameModeJoin>>,
+    /// Whether commands were synced for the pug being picked
+    commands_changed: bool,
+    /// Voice channels (and their category) created for a completed pug
+    voice_channels: Vec<ChannelId>,
+}
+
+/// What starting a pug for a filled queue does in Discord and the guild's database, and how
+/// [`FillCompensation::run`] undoes it.
+#[async_trait]
+trait FillEffects {
+    async fn announce(&self, content: String) -> anyhow::Result<(ChannelId, MessageId)>;
+    async fn create_thread(&self, name: String) -> anyhow::Result<ChannelId>;
+    async fn say_in_thread(&self, thread: ChannelId, content: String) -> anyhow::Result<()>;
+    async fn parties(&self) -> anyhow::Result<Vec<Party>>;
+    /// The name of the active season, if there is one
+    async fn active_season(&self) -> anyhow::Result<Option<String>>;
+    /// Save a pug and remove its players from all queues, returning the removed joins.
+    async fn register_pug(&self, pug: &Pug) -> anyhow::Result<Vec<GameModeJoin>>;
+    /// Create and record the voice channels of a completed pug. Each channel is added to
+    /// `created_channels` as soon as it is created.
+    async fn set_up_voice_channels(
+        &self,
+        pug: Pug,
+        created_channels: &mut Vec<ChannelId>,
+    ) -> anyhow::Result<Pug>;
+    async fn sync_commands(&self) -> anyhow::Result<()>;
+    /// Follow up on a started pug, e.g. with a map vote. Nothing here is undone.
+    async fn started(&self, pug: &Pug);
 
+    /// Delete the pug of a thread, and put back the queue joins removed when it was saved.
+    async fn unregister_pug(
+        &self,
+        thread_channel_id: i64,
+        removed_joins: &[GameModeJoin],
+    ) -> anyhow::Result<()>;
+    async fn delete_channel(&self, channel: ChannelId) -> anyhow::Result<()>;
+    async fn delete_message(&self, channel: ChannelId, message: MessageId) -> anyhow::Result<()>;
+}
+
+/// Starts pugs in the pug channel of a guild.
+struct GuildFillEffects<'a> {
+    ctx: &'a Context,
+    client: &'a Client,
+    db: Database,
+    guild_id: GuildId,
+    guild_channel: &'a GuildChannel,
+}
+
+#[async_trait]
+impl FillEffects for GuildFillEffects<'_> {
+    async fn announce(&self, content: String) -> anyhow::Result<(ChannelId, MessageId)> {
+        let message = self.guild_channel.say(&self.ctx.http, content).await?;
+        Ok((message.channel_id, message.id))
+    }
+
+    async fn create_thread(&self, name: String) -> anyhow::Result<ChannelId> {
+        let thread = self
+            .guild_channel
+            .create_thread(
+                &self.ctx.http,
+                CreateThread::new(name)
+                    .kind(ChannelType::PublicThread)
+                    .auto_archive_duration(serenity::all::AutoArchiveDuration::OneHour),
+            )
+            .await?;
+        // only cosmetic, while the pug is being set up
+        let _ = thread.id.broadcast_typing(&self.ctx.http).await;
+        Ok(thread.id)
+    }
+
+    async fn say_in_thread(&self, thread: ChannelId, content: String) -> anyhow::Result<()> {
+        thread.say(&self.ctx.http, content).await?;
+        Ok(())
+    }
+
+    async fn parties(&self) -> anyhow::Result<Vec<Party>> {
+        Ok(db::read::get_parties(self.db.clone()).await?)
+    }
+
+    async fn active_season(&self) -> anyhow::Result<Option<String>> {
+        Ok(db::read::get_active_season(self.db.clone())
+            .await?
+            .map(|season| season.name))
+    }
+
+    async fn register_pug(&self, pug: &Pug) -> anyhow::Result<Vec<GameModeJoin>> {
+        Ok(register_filled_pug(self.client, self.db.clone(), pug).await?)
+    }
+
+    async fn set_up_voice_channels(
+        &self,
+        pug: Pug,
+        created_channels: &mut Vec<ChannelId>,
+    ) -> anyhow::Result<Pug> {
+        transform::set_up_completed_pug(self.ctx, self.db.clone(), pug, created_channels).await
+    }
+
+    async fn sync_commands(&self) -> anyhow::Result<()> {
+        application_commands::sync(self.ctx, self.guild_id, self.db.clone()).await?;
+        Ok(())
+    }
+
+    async fn started(&self, pug: &Pug) {
+        match pug.state {
+            // the pug has started either way, so it is not undone along with the vote
+            PugState::Completed => {
+                if let Err(err) = map_vote::start(self.ctx, self.db.clone(), pug).await {
+                    error!(
+                        "Failed to start the map vote of pug {}: {:?}",
+                        pug.thread_channel_id, err
+                    );
+                }
+            }
+            // a timer which will auto pick captains if necessary
+            _ => {
+                tokio::spawn(captain::autopick_countdown(
+                    self.ctx.clone(),
+                    self.db.clone(),
+                    ChannelId::from(pug.thread_channel_id as u64),
+                    self.guild_id,
+                ));
+            }
+        }
+    }
+
+    async fn unregister_pug(
+        &self,
+        thread_channel_id: i64,
+        removed_joins: &[GameModeJoin],
+    ) -> anyhow::Result<()> {
+        unregister_filled_pug(
+            self.client,
+            self.db.clone(),
+            thread_channel_id,
+            removed_joins,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_channel(&self, channel: ChannelId) -> anyhow::Result<()> {
+        channel.delete(&self.ctx.http).await?;
+        Ok(())
+    }
+
+    async fn delete_message(&self, channel: ChannelId, message: MessageId) -> anyhow::Result<()> {
+        channel.delete_message(&self.ctx.http, message).await?;
+        Ok(())
+    }
+}
+
+impl FillCompensation {
+    /// Undo the recorded changes. This is best-effort: failures are logged and the
+    /// remaining changes are still undone.
+    async fn run(self, effects: &impl FillEffects) {
+        if let (Some(removed_joins), Some(thread)) = (&self.removed_joins, self.thread) {
+            if let Err(err) = effects
+                .unregister_pug(thread.get() as i64, removed_joins)
+                .await
+            {
+                error!(
+                    "Failed to delete pug and restore {} queue joins: {:?}",
+                    removed_joins.len(),
+                    err
+                );
+            }
+        }
+        // once the pug is deleted, syncing removes the commands for picking it
+        if self.commands_changed {
+            if let Err(err) = effects.sync_commands().await {
+                error!("Failed to sync commands: {:?}", err);
+            }
+        }
+
+        // channels before the category they are in
+        for channel in self.voice_channels.into_iter().rev() {
+            if let Err(err) = effects.delete_channel(channel).await {
+                error!("Failed to delete voice channel {}: {:?}", channel, err);
+            }
+        }
+        if let Some(thread) = self.thread {
+            if let Err(err) = effects.delete_channel(thread).await {
+                error!("Failed to delete pug thread {}: {:?}", thread, err);
+            }
+        }
+        if let Some((channel, message)) = self.announcement {
+            if let Err(err) = effects.delete_message(channel, message).await {
+                error!("Failed to delete pug announcement: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Announce a filled queue and start its pug: create a thread for it, save it (removing its
+/// players from all queues), then set up either the completed two player pug or the
+/// commands for picking.
+///
+/// Each change is recorded in `compensation` as soon as it is made.
+async fn start_filled_pug(
+    effects: &impl FillEffects,
+    game_mode: &GameMode,
+    players: &[u64],
+    party_size_limit: usize,
+    compensation: &mut FillCompensation,
+) -> anyhow::Result<()> {
     let mut announcement = MessageBuilder::default();
     announcement
-        .push_bold(&game_mode.label)
+        .push_bold(game_mode.display_name())
         .push_line(" filled!");
     for player in players.iter() {
         announcement.mention(&UserId::from(*player)).push(" ");
     }
 
-    let m = guild_channel.say(&ctx.http, announcement.build()).await?;
+    compensation.announcement = Some(
+        effects
+            .announce(announcement.build())
+            .await
+            .context("Failed to announce a filled pug")?,
+    );
 
     let now = Utc::now();
-    let pug_thread = guild_channel
-        .create_thread(
-            &ctx.http,
-            CreateThread::new(format!(
-                "{} | {}-{}-{}",
-                &game_mode.label,
-                now.year(),
-                now.month(),
-                now.day()
-            ))
-            .kind(ChannelType::PublicThread)
-            .auto_archive_duration(serenity::all::AutoArchiveDuration::OneHour),
-        )
-        .await?;
-
-    let _working_in_thread = pug_thread.clone().start_typing(&ctx.http);
+    let pug_thread = effects
+        .create_thread(format!(
+            "{} | {}-{}-{}",
+            &game_mode.label,
+            now.year(),
+            now.month(),
+            now.day()
+        ))
+        .await
+        .context("Failed to create a thread for a filled pug")?;
+    compensation.thread = Some(pug_thread);
 
     // generate a pick sequence
     let pick_sequence = crate::utils::pick_sequence::generate(&(game_mode.player_count as u64));
 
-    // remove participants from all queues
-    db::write::remove_players_from_all_queues(db.clone(), &players)
+    let mut pug = Pug::new(
+        game_mode.label.clone(),
+        pug_thread.get() as i64,
+        players.iter().map(|user_id| *user_id as i64).collect(),
+        pick_sequence,
+    );
+    let parties = effects
+        .parties()
         .await
-        .context("A pug filled and the db request to remove participants from all queues failed")?;
+        .context("Failed to read the parties of a filled pug's players")?;
+    pug.parties = party::parties_among(&parties, &pug.players, party_size_limit);
 
     if game_mode.player_count == 2 {
         // two-player game modes do not undergo a picking process,
-        // so we simply register a completed pug:
-
-        let autocompleted_picking_session = PickingSession {
-            created: now,
-            game_mode: game_mode.label.clone(),
-            thread_channel_id: pug_thread.id.get() as i64,
-            pick_sequence,
-            last_reset: None,
-        };
+        // so the pug is completed right away:
 
         // players assigned to random team,
         // with empty team lists
@@ -221,25 +563,33 @@ pub async fn join_helper(
             false => (players.first(), players.last()),
         };
 
-        let completed_pug = transform::resolve_to_completed_pug(
-            &ctx,
-            db.clone(),
-            autocompleted_picking_session,
-            *first_random_player.unwrap(),
-            vec![],
-            *remaining_player.unwrap(),
-            vec![],
-        )
-        .await
-        .context(
-            "Failed to auto promote 2 player game mode to completed pug \
-            status after bypassing picking session",
-        )?;
+        let active_season = effects
+            .active_season()
+            .await
+            .context("Failed to check for an active season to tag the completed pug with")?;
+
+        pug.state = PugState::Completed;
+        pug.blue_team_captain = first_random_player.map(|user_id| *user_id as i64);
+        pug.red_team_captain = remaining_player.map(|user_id| *user_id as i64);
+        pug.season = active_season;
+
+        // save the pug and remove participants from all queues
+        compensation.removed_joins = Some(
+            effects
+                .register_pug(&pug)
+                .await
+                .context("Failed to save a completed two player pug")?,
+        );
+
+        let completed_pug = effects
+            .set_up_voice_channels(pug, &mut compensation.voice_channels)
+            .await
+            .context("Failed to set up voice channels for a completed two player pug")?;
 
         // Unwrapping like this is probably fine because it comes from a String
         // (which came from a proper u64) that has not been moved about or tampered with.
-        let red_player = UserId::from(completed_pug.red_team_captain as u64);
-        let blue_player = UserId::from(completed_pug.blue_team_captain as u64);
+        let red_player = UserId::from(completed_pug.red_team_captain.unwrap() as u64);
+        let blue_player = UserId::from(completed_pug.blue_team_captain.unwrap() as u64);
 
         // then announce auto-picked team colors in pug thread
         let response = MessageBuilder::new()
@@ -251,55 +601,30 @@ pub async fn join_helper(
             .mention(&blue_player)
             .build();
 
-        pug_thread.say(&ctx.http, response).await?;
-    } else {
-        // write picking session with these players in it
-        register_picking_session(
-            db.clone(),
-            &pug_thread.id.get(),
-            &game_mode.label,
-            &players,
-            pick_sequence,
-        )
-        .await?;
+        effects
+            .say_in_thread(pug_thread, response)
+            .await
+            .context("Failed to announce the teams of a completed two player pug")?;
 
-        // create commands (and register in db): /captain /autocaptain /nocapt /reset
+        effects.started(&completed_pug).await;
+    } else {
+        // save the pug with these players in it, to be picked,
+        // and remove participants from all queues
+        compensation.removed_joins = Some(effects.register_pug(&pug).await.context(
+            "A pug filled and saving it while removing participants from all queues failed",
+        )?);
 
-        // TODO: perhaps it's a good idea to manually handle the error case here
-        // i.e. attempt to delete any commands created so far?
-        let autocaptain_cmd = guild_id
-            .create_command(&ctx.http, build_autocaptain())
-            .await?;
-        let captain_cmd = guild_id.create_command(&ctx.http, build_captain()).await?;
-        let nocaptain_cmd = guild_id
-            .create_command(&ctx.http, build_nocaptain())
-            .await?;
-        let reset_cmd = guild_id.create_command(&ctx.http, build_reset()).await?;
+        // sync commands for the pug being picked: /captain /autocaptain /nocaptain /reset
+        compensation.commands_changed = true;
+        effects
+            .sync_commands()
+            .await
+            .context("Failed to sync commands for captain selection")?;
 
-        save_guild_commands(
-            db.clone(),
-            vec![autocaptain_cmd, captain_cmd, nocaptain_cmd, reset_cmd],
-        )
-        .await?;
-
-        // spawn a timer which will auto pick captains if necessary
-        let ctx_clone = ctx.clone();
-        tokio::spawn(captain::autopick_countdown(
-            ctx_clone,
-            db.clone(),
-            pug_thread.id,
-            guild_id,
-        ));
+        effects.started(&pug).await;
     }
 
-    // TODO: announce participants' removal from queues
-    // let mut announcement = MessageBuilder::default();
-
-    return Ok(
-        "If any of the following users were in the queue of any other game mode, \
-    you have been removed"
-            .to_string(),
-    );
+    Ok(())
 }
 
 /// Remove user from game queue. Currently, this will NOT cancel a picking session if
@@ -346,7 +671,7 @@ pub async fn leave(ctx: &Context, interaction: &CommandInteraction) -> anyhow::R
     };
 
     super::queue::leave_helper(
-        &ctx,
+        ctx,
         guild_id,
         guild_channel,
         db,
@@ -358,7 +683,7 @@ pub async fn leave(ctx: &Context, interaction: &CommandInteraction) -> anyhow::R
 
 pub async fn leave_helper(
     ctx: &Context,
-    _guild_id: GuildId,
+    guild_id: GuildId,
     _guild_channel: GuildChannel,
     db: Database,
     target_game_modes: IntendedGameMode,
@@ -371,27 +696,119 @@ pub async fn leave_helper(
     // - return everyone except the specified user to the game mode's queue, also removing
     // (and informing) those who were in the queue
 
+    let name_of_user = match UserId::from(user_to_remove).to_user_cached(&ctx.cache) {
+        Some(user) => user.name.clone(),
+        None => "User".to_string(),
+    };
+
     let game_mode_label = match target_game_modes {
-        IntendedGameMode::Single(desired_game_mode) => desired_game_mode,
+        // the label may have been given as an alias
+        IntendedGameMode::Single(desired_game_mode) => {
+            match find_game_mode(db.clone(), &desired_game_mode).await? {
+                Some(game_mode) => game_mode.label,
+                None => return Ok("No game mode found with this name".to_string()),
+            }
+        }
         IntendedGameMode::All => {
-            return Ok("The ability to leave multiple queues at once is not yet ready".to_string());
+            let _queue_lock = queue_lock::lock(ctx, guild_id).await;
+            let removed_join_records = db::write::remove_player_from_all_queues(db, user_to_remove)
+                .await
+                .context(format!(
+                    "Failed to remove user {} from all queues",
+                    user_to_remove
+                ))?;
+            if removed_join_records.is_empty() {
+                return Ok(format!("{} is not in any queue", name_of_user));
+            }
+            queue_board::refresh(ctx, guild_id).await;
+            return Ok(format!(
+                "{} removed from {}",
+                name_of_user,
+                removed_join_records
+                    .iter()
+                    .map(|join_record| join_record.game_mode_label.as_str())
+                    .join(", ")
+            ));
         }
     };
 
-    let name_of_user = match UserId::from(user_to_remove).to_user_cached(&ctx.cache) {
-        Some(user) => user.name.clone(),
-        None => "User".to_string(),
-    };
+    let _queue_lock = queue_lock::lock(ctx, guild_id).await;
     match db::write::remove_player_from_game_mode_queue(db, game_mode_label, user_to_remove).await?
     {
-        Some(removed_join_record) => Ok(format!(
-            "{} removed from {}",
-            name_of_user, removed_join_record.game_mode_label
-        )),
+        Some(removed_join_record) => {
+            queue_board::refresh(ctx, guild_id).await;
+            Ok(format!(
+                "{} removed from {}",
+                name_of_user, removed_join_record.game_mode_label
+            ))
+        }
         None => Ok(format!("{} is not in the queue", name_of_user)),
     }
 }
 
+/// Join or leave a queue with a button on the queue board, replying only to the user who
+/// pressed it.
+pub async fn board_button(ctx: &Context, interaction: &ComponentInteraction) -> anyhow::Result<()> {
+    let guild_id = interaction
+        .guild_id
+        .context("Queue board buttons are only used in guilds")?;
+    let (action, game_mode_label) = queue_board::from_custom_id(&interaction.data.custom_id)
+        .context(format!(
+            "Malformed queue board button custom id: {}",
+            interaction.data.custom_id
+        ))?;
+
+    // joining may start a pug, which takes longer than Discord waits for a response
+    interaction
+        .defer_ephemeral(&ctx.http)
+        .await
+        .context("Failed to acknowledge a queue board button")?;
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let guild_channel = match interaction
+        .channel_id
+        .to_channel(&ctx)
+        .await
+        .context("Tried to obtain `Channel` from a ChannelId")?
+    {
+        Channel::Guild(channel) => channel,
+        _ => anyhow::bail!("Queue board buttons are only used in guild channels"),
+    };
+
+    let target = IntendedGameMode::Single(game_mode_label);
+    let user_id = interaction.user.id.get();
+    let response = match action {
+        queue_board::BoardAction::Join => {
+            join_helper(
+                ctx,
+                guild_id,
+                guild_channel,
+                db,
+                target,
+                user_id,
+                ReplyStyle::Compact,
+            )
+            .await?
+        }
+        queue_board::BoardAction::Leave => {
+            leave_helper(ctx, guild_id, guild_channel, db, target, user_id).await?
+        }
+    };
+
+    interaction
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(response))
+        .await
+        .context("Failed to reply to a queue board button")?;
+    Ok(())
+}
+
 /// Show available game modes and queued players.
 pub async fn list(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
     // TODO: ensure guild channel
@@ -405,49 +822,348 @@ pub async fn list(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Re
     };
     let db = client.database(&guild_id.to_string());
 
-    let mut queues: HashMap<GameMode, Vec<GameModeJoin>> = db::read::get_all_queues(db.clone())
+    let queues = db::read::get_all_queues(db.clone())
         .await
         .context("Tried to get all queues for listing")?;
+    let parties = db::read::get_parties(db.clone())
+        .await
+        .context("Tried to get parties for listing")?
+        .into_iter()
+        .map(|party| party.members)
+        .collect::<Vec<Vec<i64>>>();
 
-    let mut response = MessageBuilder::default();
-
-    for (game_mode, game_mode_queue) in queues.drain() {
-        if game_mode_queue.is_empty() {
+    let mut queue_views = Vec::default();
+    for (game_mode, game_mode_queue) in queues.into_iter() {
+        if game_mode_queue.is_empty() || game_mode.disabled {
             // Don't clutter the output by listing empty queues
             continue;
         }
-        let mut participant_data = Vec::default();
-        for join_record in game_mode_queue.iter() {
-            participant_data.push(transform::join_record_to_player_info(&ctx, &join_record).await?);
+        queue_views.push(QueueView::load(ctx, game_mode, &game_mode_queue, &parties).await?);
+    }
+
+    if queue_views.is_empty() {
+        return Ok("All pug queues are empty".to_string());
+    }
+    queue_views.sort_by(|a, b| a.game_mode.label.cmp(&b.game_mode.label));
+    presentation::respond(ctx, interaction, presentation::queues(&queue_views)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    const PUG_CHANNEL: u64 = 10;
+
+    /// The steps of `start_filled_pug` which can fail, in the order they are taken
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Step {
+        Announce,
+        CreateThread,
+        Parties,
+        ActiveSeason,
+        RegisterPug,
+        /// Setting up voice channels, after the given number of them were created
+        VoiceChannels(usize),
+        SayInThread,
+        SyncCommands,
+    }
+
+    const TWO_PLAYER_STEPS: [Step; 9] = [
+        Step::Announce,
+        Step::CreateThread,
+        Step::Parties,
+        Step::ActiveSeason,
+        Step::RegisterPug,
+        Step::VoiceChannels(0),
+        Step::VoiceChannels(1),
+        Step::VoiceChannels(2),
+        Step::SayInThread,
+    ];
+
+    const PICKING_STEPS: [Step; 5] = [
+        Step::Announce,
+        Step::CreateThread,
+        Step::Parties,
+        Step::RegisterPug,
+        Step::SyncCommands,
+    ];
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    struct Guild {
+        /// (game mode label, player) of each queue join
+        queues: Vec<(String, i64)>,
+        pugs: Vec<i64>,
+        channels: Vec<u64>,
+        messages: Vec<(u64, u64)>,
+        /// Whether commands for picking a pug are registered
+        picking_commands: bool,
+        started: bool,
+    }
+
+    /// A guild kept in memory, failing at one step of starting a pug.
+    struct FakeGuild {
+        guild: Mutex<Guild>,
+        next_id: Mutex<u64>,
+        fail_at: Option<Step>,
+    }
+
+    impl FakeGuild {
+        fn new(guild: Guild, fail_at: Option<Step>) -> Self {
+            FakeGuild {
+                guild: Mutex::new(guild),
+                next_id: Mutex::new(100),
+                fail_at,
+            }
+        }
+
+        fn take(&self, step: Step) -> anyhow::Result<()> {
+            match self.fail_at == Some(step) {
+                true => anyhow::bail!("{:?} failed on purpose", step),
+                false => Ok(()),
+            }
+        }
+
+        fn create_channel(&self) -> u64 {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            self.guild.lock().unwrap().channels.push(*next_id);
+            *next_id
+        }
+
+        fn guild(&self) -> Guild {
+            self.guild.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl FillEffects for FakeGuild {
+        async fn announce(&self, _content: String) -> anyhow::Result<(ChannelId, MessageId)> {
+            self.take(Step::Announce)?;
+            let message = {
+                let mut next_id = self.next_id.lock().unwrap();
+                *next_id += 1;
+                *next_id
+            };
+            self.guild
+                .lock()
+                .unwrap()
+                .messages
+                .push((PUG_CHANNEL, message));
+            Ok((ChannelId::new(PUG_CHANNEL), MessageId::new(message)))
+        }
+
+        async fn create_thread(&self, _name: String) -> anyhow::Result<ChannelId> {
+            self.take(Step::CreateThread)?;
+            Ok(ChannelId::new(self.create_channel()))
+        }
+
+        async fn say_in_thread(&self, _thread: ChannelId, _content: String) -> anyhow::Result<()> {
+            self.take(Step::SayInThread)
+        }
+
+        async fn parties(&self) -> anyhow::Result<Vec<Party>> {
+            self.take(Step::Parties)?;
+            Ok(Vec::new())
+        }
+
+        async fn active_season(&self) -> anyhow::Result<Option<String>> {
+            self.take(Step::ActiveSeason)?;
+            Ok(None)
+        }
+
+        async fn register_pug(&self, pug: &Pug) -> anyhow::Result<Vec<GameModeJoin>> {
+            self.take(Step::RegisterPug)?;
+            let mut guild = self.guild.lock().unwrap();
+            guild.pugs.push(pug.thread_channel_id);
+            let (removed, kept) = guild
+                .queues
+                .drain(..)
+                .partition::<Vec<_>, _>(|(_, player)| pug.players.contains(player));
+            guild.queues = kept;
+            Ok(removed
+                .into_iter()
+                .map(|(game_mode_label, player_user_id)| GameModeJoin {
+                    game_mode_label,
+                    player_user_id,
+                    joined: Utc::now(),
+                })
+                .collect())
+        }
+
+        async fn set_up_voice_channels(
+            &self,
+            pug: Pug,
+            created_channels: &mut Vec<ChannelId>,
+        ) -> anyhow::Result<Pug> {
+            // a category, then a channel for each team
+            for created in 0..3 {
+                self.take(Step::VoiceChannels(created))?;
+                created_channels.push(ChannelId::new(self.create_channel()));
+            }
+            Ok(pug)
+        }
+
+        async fn sync_commands(&self) -> anyhow::Result<()> {
+            self.take(Step::SyncCommands)?;
+            let mut guild = self.guild.lock().unwrap();
+            guild.picking_commands = !guild.pugs.is_empty();
+            Ok(())
+        }
+
+        async fn started(&self, _pug: &Pug) {
+            self.guild.lock().unwrap().started = true;
         }
 
-        let formatted_names = participant_data
+        async fn unregister_pug(
+            &self,
+            thread_channel_id: i64,
+            removed_joins: &[GameModeJoin],
+        ) -> anyhow::Result<()> {
+            let mut guild = self.guild.lock().unwrap();
+            guild.pugs.retain(|pug| *pug != thread_channel_id);
+            guild.queues.extend(
+                removed_joins
+                    .iter()
+                    .map(|join| (join.game_mode_label.clone(), join.player_user_id)),
+            );
+            Ok(())
+        }
+
+        async fn delete_channel(&self, channel: ChannelId) -> anyhow::Result<()> {
+            let mut guild = self.guild.lock().unwrap();
+            let count = guild.channels.len();
+            guild.channels.retain(|id| *id != channel.get());
+            anyhow::ensure!(guild.channels.len() < count, "no channel {}", channel);
+            Ok(())
+        }
+
+        async fn delete_message(
+            &self,
+            channel: ChannelId,
+            message: MessageId,
+        ) -> anyhow::Result<()> {
+            let mut guild = self.guild.lock().unwrap();
+            let count = guild.messages.len();
+            guild
+                .messages
+                .retain(|id| *id != (channel.get(), message.get()));
+            anyhow::ensure!(guild.messages.len() < count, "no message {}", message);
+            Ok(())
+        }
+    }
+
+    fn game_mode(label: &str, player_count: i64) -> GameMode {
+        GameMode {
+            label: label.to_string(),
+            player_count,
+            ..GameMode::default()
+        }
+    }
+
+    /// A guild where the queue of `game_mode` just filled. The first player is also in another queue.
+    fn filled(game_mode: &GameMode) -> (Guild, Vec<u64>) {
+        let players = (1..=game_mode.player_count as u64).collect::<Vec<u64>>();
+        let mut queues = players
             .iter()
-            .format_with(" :small_blue_diamond: ", |player_info, f| f(player_info));
+            .map(|player| (game_mode.label.clone(), *player as i64))
+            .collect::<Vec<_>>();
+        queues.push(("other".to_string(), 1));
+        queues.push(("other".to_string(), 99));
+        let guild = Guild {
+            queues,
+            ..Guild::default()
+        };
+        (guild, players)
+    }
 
-        response.push_line(format!(
-            "**{}** ({}/{}): {}",
-            game_mode.label,
-            game_mode_queue.len(),
-            game_mode.player_count,
-            formatted_names
-        ));
+    fn sorted(mut guild: Guild) -> Guild {
+        guild.queues.sort();
+        guild
     }
 
-    let response_text: String = response.build();
-    if response_text.is_empty() {
-        Ok("All pug queues are empty".to_string())
-    } else {
-        Ok(response_text)
+    async fn fail_each_step(game_mode: &GameMode, steps: &[Step]) {
+        let (before, players) = filled(game_mode);
+        for step in steps {
+            let guild = FakeGuild::new(before.clone(), Some(*step));
+            let mut compensation = FillCompensation::default();
+            let result = start_filled_pug(&guild, game_mode, &players, 5, &mut compensation).await;
+            assert!(result.is_err(), "{:?} did not fail", step);
+
+            compensation.run(&guild).await;
+            assert_eq!(
+                sorted(guild.guild()),
+                sorted(before.clone()),
+                "failing at {:?} was not undone",
+                step
+            );
+        }
     }
-}
 
-// if `verbose` argument is true, this player output text
-// contains names in addition to player counts
-async fn list_helper(
-    ctx: &Context,
-    queues: &HashMap<String, Vec<GameModeJoin>>,
-    verbose: bool,
-) -> String {
-    "".to_string()
+    #[tokio::test]
+    async fn failing_any_step_of_a_two_player_pug_is_undone() {
+        fail_each_step(&game_mode("duel", 2), &TWO_PLAYER_STEPS).await;
+    }
+
+    #[tokio::test]
+    async fn failing_any_step_of_a_pug_to_be_picked_is_undone() {
+        fail_each_step(&game_mode("ctf", 10), &PICKING_STEPS).await;
+    }
+
+    #[tokio::test]
+    async fn a_two_player_pug_starts_with_its_voice_channels() {
+        let duel = game_mode("duel", 2);
+        let (before, players) = filled(&duel);
+        let guild = FakeGuild::new(before, None);
+        let mut compensation = FillCompensation::default();
+        start_filled_pug(&guild, &duel, &players, 5, &mut compensation)
+            .await
+            .unwrap();
+
+        let after = guild.guild();
+        assert_eq!(after.queues, vec![("other".to_string(), 99)]);
+        assert_eq!(after.pugs.len(), 1);
+        // thread, category and two team channels
+        assert_eq!(after.channels.len(), 4);
+        assert_eq!(compensation.voice_channels.len(), 3);
+        assert!(!after.picking_commands);
+        assert!(after.started);
+    }
+
+    #[tokio::test]
+    async fn a_pug_to_be_picked_starts_with_commands_for_picking() {
+        let ctf = game_mode("ctf", 10);
+        let (before, players) = filled(&ctf);
+        let guild = FakeGuild::new(before, None);
+        let mut compensation = FillCompensation::default();
+        start_filled_pug(&guild, &ctf, &players, 5, &mut compensation)
+            .await
+            .unwrap();
+
+        let after = guild.guild();
+        assert_eq!(after.queues, vec![("other".to_string(), 99)]);
+        assert_eq!(after.pugs.len(), 1);
+        assert_eq!(after.channels.len(), 1);
+        assert!(after.picking_commands);
+        assert!(after.started);
+    }
+
+    #[tokio::test]
+    async fn a_failed_undo_step_does_not_stop_the_rest() {
+        let (before, _) = filled(&game_mode("ctf", 10));
+        let guild = FakeGuild::new(before.clone(), None);
+        let (channel, message) = guild.announce(String::new()).await.unwrap();
+        let thread = guild.create_thread(String::new()).await.unwrap();
+        let compensation = FillCompensation {
+            announcement: Some((channel, message)),
+            thread: Some(thread),
+            // already deleted, so deleting it fails
+            voice_channels: vec![ChannelId::new(1)],
+            ..FillCompensation::default()
+        };
+
+        compensation.run(&guild).await;
+        assert_eq!(guild.guild(), before);
+    }
 }
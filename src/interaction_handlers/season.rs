@@ -0,0 +1,147 @@
+use anyhow::Context as AnyhowContext;
+use mongodb::{Client, Database};
+use serenity::client::Context;
+use serenity::model::application::{CommandDataOptionValue, CommandInteraction};
+use serenity::model::id::UserId;
+use serenity::utils::MessageBuilder;
+
+use crate::db;
+use crate::db::model::SeasonStanding;
+use crate::utils::leaderboard::{rank, LeaderboardMetric};
+use crate::DbClientRef;
+
+/// Command handler for /season, which dispatches to its `start` and `end` subcommands.
+pub async fn manage(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let subcommand = interaction
+        .data
+        .options
+        .first()
+        .context("The /season command was used without a subcommand")?;
+
+    let sub_options = match &subcommand.value {
+        CommandDataOptionValue::SubCommand(sub_options) => sub_options,
+        _ => anyhow::bail!("The first option of /season is expected to be a subcommand"),
+    };
+
+    match subcommand.name.as_str() {
+        "start" => {
+            let name = sub_options
+                .iter()
+                .find(|option| option.name.eq("name"))
+                .context("The `name` option is missing")?
+                .value
+                .as_str()
+                .context("Somehow, the value of the `name` option is not a string")?
+                .trim()
+                .to_string();
+            start(db, name).await
+        }
+        "end" => end(ctx, &client, db).await,
+        other => anyhow::bail!("Unrecognized /season subcommand: {}", other),
+    }
+}
+
+async fn start(db: Database, name: String) -> anyhow::Result<String> {
+    // The separator is reserved for the custom ids of leaderboard buttons
+    if name.is_empty() || name.contains('|') {
+        return Ok("Season names cannot be empty or contain `|`".to_string());
+    }
+
+    if let Some(active_season) = db::read::get_active_season(db.clone()).await? {
+        return Ok(format!(
+            "Season **{}** is still in progress. End it before starting a new one.",
+            active_season.name
+        ));
+    }
+
+    if db::read::find_season(db.clone(), &name).await?.is_some() {
+        return Ok(format!("A season called **{}** already exists", name));
+    }
+
+    db::write::start_season(db, name.clone())
+        .await
+        .context("Failed to save new season")?;
+
+    Ok(format!(
+        "Season **{}** has started. Pugs completed from now on count towards it.",
+        name
+    ))
+}
+
+/// End the active season, archiving the final standings of every game mode.
+async fn end(ctx: &Context, client: &Client, db: Database) -> anyhow::Result<String> {
+    let season = match db::read::get_active_season(db.clone()).await? {
+        Some(season) => season,
+        None => return Ok("There is no season in progress".to_string()),
+    };
+
+    let game_modes = db::read::get_game_modes(db.clone()).await?;
+
+    let mut response = MessageBuilder::default();
+    response.push_line(format!("Season **{}** has ended", season.name));
+
+    let mut standings = Vec::default();
+    for game_mode in game_modes {
+        let entries = db::read::get_leaderboard_entries(
+            db.clone(),
+            &game_mode.label,
+            Some(&season.name),
+            None,
+            None,
+        )
+        .await
+        .context(format!(
+            "Failed to aggregate final standings of {} for season {}",
+            game_mode.label, season.name
+        ))?;
+
+        let ranked_entries = rank(entries, LeaderboardMetric::GamesPlayed);
+        if let Some(top_entry) = ranked_entries.first() {
+            let name = match UserId::from(top_entry.user_id as u64).to_user(ctx).await {
+                Ok(user) => user.name,
+                Err(_) => format!("Unknown user ({})", top_entry.user_id),
+            };
+            response.push_line(format!(
+                "**{}**: most games played by {} ({})",
+                game_mode.label, name, top_entry.games_played
+            ));
+        }
+
+        standings.extend(
+            ranked_entries
+                .into_iter()
+                .enumerate()
+                .map(|(position, entry)| SeasonStanding {
+                    season: season.name.clone(),
+                    game_mode: game_mode.label.clone(),
+                    rank: position as i64 + 1,
+                    user_id: entry.user_id,
+                    games_played: entry.games_played,
+                    games_decided: entry.games_decided,
+                    wins: entry.wins,
+                    times_captained: entry.times_captained,
+                }),
+        );
+    }
+
+    // the season ends only if its standings are archived, so /season end can be retried
+    let ended = db::write::end_season(client, db, standings)
+        .await
+        .context("Failed to end the active season and archive its final standings")?;
+    if ended.is_none() {
+        // ended by someone else while the standings were being ranked
+        return Ok("There is no season in progress".to_string());
+    }
+
+    Ok(response.build())
+}
@@ -0,0 +1,131 @@
+use anyhow::Context as AnyhowContext;
+use itertools::Itertools;
+use serenity::client::Context;
+use serenity::model::application::CommandInteraction;
+use serenity::model::id::UserId;
+use serenity::utils::MessageBuilder;
+
+use crate::db;
+use crate::db::model::PlayerFrequency;
+use crate::utils::time::{Accuracy, HumanTime, Tense};
+use crate::DbClientRef;
+
+/// Show participation statistics for a player.
+///
+/// Accepts optional fields `user` (defaults to the caller), `game_mode` and `season`.
+pub async fn player_stats(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+) -> anyhow::Result<String> {
+    let guild_id = interaction.guild_id.unwrap();
+
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let target_user = match interaction.data.resolved.users.values().next() {
+        Some(user) => user.clone(),
+        None => interaction.user.clone(),
+    };
+
+    // the game mode may have been given as an alias
+    let game_mode_label = match interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("game_mode"))
+        .and_then(|option| option.value.as_str())
+    {
+        Some(name) => match db::read::find_game_mode(db.clone(), name).await? {
+            Some(game_mode) => Some(game_mode.label),
+            None => return Ok("No game mode found with this name".to_string()),
+        },
+        None => None,
+    };
+    let game_mode_label = game_mode_label.as_deref();
+
+    let season = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name.eq("season"))
+        .and_then(|option| option.value.as_str());
+
+    let user_id = target_user.id.get();
+    let stats = db::read::get_player_stats(db.clone(), &user_id, game_mode_label, season)
+        .await
+        .context("Failed to aggregate player statistics from completed pugs")?;
+    let pick_positions = db::read::get_pick_position_stats(db, &user_id, game_mode_label, season)
+        .await
+        .context("Failed to aggregate pick positions of player")?;
+
+    let mut response = MessageBuilder::default();
+    response.push_bold(&target_user.name);
+    let filters = [game_mode_label, season]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<&str>>();
+    if !filters.is_empty() {
+        response.push(format!(" ({})", filters.join(", ")));
+    }
+    response.push_line("");
+
+    if stats.games_played == 0 {
+        response.push_line("No completed pugs yet");
+        return Ok(response.build());
+    }
+
+    response
+        .push_line(format!("Games played: {}", stats.games_played))
+        .push_line(format!("Times captained: {}", stats.times_captained));
+
+    match pick_positions {
+        Some(picks) => response.push_line(format!(
+            "Average pick position: {:.1} (picked {} times)",
+            picks.average_pick_position, picks.times_picked
+        )),
+        None => response.push_line("Average pick position: never picked"),
+    };
+
+    response.push_line(format!(
+        "Frequent teammates: {}",
+        format_frequencies(ctx, &stats.teammates).await?
+    ));
+    response.push_line(format!(
+        "Frequent opponents: {}",
+        format_frequencies(ctx, &stats.opponents).await?
+    ));
+
+    if let Some(last_played) = stats.last_played {
+        let ht = HumanTime::from(last_played);
+        response.push_line(format!(
+            "Last played: {}",
+            ht.to_text_en(Accuracy::Rough, Tense::Past)
+        ));
+    }
+
+    Ok(response.build())
+}
+
+async fn format_frequencies(
+    ctx: &Context,
+    frequencies: &[PlayerFrequency],
+) -> anyhow::Result<String> {
+    if frequencies.is_empty() {
+        return Ok("none".to_string());
+    }
+
+    let mut entries = Vec::default();
+    for frequency in frequencies {
+        let user = UserId::from(frequency.user_id as u64)
+            .to_user(ctx)
+            .await
+            .context("Error encountered while converting `UserId` to `User`")?;
+        entries.push(format!("{} ({})", user.name, frequency.count));
+    }
+    Ok(entries.iter().join(", "))
+}
@@ -2,49 +2,47 @@ use std::collections::HashSet;
 use std::sync::Arc;
 
 use chrono::{Duration, Utc};
-use serenity::all::{CreateEmbed, CreateMessage};
 use serenity::model::id::{ChannelId, GuildId, UserId};
 use serenity::prelude::*;
 use serenity::utils::MessageBuilder;
 use tracing::{error, info, instrument};
 
 use crate::db::write::mark_voice_channels_deleted;
+use crate::utils::metrics;
+use crate::utils::ops::{self, OpsEvent};
 
+/// Log system resource load, and alert ops when it exceeds the configured limits.
 #[instrument(skip(ctx))]
 pub async fn log_system_load(ctx: Arc<Context>) {
-    let cpu_load = sys_info::loadavg().unwrap();
-    let mem_use = sys_info::mem_info().unwrap();
-    let m = CreateMessage::new().add_embed(
-        CreateEmbed::new()
-            .title("System Resource Load")
-            .field(
-                "CPU Load Average",
-                format!("{:.2}%", cpu_load.one * 10.0),
-                false,
-            )
-            .field(
-                "Memory Usage",
-                format!(
-                    "{:.2} MB Free out of {:.2} MB",
-                    mem_use.free as f32 / 1000.0,
-                    mem_use.total as f32 / 1000.0
-                ),
-                false,
-            ),
+    let (cpu_load, mem_use) = match (sys_info::loadavg(), sys_info::mem_info()) {
+        (Ok(cpu_load), Ok(mem_use)) => (cpu_load, mem_use),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("Failed to read system resource load: {:?}", err);
+            metrics::job_run(&ctx, "log_system_load", false).await;
+            return;
+        }
+    };
+    let cpu_load_percent = cpu_load.one * 10.0;
+    let free_memory_mb = mem_use.free as f64 / 1000.0;
+    let load = format!(
+        "CPU Load Average: {:.2}%\nMemory Usage: {:.2} MB Free out of {:.2} MB",
+        cpu_load_percent,
+        free_memory_mb,
+        mem_use.total as f64 / 1000.0
     );
+    info!("{}", load);
+    metrics::job_run(&ctx, "log_system_load", true).await;
 
-    if let Err(why) = UserId::from(209721904662183937)
-        .create_dm_channel(&*ctx)
-        .await
-        .expect("expected opened dm channel with sudomann")
-        .send_message(&ctx, m)
-        .await
+    let config = crate::config::get(&ctx).await;
+    if cpu_load_percent > config.ops.cpu_load_limit
+        || free_memory_mb < config.ops.min_free_memory_mb
     {
-        eprintln!("Error sending message: {:?}", why);
-    };
+        ops::report(&ctx, OpsEvent::Load, load).await;
+    }
 }
 
-/// Remove players from pug if they joined over 6 hours ago.
+/// Remove players from queues if they joined longer ago than configured (`pugs.stale_join_hours`,
+/// or the `max_queue_hours` of the game mode).
 ///
 /// Since currently there is no enforcing of processing pug commands
 /// in a designated pug channel, when a designated pug channel is not declared,
@@ -53,6 +51,7 @@ pub async fn log_system_load(ctx: Arc<Context>) {
 pub async fn clear_out_stale_joins(ctx: Arc<Context>) {
     let current_time = Utc::now();
     let _formatted_time = current_time.to_rfc2822();
+    let stale_join_hours = crate::config::get(&ctx).await.pugs.stale_join_hours;
 
     let db_client = {
         let data = ctx.data.read().await;
@@ -84,7 +83,14 @@ pub async fn clear_out_stale_joins(ctx: Arc<Context>) {
         }
         temp_log.push_line(":").push_line("==============");
 
-        match crate::db::read::get_stale_game_mode_joins(guild_db.clone(), Duration::hours(4)).await
+        // keeps the wait times on the queue board current, and shows any removals below
+        crate::utils::queue_board::refresh(&ctx, guild_id).await;
+
+        match crate::db::read::get_stale_game_mode_joins(
+            guild_db.clone(),
+            Duration::hours(stale_join_hours),
+        )
+        .await
         {
             Ok(game_mode_joins) => {
                 if game_mode_joins.is_empty() {
@@ -93,15 +99,22 @@ pub async fn clear_out_stale_joins(ctx: Arc<Context>) {
                 match crate::db::read::get_pug_channel(guild_db.clone()).await {
                     Ok(maybe_pug_channel) => {
                         let mut removed_users: HashSet<UserId> = HashSet::default();
+                        // a player must not be removed while a pug they filled is being started
+                        let queue_lock = crate::utils::queue_lock::lock(&ctx, guild_id).await;
                         for join in game_mode_joins {
                             let u_id = join.player_user_id as u64;
                             removed_users.insert(UserId::from(u_id));
-                            tokio::spawn(crate::db::write::remove_player_from_game_mode_queue(
+                            if let Err(err) = crate::db::write::remove_player_from_game_mode_queue(
                                 guild_db.clone(),
                                 join.game_mode_label,
                                 u_id,
-                            ));
+                            )
+                            .await
+                            {
+                                error!("Failed to remove stale join of user {}: {}", u_id, err);
+                            }
                         }
+                        drop(queue_lock);
 
                         // delete joins
                         match maybe_pug_channel {
@@ -158,14 +171,161 @@ pub async fn clear_out_stale_joins(ctx: Arc<Context>) {
             job_log.push(temp_log.build());
         }
     }
+    metrics::job_run(&ctx, "clear_out_stale_joins", !has_error).await;
     let job_log_output = job_log.build();
     if has_error {
         error!("{}", job_log_output);
+        ops::report(
+            &ctx,
+            OpsEvent::JobFailure,
+            format!("Clearing out stale queue joins:\n{}", job_log_output),
+        )
+        .await;
     } else {
         info!("{}", job_log_output);
     }
 }
 
+/// Leave results which were reported but not confirmed by the other captain in time for
+/// moderators to settle
+#[instrument(skip(ctx))]
+pub async fn escalate_unconfirmed_results(ctx: Arc<Context>) {
+    let result_confirm_mins = crate::config::get(&ctx).await.pugs.result_confirm_mins;
+
+    let db_client = {
+        let data = ctx.data.read().await;
+        match data.get::<crate::DbClientRef>() {
+            Some(c) => c.clone(),
+            None => {
+                info!("Client for database was not available - skipping result escalation");
+                return;
+            }
+        }
+    };
+
+    let mut has_error = false;
+    let mut job_log = MessageBuilder::default();
+    for guild_id in ctx.cache.guilds() {
+        let guild_db = db_client.database(guild_id.get().to_string().as_str());
+        let mut temp_log = MessageBuilder::default();
+        match guild_id.name(&ctx) {
+            Some(name) => temp_log.push_line(name),
+            None => temp_log.push_line(guild_id.to_string()),
+        };
+
+        match crate::db::read::get_unconfirmed_result_reports(
+            guild_db.clone(),
+            Duration::minutes(result_confirm_mins),
+        )
+        .await
+        {
+            Ok(pugs) => {
+                for pug in pugs {
+                    let reason = format!("not confirmed within {} minutes", result_confirm_mins);
+                    if let Err(err) =
+                        crate::utils::result_report::escalate(&ctx, guild_db.clone(), &pug, &reason)
+                            .await
+                    {
+                        has_error = true;
+                        temp_log
+                            .push_line(format!(
+                                "Failed to escalate the result of pug {}:",
+                                pug.thread_channel_id
+                            ))
+                            .push_line(format!("{:?}", err));
+                    }
+                }
+            }
+            Err(err) => {
+                has_error = true;
+                temp_log
+                    .push_line("Failed to read unconfirmed result reports")
+                    .push_line(err.to_string());
+            }
+        }
+        if has_error {
+            job_log.push(temp_log.build());
+        }
+    }
+    metrics::job_run(&ctx, "escalate_unconfirmed_results", !has_error).await;
+    if has_error {
+        let job_log_output = job_log.build();
+        error!("{}", job_log_output);
+        ops::report(
+            &ctx,
+            OpsEvent::JobFailure,
+            format!("Escalating unconfirmed results:\n{}", job_log_output),
+        )
+        .await;
+    }
+}
+
+/// End map votes which are past their end, but were not ended when they were due,
+/// e.g. because the bot restarted in the meantime
+#[instrument(skip(ctx))]
+pub async fn end_overdue_map_votes(ctx: Arc<Context>) {
+    let db_client = {
+        let data = ctx.data.read().await;
+        match data.get::<crate::DbClientRef>() {
+            Some(c) => c.clone(),
+            None => {
+                info!("Client for database was not available - skipping ending map votes");
+                return;
+            }
+        }
+    };
+
+    let mut has_error = false;
+    let mut job_log = MessageBuilder::default();
+    for guild_id in ctx.cache.guilds() {
+        let guild_db = db_client.database(guild_id.get().to_string().as_str());
+        let mut temp_log = MessageBuilder::default();
+        match guild_id.name(&ctx) {
+            Some(name) => temp_log.push_line(name),
+            None => temp_log.push_line(guild_id.to_string()),
+        };
+
+        match crate::db::read::get_ended_map_votes(guild_db.clone()).await {
+            Ok(pugs) => {
+                for pug in pugs {
+                    if let Err(err) =
+                        crate::utils::map_vote::end(&ctx, guild_db.clone(), pug.thread_channel_id)
+                            .await
+                    {
+                        has_error = true;
+                        temp_log
+                            .push_line(format!(
+                                "Failed to end the map vote of pug {}:",
+                                pug.thread_channel_id
+                            ))
+                            .push_line(format!("{:?}", err));
+                    }
+                }
+            }
+            Err(err) => {
+                has_error = true;
+                temp_log
+                    .push_line("Failed to read ended map votes")
+                    .push_line(err.to_string());
+            }
+        }
+        if has_error {
+            job_log.push(temp_log.build());
+        }
+    }
+    metrics::job_run(&ctx, "end_overdue_map_votes", !has_error).await;
+    if has_error {
+        let job_log_output = job_log.build();
+        error!("{}", job_log_output);
+        ops::report(
+            &ctx,
+            OpsEvent::JobFailure,
+            format!("Ending overdue map votes:\n{}", job_log_output),
+        )
+        .await;
+    }
+}
+
 #[instrument(skip(ctx))]
 pub async fn remove_stale_team_voice_channels(ctx: Arc<Context>) {
     // !TODO: make sure to skip deleting a voice channel if it's not empty??
@@ -213,7 +373,7 @@ pub async fn remove_stale_team_voice_channels(ctx: Arc<Context>) {
                     // FIXME: make db request to flip booleans to true (mark as deleted)
                     // for documents where at least 1/3 of its voice channel ids are in the list
 
-                    for id in vec![
+                    for id in [
                         channel_set.category.id as u64,
                         channel_set.blue_channel.id as u64,
                         channel_set.red_channel.id as u64,
@@ -249,7 +409,11 @@ pub async fn remove_stale_team_voice_channels(ctx: Arc<Context>) {
                     }
                 }
                 if !deleted.is_empty() {
-                    mark_voice_channels_deleted(guild_db, deleted).await;
+                    if let Err(err) = mark_voice_channels_deleted(guild_db, deleted).await {
+                        has_error = true;
+                        job_log.push_line("Failed to mark deleted voice channels:");
+                        job_log.push_line(err.to_string());
+                    }
                 }
             }
             Err(err) => {
@@ -258,30 +422,23 @@ pub async fn remove_stale_team_voice_channels(ctx: Arc<Context>) {
                 job_log.push(err.to_string());
             }
         }
+        metrics::job_run(&ctx, "remove_stale_team_voice_channels", !has_error).await;
         let job_log_output = job_log.build();
         if has_error {
             error!("{}", job_log_output);
+            ops::report(
+                &ctx,
+                OpsEvent::JobFailure,
+                format!("Removing stale team voice channels:\n{}", job_log_output),
+            )
+            .await;
         } else {
             info!("{}", job_log_output);
         }
     }
 }
 
-#[instrument(skip(ctx))]
-pub async fn remove_stale_threads(ctx: &Arc<Context>, guilds: &Vec<GuildId>) {
-    // !TODO: skip deleting a voice channel if it's not empty
-    let db_client = {
-        let data = ctx.data.read().await;
-        match data.get::<crate::DbClientRef>() {
-            Some(c) => c.clone(),
-            None => {
-                info!("Client for database was not available - skipping stale thread removal");
-                return;
-            }
-        }
-    };
-
-    for guild_id in guilds {
-        // TODO: this job does nothing yet
-    }
+#[instrument(skip_all)]
+pub async fn remove_stale_threads(_ctx: &Arc<Context>, _guilds: &[GuildId]) {
+    // TODO: this job does nothing yet
 }
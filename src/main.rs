@@ -1,23 +1,34 @@
 pub mod command_builder;
 pub mod commands;
+pub mod config;
 pub mod db;
 pub mod error;
 mod event_handler;
 pub mod interaction_handlers;
 pub mod jobs;
 pub mod utils;
-use std::env;
+use std::collections::HashMap;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
+use config::ConfigRef;
+use db::readiness::DbReachable;
 use event_handler::Handler;
 use serenity::gateway::ShardManager;
 use serenity::http::Http;
 use serenity::prelude::*;
-use tracing::error;
+use tokio::time::Duration;
 use tracing::log::info;
+use tracing::{error, warn};
 use tracing_subscriber::FmtSubscriber;
-use utils::crucial_user_ids;
+use utils::application_commands::CommandSyncLocks;
+use utils::crucial_user_ids::{self, CrucialIds};
+use utils::guild_lock::GuildLocks;
+use utils::leaderboard::LeaderboardCache;
+use utils::metrics::{Metrics, MetricsRef};
+use utils::queue_board::QueueBoards;
+use utils::queue_lock::QueueLocks;
+use utils::shutdown::{Shutdown, ShutdownRef};
 
 pub struct ShardManagerContainer;
 impl TypeMapKey for ShardManagerContainer {
@@ -37,67 +48,116 @@ impl TypeMapKey for DbClientRef {
     type Value = mongodb::Client;
 }
 
+pub struct CrucialIdsRef;
+impl TypeMapKey for CrucialIdsRef {
+    type Value = Arc<CrucialIds>;
+}
+
 #[tokio::main]
 async fn main() {
-    dotenv::dotenv().expect("Failed to load .env file");
     let subscriber = FmtSubscriber::builder().finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("Failed to start the logger");
 
-    let handle_to_db_client_setup = tokio::spawn(db::setup());
+    // a .env file is optional, its variables override the config file
+    if let Err(err) = dotenv::dotenv() {
+        info!("No .env file loaded: {}", err);
+    }
+    let config = match config::load() {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    info! {"Connecting to database...\n"};
+    let mongo_ready_max_wait = Duration::from_secs(config.mongo.ready_max_wait_secs);
+    let handle_to_db_client_setup =
+        tokio::spawn(db::setup(config.mongo.uri.clone(), mongo_ready_max_wait));
 
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    let token = config.discord.token.as_str();
 
     // Fetch bot id and superusers' ids
-    let important_user_ids = crucial_user_ids::obtain(Http::new(token.as_str()))
-        .await
-        .expect("Could not access application info: {:?}");
+    let important_user_ids =
+        crucial_user_ids::obtain(Http::new(token), config.discord.superusers.iter().copied())
+            .await
+            .expect("Could not access application info: {:?}");
 
-    let mut discord_client = Client::builder(&token, GatewayIntents::all())
+    let mut discord_client = Client::builder(token, GatewayIntents::all())
         .event_handler(Handler {
             is_loop_running: AtomicBool::new(false),
         })
         .application_id(*important_user_ids.get_bot())
         .await
         .expect("Error creating client");
+    let shutdown = Arc::new(Shutdown::new());
     {
         let mut data = discord_client.data.write().await;
         data.insert::<ShardManagerContainer>(discord_client.shard_manager.clone());
+        data.insert::<LeaderboardCache>(Arc::new(RwLock::new(HashMap::default())));
+        data.insert::<QueueLocks>(Arc::new(GuildLocks::new()));
+        data.insert::<QueueBoards>(Arc::new(Mutex::new(HashMap::default())));
+        data.insert::<CommandSyncLocks>(Arc::new(GuildLocks::new()));
+        data.insert::<CrucialIdsRef>(Arc::new(important_user_ids));
+        data.insert::<ConfigRef>(config.clone());
+        data.insert::<MetricsRef>(Arc::new(Metrics::new()));
+        data.insert::<ShutdownRef>(shutdown.clone());
     }
 
     let shard_manager = discord_client.shard_manager.clone();
+    let shutdown_deadline = Duration::from_secs(config.shutdown.deadline_secs);
 
     tokio::spawn(async move {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("Could not register ctrl+c handler");
+        utils::shutdown::signalled().await;
+        info!("Shutting down, waiting for in-flight work to finish");
+        if !shutdown.drain(shutdown_deadline).await {
+            warn!(
+                "Shutting down with {} unit(s) of work still in flight",
+                shutdown.in_flight()
+            );
+        }
         shard_manager.shutdown_all().await;
     });
 
-    info! {"Connecting to database...\n"};
-    match handle_to_db_client_setup.await {
-        Ok(db_client) => {
-            info!("The MongoDB client connection to the database deployment is live");
-            let mut data = discord_client.data.write().await;
-            data.insert::<DbClientRef>(db_client);
-        }
-        Err(err) => {
-            if err.is_panic() {
-                // TODO: does this actually halt the bot during stack unwinding?
-                // Resume the panic on the main task
-                // panic::resume_unwind(err.into_panic());
-                // err.into_panic();
-                error!("Failed to establish connection between client and database. Exiting...");
+    // Discord is connected to without waiting for the database, and interactions are turned
+    // away with a "starting up" reply until the database is ready
+    let data = discord_client.data.clone();
+    tokio::spawn(async move {
+        let db_client = match handle_to_db_client_setup.await {
+            Ok(Ok(db_client)) => db_client,
+            Ok(Err(err)) => {
+                error!(
+                    "The database was not ready within {} seconds. Exiting...\n{:?}",
+                    mongo_ready_max_wait.as_secs(),
+                    err
+                );
                 std::process::exit(1);
-            } else {
-                // TODO: handle this case where joining thread failed for some reason other
-                // than db::setup() panicking
-                panic!(
-                    "Failed to join the thread that was supposed to create the \
-                mongodb client object. Perhaps it was cancelled?"
+            }
+            Err(join_err) => {
+                error!(
+                    "Failed to join the task setting up the database client. Exiting...\n{:?}",
+                    join_err
                 );
+                std::process::exit(1);
             }
+        };
+        info!("The MongoDB client connection to the database deployment is live");
+        let reachable = Arc::new(AtomicBool::new(true));
+        {
+            let mut data = data.write().await;
+            data.insert::<DbReachable>(reachable.clone());
+            data.insert::<DbClientRef>(db_client.clone());
         }
+        db::readiness::monitor(db_client, reachable).await;
+    });
+
+    if let Some(addr) = config.status_server.addr.clone() {
+        tokio::spawn(utils::status_server::serve(
+            addr,
+            discord_client.data.clone(),
+            discord_client.cache.clone(),
+        ));
     }
 
     info! {"Connecting to Discord...\n"};
@@ -1,8 +1,24 @@
 pub mod application_commands;
 pub mod captain;
 pub mod crucial_user_ids;
+pub mod export;
+pub mod fuzzy;
+pub mod guild_lock;
+pub mod incident;
+pub mod leaderboard;
+pub mod map_vote;
+pub mod metrics;
+pub mod notifications;
 pub mod onboarding;
+pub mod ops;
+pub mod party;
 pub mod pick_sequence;
+pub mod presentation;
+pub mod queue_board;
+pub mod queue_lock;
+pub mod result_report;
+pub mod shutdown;
+pub mod status_server;
 pub mod time;
 pub mod transform;
 // pub mod validation;
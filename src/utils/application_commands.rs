@@ -1,67 +1,372 @@
-use anyhow::{bail, Context as AnyhowContext};
+//! Every guild command, and the guild state it depends on.
+//!
+//! [`REGISTRY`] describes when each command is registered and how it is built.
+//! [`sync`] builds the commands the current state of a guild calls for, compares them
+//! against what is registered with Discord and saved in the database, and applies any
+//! difference with a single bulk overwrite.
+
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Context as AnyhowContext;
+use itertools::Itertools;
 use mongodb::Database;
+use serenity::builder::CreateCommand;
 use serenity::client::Context;
-use serenity::model::id::{CommandId, GuildId};
+use serenity::model::id::GuildId;
+use serenity::prelude::TypeMapKey;
+use tracing::info;
 
-use crate::command_builder::base::{
-    build_addplayer, build_delmod, build_delplayer, build_join, build_last, build_leave,
-};
+use crate::command_builder::base::*;
+use crate::command_builder::*;
 use crate::db;
-use crate::db::model::GameMode;
+use crate::db::model::GuildCommand;
+use crate::utils::guild_lock::GuildLocks;
+
+/// The state of a guild which decides its command set.
+///
+/// Options which depend on other data (game modes, players left to pick) are suggested by
+/// autocomplete instead, so changes to that data do not need commands to be synced.
+pub struct CommandState {
+    /// Whether `.configure` was run. Until then, only /help is registered
+    pub configured: bool,
+    pub picking: PickingStage,
+}
+
+pub enum PickingStage {
+    /// No pug is being picked
+    None,
+    /// A pug is waiting for both of its captains
+    Captains,
+    /// The captains of a pug are picking its players
+    Players,
+}
+
+impl CommandState {
+    /// Read the state of a guild from its database.
+    pub async fn load(db: Database) -> anyhow::Result<Self> {
+        let settings = db::read::get_guild_settings(db.clone())
+            .await
+            .context("Failed to read guild settings")?;
+        let picking = match db::read::get_current_picking_session(db)
+            .await
+            .context("Tried checking for an active picking session")?
+        {
+            None => PickingStage::None,
+            Some(pug) if !pug.has_both_captains() => PickingStage::Captains,
+            Some(_) => PickingStage::Players,
+        };
+        Ok(CommandState {
+            configured: settings.commands_configured,
+            picking,
+        })
+    }
+
+    fn is_picking(&self) -> bool {
+        !matches!(self.picking, PickingStage::None)
+    }
+
+    fn is_choosing_captains(&self) -> bool {
+        matches!(self.picking, PickingStage::Captains)
+    }
+
+    fn is_picking_players(&self) -> bool {
+        matches!(self.picking, PickingStage::Players)
+    }
+}
 
-const COMMANDS_WITH_GAME_MODE_OPTION: &[&str; 6] =
-    &["join", "leave", "delmod", "last", "addplayer", "delplayer"];
+/// A guild command, and when it is registered.
+struct CommandSpec {
+    name: &'static str,
+    when: fn(&CommandState) -> bool,
+    build: fn() -> CreateCommand,
+}
+
+/// Every guild command, in the order they are registered.
+const REGISTRY: &[CommandSpec] = &[
+    CommandSpec {
+        name: "help",
+        when: |_| true,
+        build: build_help,
+    },
+    CommandSpec {
+        name: "setpugchannel",
+        when: |state| state.configured,
+        build: build_pugchannel,
+    },
+    CommandSpec {
+        name: "addmod",
+        when: |state| state.configured,
+        build: build_addmod,
+    },
+    CommandSpec {
+        name: "delmod",
+        when: |state| state.configured,
+        build: build_delmod,
+    },
+    CommandSpec {
+        name: "editmod",
+        when: |state| state.configured,
+        build: build_editmod,
+    },
+    CommandSpec {
+        name: "map",
+        when: |state| state.configured,
+        build: build_map,
+    },
+    CommandSpec {
+        name: "list",
+        when: |state| state.configured,
+        build: build_list,
+    },
+    CommandSpec {
+        name: "last",
+        when: |state| state.configured,
+        build: build_last,
+    },
+    CommandSpec {
+        name: "join",
+        when: |state| state.configured,
+        build: build_join,
+    },
+    CommandSpec {
+        name: "leave",
+        when: |state| state.configured,
+        build: build_leave,
+    },
+    CommandSpec {
+        name: "addplayer",
+        when: |state| state.configured,
+        build: build_addplayer,
+    },
+    CommandSpec {
+        name: "delplayer",
+        when: |state| state.configured,
+        build: build_delplayer,
+    },
+    CommandSpec {
+        name: "notify",
+        when: |state| state.configured,
+        build: build_notify,
+    },
+    CommandSpec {
+        name: "party",
+        when: |state| state.configured,
+        build: build_party,
+    },
+    CommandSpec {
+        name: "report",
+        when: |state| state.configured,
+        build: build_report,
+    },
+    CommandSpec {
+        name: "setresult",
+        when: |state| state.configured,
+        build: build_setresult,
+    },
+    CommandSpec {
+        name: "stats",
+        when: |state| state.configured,
+        build: build_stats,
+    },
+    CommandSpec {
+        name: "leaderboard",
+        when: |state| state.configured,
+        build: build_leaderboard,
+    },
+    CommandSpec {
+        name: "season",
+        when: |state| state.configured,
+        build: build_season,
+    },
+    CommandSpec {
+        name: "export",
+        when: |state| state.configured,
+        build: build_export,
+    },
+    CommandSpec {
+        name: "incident",
+        when: |state| state.configured,
+        build: build_incident,
+    },
+    CommandSpec {
+        name: "reset",
+        when: CommandState::is_picking,
+        build: build_reset,
+    },
+    CommandSpec {
+        name: "autocaptain",
+        when: CommandState::is_choosing_captains,
+        build: build_autocaptain,
+    },
+    CommandSpec {
+        name: "captain",
+        when: CommandState::is_choosing_captains,
+        build: build_captain,
+    },
+    CommandSpec {
+        name: "nocaptain",
+        when: CommandState::is_choosing_captains,
+        build: build_nocaptain,
+    },
+    CommandSpec {
+        name: "pick",
+        when: CommandState::is_picking_players,
+        build: build_pick,
+    },
+    CommandSpec {
+        name: "teams",
+        when: CommandState::is_picking_players,
+        build: build_teams,
+    },
+];
+
+/// The commands which were (or needed to be) changed by a [`sync`].
+#[derive(Debug, Default)]
+pub struct CommandDiff {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+impl CommandDiff {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.updated.is_empty() && self.deleted.is_empty()
+    }
+}
+
+impl fmt::Display for CommandDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "Commands are up to date");
+        }
+        let lines = [
+            ("Created", &self.created),
+            ("Updated", &self.updated),
+            ("Deleted", &self.deleted),
+        ]
+        .into_iter()
+        .filter(|(_, names)| !names.is_empty())
+        .format_with("\n", |(change, names), f| {
+            f(&format_args!(
+                "{}: {}",
+                change,
+                names.iter().map(|name| format!("/{}", name)).join(", ")
+            ))
+        });
+        write!(f, "{}", lines)
+    }
+}
 
-/// The commands listed require an up-to-date list of game modes to display as choices -
-/// this is a convenience function to update them for a given guild, provided a list of game modes:
+/// One lock per guild, held while its commands are synced, so a sync working from older
+/// state cannot overwrite the commands set by a newer one.
+pub struct CommandSyncLocks;
+impl TypeMapKey for CommandSyncLocks {
+    type Value = Arc<GuildLocks>;
+}
+
+/// Register the commands the current state of a guild calls for, and save records of them.
 ///
-/// - /join
-/// - /leave
-/// - /delmod
-/// - /last
-/// - /addplayer
-/// - /delplayer
-pub async fn refresh_commands_with_game_mode_option(
-    ctx: &Context,
-    guild_id: GuildId,
-    db: Database,
-    game_modes: Vec<GameMode>,
-) -> anyhow::Result<()> {
-    // !TODO: current implementation is tooo slow
-    // consider using tokio::spawn + join_all to parallelize, so it completes under 3 secs
-    for command_name in COMMANDS_WITH_GAME_MODE_OPTION {
-        let saved_guild_command = db::read::find_command(db.clone(), &command_name)
-            .await?
-            .context(format!(
-                "No `{}` command was found in the database",
-                &command_name
-            ))?;
-
-        let updated_command_to_apply = match *command_name {
-            "join" => build_join(&game_modes),
-            "leave" => build_leave(&game_modes),
-            "delmod" => build_delmod(&game_modes),
-            "last" => build_last(&game_modes),
-            "addplayer" => build_addplayer(&game_modes),
-            "delplayer" => build_delplayer(&game_modes),
-            _ => {
-                bail!("Double-check match arms against command set for a typo in command name");
+/// Discord is only called when a command has to be created, changed or deleted,
+/// and then all commands are set at once with a bulk overwrite. Commands which did not
+/// change keep their ids.
+pub async fn sync(ctx: &Context, guild_id: GuildId, db: Database) -> anyhow::Result<CommandDiff> {
+    let locks = {
+        let data = ctx.data.read().await;
+        data.get::<CommandSyncLocks>()
+            .expect("Expected the command sync locks to be available for use")
+            .clone()
+    };
+    let _sync_lock = locks.lock(guild_id).await;
+
+    let state = CommandState::load(db.clone()).await?;
+    let desired = REGISTRY
+        .iter()
+        .filter(|spec| (spec.when)(&state))
+        .map(|spec| {
+            let builder = (spec.build)();
+            let definition = serde_json::to_string(&builder)
+                .context(format!("Failed to serialize the /{} command", spec.name))?;
+            Ok((spec.name, definition, builder))
+        })
+        .collect::<anyhow::Result<Vec<(&'static str, String, CreateCommand)>>>()?;
+
+    let registered = guild_id
+        .get_commands(&ctx.http)
+        .await
+        .context("Failed to retrieve list of guild commands from discord")?;
+    let records = db::read::get_commands(db.clone())
+        .await
+        .context("Failed to read saved guild commands from database")?;
+
+    let mut diff = CommandDiff::default();
+    for (name, definition, _) in desired.iter() {
+        let registered_command = match registered.iter().find(|command| command.name == *name) {
+            Some(command) => command,
+            None => {
+                diff.created.push(name.to_string());
+                continue;
             }
         };
+        let up_to_date = records.iter().any(|record| {
+            record.name == *name
+                && record.command_id as u64 == registered_command.id.get()
+                && record.definition.as_ref() == Some(definition)
+        });
+        if !up_to_date {
+            diff.updated.push(name.to_string());
+        }
+    }
+    diff.deleted = registered
+        .iter()
+        .filter(|command| desired.iter().all(|(name, _, _)| command.name != *name))
+        .map(|command| command.name.clone())
+        .collect();
 
-        guild_id
-            .edit_command(
-                &ctx.http,
-                CommandId::from(saved_guild_command.command_id as u64),
-                updated_command_to_apply,
-            )
-            .await
-            .context(
-                "Attempted to edit existing join application command to \
-                overwrite its options with a new one which has an \
-                up to date list of game mode choices",
-            )?;
+    if diff.is_empty() {
+        // only records of commands which no longer exist can be left to clean up
+        if records.len() != desired.len() {
+            let records = records
+                .into_iter()
+                .filter(|record| desired.iter().any(|(name, _, _)| record.name == *name))
+                .collect();
+            db::write::replace_guild_commands(db, records)
+                .await
+                .context("Failed to delete records of commands which no longer exist")?;
+        }
+        return Ok(diff);
     }
 
-    Ok(())
+    info!("Syncing commands of {}: {}", guild_id, diff);
+    let (names_and_definitions, builders): (Vec<(&str, String)>, Vec<CreateCommand>) = desired
+        .into_iter()
+        .map(|(name, definition, builder)| ((name, definition), builder))
+        .unzip();
+    let commands = guild_id
+        .set_commands(&ctx.http, builders)
+        .await
+        .context(format!(
+            "Failed to overwrite guild commands for: {:?}",
+            &guild_id
+        ))?;
+
+    let records = names_and_definitions
+        .into_iter()
+        .map(|(name, definition)| {
+            let command = commands
+                .iter()
+                .find(|command| command.name == name)
+                .context(format!("Discord did not return the /{} command", name))?;
+            Ok(GuildCommand {
+                command_id: command.id.get() as i64,
+                name: name.to_string(),
+                definition: Some(definition),
+            })
+        })
+        .collect::<anyhow::Result<Vec<GuildCommand>>>()?;
+    db::write::replace_guild_commands(db, records)
+        .await
+        .context("Guild commands have been set, but something went wrong saving command records in the database")?;
+
+    Ok(diff)
 }
@@ -1,27 +1,19 @@
 use core::fmt;
-use std::collections::{HashMap, HashSet};
-use std::hash::Hash;
-use std::iter::FromIterator;
-
-use crate::command_builder::{build_pick, build_reset, build_teams};
-use crate::db::model::{GuildCommand, PickingSession, Player, Team};
-use crate::db::read::get_picking_session_members;
-use crate::db::read::{get_current_picking_session, is_captain_position_available};
+
+use crate::db::model::Team;
+use crate::db::read::get_current_picking_session;
 use crate::error::SetCaptainErr;
 use crate::{db, DbClientRef};
 use anyhow::{bail, Context as AnyhowContext};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
 use mongodb::Database;
 use rand::prelude::{IteratorRandom, SliceRandom};
 use serenity::builder::EditMessage;
-use serenity::model::application::Command;
 use serenity::model::id::GuildId;
 use serenity::{client::Context, model::id::ChannelId, utils::MessageBuilder};
 use tokio::time::{interval, Duration};
 use tracing::info;
 
-const MAX_WAIT_SECS: i64 = 30;
-
 // Intended to be spawned into a new thread, not awaited.
 pub async fn autopick_countdown(
     ctx: Context,
@@ -31,11 +23,12 @@ pub async fn autopick_countdown(
 ) {
     let mut interval = interval(Duration::from_secs(1));
     let mut seconds_elapsed;
+    let max_wait_secs = crate::config::get(&ctx).await.pugs.auto_captain_wait_secs;
 
     let mut countdown_message = pug_thread_channel_id
         .say(
             &ctx,
-            format!("Auto captains in about `{}` seconds", MAX_WAIT_SECS),
+            format!("Auto captains in about `{}` seconds", max_wait_secs),
         )
         .await
         .expect("Auto captain alert to send successfully");
@@ -43,8 +36,32 @@ pub async fn autopick_countdown(
 
     let countdown_message_timestamp: DateTime<Utc> = *countdown_message.timestamp;
 
+    let shutdown = crate::utils::shutdown::get(&ctx).await;
+
     loop {
-        interval.tick().await;
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.stopping() => {
+                // the pug itself is saved, so the countdown starts over once the bot is back
+                let final_update = MessageBuilder::new()
+                    .push_strike_line(format!(
+                        "Auto captains in about `{}` seconds",
+                        max_wait_secs
+                            - Utc::now()
+                                .signed_duration_since(countdown_message_timestamp)
+                                .num_seconds()
+                    ))
+                    .push_italic(
+                        "Countdown interrupted because the bot is restarting. \
+                        It will start over once the bot is back",
+                    )
+                    .build();
+                let _ = countdown_message
+                    .edit(&ctx.http, EditMessage::new().content(final_update))
+                    .await;
+                return;
+            }
+        }
 
         seconds_elapsed = Utc::now()
             .signed_duration_since(countdown_message_timestamp)
@@ -52,7 +69,7 @@ pub async fn autopick_countdown(
 
         let new_update = format!(
             "Auto captains in about `{}` seconds",
-            MAX_WAIT_SECS - seconds_elapsed
+            max_wait_secs - seconds_elapsed
         );
 
         // This can be None if:
@@ -97,20 +114,13 @@ pub async fn autopick_countdown(
                 }
             }
 
-            let captain_position_available =
-                is_captain_position_available(db.clone(), &pug_thread_channel_id.get())
-                    .await
-                    .expect(
-                        "Failure when checking database for players with the captain assignment",
-                    );
+            let captain_position_available = !current_picking_session.has_both_captains();
 
             // cancel the auto captain timer when there are no longer open captain spots
             if !captain_position_available {
                 let final_update = MessageBuilder::new()
                     .push_strike_line(new_update)
-                    .push_italic(
-                        "Countdown cancelled becase captain positions have been occupied",
-                    )
+                    .push_italic("Countdown cancelled becase captain positions have been occupied")
                     .build();
                 let _ = countdown_message
                     .edit(&ctx.http, EditMessage::new().content(final_update))
@@ -118,8 +128,8 @@ pub async fn autopick_countdown(
                 return;
             }
 
-            // this loop should go for no more than 30 secs
-            if seconds_elapsed > MAX_WAIT_SECS {
+            // this loop should go for no more than the configured wait
+            if seconds_elapsed > max_wait_secs {
                 break;
             }
 
@@ -141,7 +151,10 @@ pub async fn autopick_countdown(
     let countdown_timeout_alert = countdown_message
         .reply(
             &ctx,
-            "Random captain assignment because it's been more than 30 seconds",
+            format!(
+                "Random captain assignment because it's been more than {} seconds",
+                max_wait_secs
+            ),
         )
         .await
         .expect("Expected message declaring timer expiration to send successfully");
@@ -150,12 +163,9 @@ pub async fn autopick_countdown(
         Ok(result) => match result {
             PostSetCaptainAction::NeedBlueCaptain | PostSetCaptainAction::NeedRedCaptain => {
                 // need error handling and alerting here, because this case should not happen
-                !todo!();
+                unreachable!("Random captains are assigned to both teams at once");
             }
-            PostSetCaptainAction::StartPicking {
-                blue_captain_id,
-                red_captain_id,
-            } => "StartPickingRed !FIXME",
+            PostSetCaptainAction::StartPicking { .. } => "StartPickingRed !FIXME",
         },
         Err(_err) => {
             // need error handling and alerting here, because this case should not happen
@@ -215,225 +225,176 @@ pub async fn captain_helper(
     };
     let db = client.database(&guild_id.to_string());
 
-    // get all players of the picking session associated with this thread
-
-    let participants: Vec<Player> = get_picking_session_members(db.clone(), &thread_channel_id)
+    // get the pug associated with this thread
+    let pug = match get_current_picking_session(db.clone())
         .await
         .context(format!(
-            "Tried to fetch a list of `Player`s who are associated with the thread: {}",
+            "Tried to fetch the pug which is associated with the thread: {}",
             thread_channel_id,
-        ))?;
+        ))? {
+        Some(pug) if pug.thread_channel_id as u64 == *thread_channel_id => pug,
+        _ => bail!(SetCaptainErr::NoPlayers),
+    };
 
-    if participants.len() == 0 {
+    if pug.players.is_empty() {
         // this shouldn't ever be true, but just in case...
         bail!(SetCaptainErr::NoPlayers);
     }
 
-    let mut existing_captains: HashMap<Team, &Player> = HashMap::new();
-
-    participants.iter().filter(|p| p.is_captain).map(|p| {
-        if existing_captains.insert(p.team.unwrap(), p).is_some() {
-            bail!(SetCaptainErr::CaptainSpotsAvailibilityDataCorrupt)
-        }
-        Ok(())
-    });
-
-    let mut possible_captains = participants.iter().filter(|p| p.is_captain == false);
+    // players placed on a team with their party can't captain the other one
+    let possible_captains = pug.pickable_players();
     // FIXME: honor /nocapt and exclude players who opted out of being auto-captained
-    // .filter(|(_, user_id)| !excluded_players.contains(user_id))
+    // .filter(|user_id| !pug.captain_opt_outs.contains(user_id))
     // exclude them from iterator output
 
-    let operation_outcome = match existing_captains.len() {
-        2 => {
+    let existing_captain = match (pug.blue_team_captain, pug.red_team_captain) {
+        (Some(_), Some(_)) => {
             bail!(SetCaptainErr::CaptainSpotsFilled);
         }
-        1 => {
-            let player = match maybe_user_id {
+        (Some(blue_captain), None) => Some((Team::Blue, blue_captain)),
+        (None, Some(red_captain)) => Some((Team::Red, red_captain)),
+        (None, None) => None,
+    };
+
+    let (operation_outcome, updated_pug) = match existing_captain {
+        Some((team_of_the_existing_captain, existing_captain_user_id)) => {
+            let player_user_id = match maybe_user_id {
                 Some(provided_user_id) => {
+                    let provided_user_id = provided_user_id as i64;
                     // check whether user is in pug
-                    let is_a_participant = participants
-                        .iter()
-                        .any(|p| p.user_id as u64 == provided_user_id);
-                    if !is_a_participant {
+                    if !pug.players.contains(&provided_user_id) {
                         bail!(SetCaptainErr::ForeignUser);
                     }
 
                     // check whether user is captain already
-                    let is_a_captain = participants
-                        .iter()
-                        .any(|p| p.user_id as u64 == provided_user_id && p.is_captain);
-                    if is_a_captain {
+                    if pug.is_captain(provided_user_id) {
                         bail!(SetCaptainErr::IsCaptainAlready);
                     }
-                    possible_captains
-                        .find(|p| p.user_id as u64 == provided_user_id)
-                        .unwrap()
+
+                    // check whether user was placed on a team already
+                    if pug.team_of(provided_user_id).is_some() {
+                        bail!(SetCaptainErr::OnTeamAlready);
+                    }
+                    provided_user_id
                 }
-                None => possible_captains.choose(&mut rand::thread_rng()).unwrap(),
+                None => match possible_captains.choose(&mut rand::thread_rng()) {
+                    Some(user_id) => *user_id,
+                    None => bail!(SetCaptainErr::NoPlayers),
+                },
             };
 
-            let team_of_the_existing_captain = *existing_captains.keys().next().unwrap();
+            // the new captain takes the spot on the team without one
+            let (blue_captain_id, red_captain_id) = match team_of_the_existing_captain {
+                Team::Blue => (existing_captain_user_id, player_user_id),
+                Team::Red => (player_user_id, existing_captain_user_id),
+            };
 
-            let player_user_id = player.user_id as u64;
-            db::write::set_one_captain(
+            let updated_pug = db::write::set_captains(
                 db.clone(),
-                &thread_channel_id,
-                &player_user_id,
-                team_of_the_existing_captain,
+                &pug,
+                Some(blue_captain_id),
+                Some(red_captain_id),
             )
             .await
             .context("Database write operation failed when trying to set user as captain")?;
 
-            match team_of_the_existing_captain {
-                Team::Blue => PostSetCaptainAction::StartPicking {
-                    blue_captain_id: existing_captains.get(&Team::Blue).unwrap().user_id as u64,
-                    red_captain_id: player_user_id,
+            (
+                PostSetCaptainAction::StartPicking {
+                    blue_captain_id: blue_captain_id as u64,
+                    red_captain_id: red_captain_id as u64,
                 },
-                Team::Red => PostSetCaptainAction::StartPicking {
-                    blue_captain_id: player_user_id,
-                    red_captain_id: existing_captains.get(&Team::Red).unwrap().user_id as u64,
-                },
-            }
+                updated_pug,
+            )
         }
-        0 => {
+        None => {
             match maybe_user_id {
                 Some(user_id) => {
+                    let user_id = user_id as i64;
+                    if !pug.players.contains(&user_id) {
+                        bail!(SetCaptainErr::ForeignUser);
+                    }
+
                     // select random team on which to assign user as captain
-                    let team_options = vec![Team::Blue, Team::Red];
+                    let team_options = [Team::Blue, Team::Red];
                     let team = team_options.choose(&mut rand::thread_rng()).unwrap();
 
-                    db::write::set_one_captain(db.clone(), &thread_channel_id, &user_id, *team)
-                        .await
-                        .context(
-                            "Database write operation failed when trying to set user as captain",
-                        )?;
+                    let updated_pug = match team {
+                        Team::Blue => {
+                            db::write::set_captains(db.clone(), &pug, Some(user_id), None)
+                        }
+                        Team::Red => db::write::set_captains(db.clone(), &pug, None, Some(user_id)),
+                    }
+                    .await
+                    .context(
+                        "Database write operation failed when trying to set user as captain",
+                    )?;
 
-                    match team {
+                    let outcome = match team {
                         Team::Blue => PostSetCaptainAction::NeedRedCaptain,
                         Team::Red => PostSetCaptainAction::NeedBlueCaptain,
-                    }
+                    };
+                    (outcome, updated_pug)
                 }
                 None => {
-                    let mut two_random_players =
-                        possible_captains.choose_multiple(&mut rand::thread_rng(), 2);
-                    let blue_captain = two_random_players.pop().unwrap();
-                    let red_captain = two_random_players.pop().unwrap();
-
-                    let blue_captain_user_id = blue_captain.user_id as u64;
-                    let red_captain_user_id = red_captain.user_id as u64;
-                    db::write::set_both_captains(
+                    let two_random_players = possible_captains
+                        .into_iter()
+                        .choose_multiple(&mut rand::thread_rng(), 2);
+                    let (blue_captain_user_id, red_captain_user_id) =
+                        match two_random_players.as_slice() {
+                            [blue, red] => (*blue, *red),
+                            _ => bail!(SetCaptainErr::NoPlayers),
+                        };
+
+                    let updated_pug = db::write::set_captains(
                         db.clone(),
-                        &thread_channel_id,
-                        &blue_captain_user_id,
-                        &red_captain_user_id,
+                        &pug,
+                        Some(blue_captain_user_id),
+                        Some(red_captain_user_id),
                     )
-                    .await.context(
-                        "Database write operation failed when trying to set both captains using one transaction",
+                    .await
+                    .context(
+                        "Database write operation failed when trying to set both captains in one update",
                     )?;
 
-                    // get picking session's pick sequence to determine which color to announce
-                    // as picking first
-                    // let picking_session: PickingSession = db::read::get_current_picking_session(db.clone())
-                    //     .await
-                    //     .context("")?
-                    //     .context("Expected there to be an active picking session related to the current captain operation")?;
-
-                    // let first_pick_team = picking_session.pick_sequence.get(0).unwrap();
-                    // match first_pick_team {
-                    //     Team::Blue => PostSetCaptainAction::StartPicking,
-                    //     Team::Red => PostSetCaptainAction::StartPickingRed,
-                    // }
-
-                    //
-                    PostSetCaptainAction::StartPicking {
-                        blue_captain_id: blue_captain_user_id,
-                        red_captain_id: red_captain_user_id,
-                    }
+                    (
+                        PostSetCaptainAction::StartPicking {
+                            blue_captain_id: blue_captain_user_id as u64,
+                            red_captain_id: red_captain_user_id as u64,
+                        },
+                        updated_pug,
+                    )
                 }
             }
         }
-        _ => {
-            // this should never happen :(
-            bail!(SetCaptainErr::InvalidCount)
-        }
     };
 
-    match &operation_outcome {
-        PostSetCaptainAction::StartPicking {
-            blue_captain_id,
-            red_captain_id,
-        } => {
-            // TODO: perhaps more specific info in this console message
-            info!("Clearing /captain /nocapt and /autocaptain commands since both captains have been assigned");
-
-            // delete /captain /nocapt /autocaptain
-
-            let saved_captain_commands: Vec<GuildCommand> =
-                db::read::get_captain_related_guild_commands(db.clone())
-                    .await
-                    .context("Failed to read saved captain-related guild commands from database")?;
+    // The pug was changed (e.g. another captain was set, or it was reset)
+    // after it was read above, so nothing was written
+    let updated_pug = match updated_pug {
+        Some(updated_pug) => updated_pug,
+        None => bail!(SetCaptainErr::Conflict),
+    };
 
-            let current_guild_commands = guild_id
-                .get_commands(&ctx.http)
+    // captains start out with the players they queued with
+    let party_mates = updated_pug.captains_party_mates();
+    if !party_mates.is_empty() {
+        let placed =
+            super::party::place_picks(ctx, *guild_id, db.clone(), &updated_pug, &party_mates)
                 .await
-                .context("Failed to retrieve list of guild commands from discord")?;
-
-            let commands_to_remove = current_guild_commands
-                .into_iter()
-                .filter(|c| {
-                    saved_captain_commands
-                        .iter()
-                        .any(|saved_cmd| saved_cmd.command_id as u64 == c.id.get())
-                })
-                .collect::<Vec<Command>>();
-
-            for cmd in &commands_to_remove {
-                guild_id
-                    .delete_command(&ctx.http, cmd.id)
-                    .await
-                    .context(format!("Failed to remove the {} guild command", cmd.name))?;
-            }
+                .context("Failed to place the party mates of the captains on their teams")?;
+        if placed.is_none() {
+            bail!(SetCaptainErr::Conflict);
+        }
+    }
 
-            db::write::find_and_delete_guild_commands(
-                db.clone(),
-                commands_to_remove.into_iter().map(|c| c.name),
-            )
-            .await
-            .context("Attempted and failed to delete captain-related commands from database")?;
-
-            // Perform a set of command creation steps. These steps should
-            // occur after both teams are assigned captains and it is time to pick players.
-            // 1. Create application commands: /pick and /teams
-            // 2. Add database records for the commands
-            // !FIXME: for the sake of performance, try filtering and reusing existing participant
-            // list from above
-            let participants: Vec<Player> =
-                get_picking_session_members(db.clone(), &thread_channel_id)
-                    .await
-                    .context("Tried to fetch a list of `Player`s to convert to `User` objects")?;
-            let pick_list = participants
-                .into_iter()
-                .filter(|p| p.is_captain == false && p.team.is_none());
-            let pick_list_as_users = super::transform::players_to_users(&ctx, pick_list)
-                .await
-                .context("Failed to convert pick list `Player`s to `User`s")?;
+    match &operation_outcome {
+        PostSetCaptainAction::StartPicking { .. } => {
+            info!("Syncing commands for picking players since both captains have been assigned");
 
-            let pick_command = guild_id
-                .create_command(&ctx.http, build_pick(&pick_list_as_users))
-                .await
-                .context("Failed to create pick command for guild")?;
-            let teams_command = guild_id
-                .create_command(&ctx.http, build_teams())
+            // replaces /captain /nocaptain and /autocaptain with /pick and /teams
+            super::application_commands::sync(ctx, *guild_id, db.clone())
                 .await
-                .context("Failed to create teams command for guild")?;
-            db::write::save_guild_commands(
-                db.clone(),
-                vec![pick_command, teams_command],
-            )
-            .await
-            .context(
-                "Failure when writing records for newly created /pick, /reset and /teams commands",
-            )?;
+                .context("Failed to sync commands for picking players")?;
         }
         PostSetCaptainAction::NeedBlueCaptain | PostSetCaptainAction::NeedRedCaptain => {
             // just continue on to return - callers should handle these cases completely
@@ -1,6 +1,4 @@
 use std::collections::HashSet;
-use std::env;
-use std::str::FromStr;
 
 use serenity::http::Http;
 use serenity::model::id::{ApplicationId, UserId};
@@ -22,26 +20,16 @@ impl CrucialIds {
     }
 }
 
-/// Get the Bot/Application's [`ApplicationId`] along with any other [`UserId`]'s provided via the environment
-/// variable `SUPERUSERS` (provided they are correctly formatted - if not, they are ignored).
-
-#[instrument]
-pub async fn obtain(http: Http) -> Result<CrucialIds, Error> {
+/// Get the Bot/Application's [`ApplicationId`], and the superusers: the given [`UserId`]'s along
+/// with the bot's owner.
+#[instrument(skip(superusers))]
+pub async fn obtain<I>(http: Http, superusers: I) -> Result<CrucialIds, Error>
+where
+    I: IntoIterator<Item = UserId>,
+{
     match http.get_current_application_info().await {
         Ok(info) => {
-            let mut superusers: HashSet<UserId> = match env::var("SUPERUSERS") {
-                Ok(superusers) => superusers
-                    .split_terminator(',')
-                    .filter_map(|id| UserId::from_str(id).ok())
-                    .collect(),
-                Err(_err) => {
-                    warn!(
-                        "SUPERUSERS was not found in the environment, \
-                        so there will be no superusers aside from the owner bot/application"
-                    );
-                    HashSet::default()
-                }
-            };
+            let mut superusers: HashSet<UserId> = superusers.into_iter().collect();
             match info.owner {
                 Some(owner) => {
                     superusers.insert(owner.id);
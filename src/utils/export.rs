@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::Context as AnyhowContext;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::Database;
+use serde::Serialize;
+use serenity::http::CacheHttp;
+use serenity::model::id::UserId;
+use tracing::warn;
+
+use crate::db;
+use crate::db::model::{MatchOutcome, Pug};
+use crate::utils::time::start_of_day;
+
+/// File formats match history can be exported to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn from_value(value: &str) -> Option<Self> {
+        match value {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Extension of files in this format, without the leading dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// A completed pug as it appears in an export, with user ids resolved to names.
+///
+/// Captains are included in their team.
+#[derive(Clone, Debug, Serialize)]
+struct ExportedPug {
+    created: DateTime<Utc>,
+    game_mode: String,
+    season: Option<String>,
+    outcome: Option<MatchOutcome>,
+    blue_captain: Option<String>,
+    blue_team: Vec<String>,
+    red_captain: Option<String>,
+    red_team: Vec<String>,
+}
+
+const CSV_HEADER: &str =
+    "created,game_mode,season,outcome,blue_captain,blue_team,red_captain,red_team";
+
+/// Quote a CSV field if it contains a character that would otherwise break the row.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl ExportedPug {
+    fn to_csv_row(&self) -> String {
+        [
+            self.created.to_rfc3339(),
+            self.game_mode.clone(),
+            self.season.clone().unwrap_or_default(),
+            self.outcome
+                .map(|outcome| format!("{:?}", outcome))
+                .unwrap_or_default(),
+            self.blue_captain.clone().unwrap_or_default(),
+            self.blue_team.join("; "),
+            self.red_captain.clone().unwrap_or_default(),
+            self.red_team.join("; "),
+        ]
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<String>>()
+        .join(",")
+    }
+}
+
+/// Resolves user ids to names, remembering each one so a user is only fetched once per export.
+struct NameResolver<C: CacheHttp> {
+    cache_http: C,
+    names: HashMap<i64, String>,
+}
+
+impl<C: CacheHttp> NameResolver<C> {
+    async fn resolve(&mut self, user_id: i64) -> String {
+        if let Some(name) = self.names.get(&user_id) {
+            return name.clone();
+        }
+        let name = match UserId::from(user_id as u64).to_user(&self.cache_http).await {
+            Ok(user) => user.name,
+            Err(err) => {
+                warn!(
+                    "Failed to resolve name of user {} for export: {}",
+                    user_id, err
+                );
+                user_id.to_string()
+            }
+        };
+        self.names.insert(user_id, name.clone());
+        name
+    }
+
+    async fn resolve_all(&mut self, user_ids: &[i64]) -> Vec<String> {
+        let mut names = Vec::with_capacity(user_ids.len());
+        for user_id in user_ids {
+            names.push(self.resolve(*user_id).await);
+        }
+        names
+    }
+
+    async fn export_pug(&mut self, pug: Pug) -> ExportedPug {
+        let blue_team = pug
+            .blue_team_captain
+            .into_iter()
+            .chain(pug.blue_team)
+            .collect::<Vec<i64>>();
+        let red_team = pug
+            .red_team_captain
+            .into_iter()
+            .chain(pug.red_team)
+            .collect::<Vec<i64>>();
+        let blue_captain = match pug.blue_team_captain {
+            Some(user_id) => Some(self.resolve(user_id).await),
+            None => None,
+        };
+        let red_captain = match pug.red_team_captain {
+            Some(user_id) => Some(self.resolve(user_id).await),
+            None => None,
+        };
+        ExportedPug {
+            created: pug.created,
+            game_mode: pug.game_mode,
+            season: pug.season,
+            outcome: pug.outcome,
+            blue_captain,
+            blue_team: self.resolve_all(&blue_team).await,
+            red_captain,
+            red_team: self.resolve_all(&red_team).await,
+        }
+    }
+}
+
+/// Writes exported records one at a time, so the export never has to be held in memory
+/// as anything but its serialized form.
+struct ExportWriter<'a, W: Write> {
+    writer: &'a mut W,
+    format: ExportFormat,
+    count: usize,
+}
+
+impl<'a, W: Write> ExportWriter<'a, W> {
+    fn start(writer: &'a mut W, format: ExportFormat) -> anyhow::Result<Self> {
+        match format {
+            ExportFormat::Csv => writeln!(writer, "{}", CSV_HEADER)?,
+            ExportFormat::Json => write!(writer, "[")?,
+        }
+        Ok(ExportWriter {
+            writer,
+            format,
+            count: 0,
+        })
+    }
+
+    fn write(&mut self, record: &ExportedPug) -> anyhow::Result<()> {
+        match self.format {
+            ExportFormat::Csv => writeln!(self.writer, "{}", record.to_csv_row())?,
+            ExportFormat::Json => {
+                if self.count > 0 {
+                    write!(self.writer, ",")?;
+                }
+                serde_json::to_writer(&mut *self.writer, record)?;
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<usize> {
+        if self.format == ExportFormat::Json {
+            write!(self.writer, "]")?;
+        }
+        self.writer.flush()?;
+        Ok(self.count)
+    }
+}
+
+/// Export the match history of a guild, resolving user ids to names.
+///
+/// Completed pugs played between `from` and `to` (inclusive, either may be left open)
+/// are written to `writer`, optionally restricted to one game mode.
+/// Returns the number of exported records.
+///
+/// Only a [`Database`] and something that can reach the Discord API are needed,
+/// e.g. a [`serenity::http::Http`] built from the bot token, so this can be used
+/// outside of a command handler.
+pub async fn export_match_history<C, W>(
+    db: Database,
+    cache_http: C,
+    game_mode_label: Option<&str>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    format: ExportFormat,
+    writer: &mut W,
+) -> anyhow::Result<usize>
+where
+    C: CacheHttp,
+    W: Write,
+{
+    let from = from.map(start_of_day);
+    // the end date is inclusive, so the range extends to the start of the following day
+    let to = to.and_then(|to| to.succ_opt()).map(start_of_day);
+
+    let mut resolver = NameResolver {
+        cache_http,
+        names: HashMap::default(),
+    };
+    let mut export = ExportWriter::start(writer, format)?;
+
+    let mut pugs = db::read::get_completed_pugs_between(db, game_mode_label, from, to)
+        .await
+        .context("Failed to read completed pugs")?;
+    while let Some(pug) = pugs.try_next().await? {
+        export.write(&resolver.export_pug(pug).await)?;
+    }
+
+    export.finish()
+}
@@ -0,0 +1,185 @@
+//! Matching of what a user has typed so far against labels, for autocomplete suggestions.
+
+/// Discord shows at most this many autocomplete suggestions.
+pub const MAX_SUGGESTIONS: usize = 25;
+
+/// How well `label` matches `query`, ignoring case, or `None` if it does not match.
+///
+/// Lower is better: an exact match comes first, then a prefix, then a substring (the earlier
+/// the better), then the characters of `query` appearing in order (the closer the better).
+pub fn score(query: &str, label: &str) -> Option<usize> {
+    let query = query.trim().to_lowercase();
+    let label = label.to_lowercase();
+    if query == label {
+        return Some(0);
+    }
+    if label.starts_with(&query) {
+        return Some(1);
+    }
+    if let Some(position) = label.find(&query) {
+        return Some(2 + position);
+    }
+
+    // characters of the label skipped between those of the query
+    let mut gaps = 0;
+    let mut label_chars = label.chars();
+    for query_char in query.chars() {
+        loop {
+            match label_chars.next() {
+                Some(label_char) if label_char == query_char => break,
+                Some(_) => gaps += 1,
+                None => return None,
+            }
+        }
+    }
+    Some(100 + gaps)
+}
+
+/// The items with a name matching `query`, best first (ties ordered by their first name),
+/// limited to what Discord can show.
+pub fn rank<T, F>(query: &str, items: Vec<T>, names: F) -> Vec<T>
+where
+    F: Fn(&T) -> Vec<&str>,
+{
+    let first_name = |item: &T| {
+        names(item)
+            .first()
+            .map(|name| name.to_lowercase())
+            .unwrap_or_default()
+    };
+    let mut scored = items
+        .into_iter()
+        .filter_map(|item| {
+            names(&item)
+                .into_iter()
+                .filter_map(|name| score(query, name))
+                .min()
+                .map(|score| (score, item))
+        })
+        .collect::<Vec<(usize, T)>>();
+    scored.sort_by(|(a_score, a), (b_score, b)| {
+        a_score
+            .cmp(b_score)
+            .then_with(|| first_name(a).cmp(&first_name(b)))
+    });
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, item)| item)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::model::GameMode;
+
+    fn game_mode(label: &str, aliases: &[&str]) -> GameMode {
+        GameMode {
+            label: label.to_string(),
+            aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+            ..GameMode::default()
+        }
+    }
+
+    /// The label followed by the aliases, as autocomplete matches game modes.
+    fn names(game_mode: &GameMode) -> Vec<&str> {
+        std::iter::once(&game_mode.label)
+            .chain(game_mode.aliases.iter())
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn labels(ranked: Vec<GameMode>) -> Vec<String> {
+        ranked
+            .into_iter()
+            .map(|game_mode| game_mode.label)
+            .collect()
+    }
+
+    #[test]
+    fn scores_exact_then_prefix_then_substring_then_scattered() {
+        assert_eq!(score("tdm", "tdm"), Some(0));
+        assert_eq!(score("tdm", "tdm2v2"), Some(1));
+        assert_eq!(score("dm", "tdm"), Some(3));
+        assert_eq!(score("dm", "team-dm"), Some(7));
+        assert_eq!(score("tdm", "team-dm"), Some(104));
+        assert_eq!(score("tdm", "ctf"), None);
+    }
+
+    #[test]
+    fn scores_ignore_case_and_surrounding_whitespace() {
+        assert_eq!(score(" TDM ", "tdm"), Some(0));
+        assert_eq!(score("ctf", "CTF"), Some(0));
+    }
+
+    #[test]
+    fn empty_query_matches_everything_as_a_prefix() {
+        assert_eq!(score("", "ctf"), Some(1));
+        assert_eq!(score("  ", ""), Some(0));
+    }
+
+    #[test]
+    fn query_longer_than_label_does_not_match() {
+        assert_eq!(score("ctf2v2", "ctf"), None);
+    }
+
+    #[test]
+    fn ranks_by_score_then_label() {
+        let game_modes = vec![
+            game_mode("team-dm", &[]),
+            game_mode("tdm2v2", &[]),
+            game_mode("ctf", &[]),
+            game_mode("tdm", &[]),
+            game_mode("atdm", &[]),
+        ];
+        assert_eq!(
+            labels(rank("tdm", game_modes, names)),
+            vec!["tdm", "tdm2v2", "atdm", "team-dm"]
+        );
+    }
+
+    #[test]
+    fn ties_are_ordered_by_label_ignoring_case() {
+        let game_modes = vec![
+            game_mode("tdm-b", &[]),
+            game_mode("TDM-a", &[]),
+            game_mode("tdm-c", &[]),
+        ];
+        assert_eq!(
+            labels(rank("tdm", game_modes, names)),
+            vec!["TDM-a", "tdm-b", "tdm-c"]
+        );
+    }
+
+    #[test]
+    fn aliases_match_with_their_own_score() {
+        let game_modes = vec![
+            game_mode("capture the flag", &["ctf"]),
+            game_mode("ctf2v2", &[]),
+            game_mode("deathmatch", &["dm", "ffa"]),
+        ];
+        // the alias `ctf` is an exact match, so it beats the prefix match of `ctf2v2`
+        assert_eq!(
+            labels(rank("ctf", game_modes.clone(), names)),
+            vec!["capture the flag", "ctf2v2"]
+        );
+        assert_eq!(labels(rank("ffa", game_modes, names)), vec!["deathmatch"]);
+    }
+
+    #[test]
+    fn nothing_to_rank() {
+        assert!(rank("ctf", Vec::default(), names).is_empty());
+    }
+
+    #[test]
+    fn ranks_at_most_what_discord_shows() {
+        let game_modes = (0..40)
+            .map(|n| game_mode(&format!("ctf{:02}", n), &[]))
+            .collect();
+        let ranked = labels(rank("ctf", game_modes, names));
+        assert_eq!(ranked.len(), MAX_SUGGESTIONS);
+        assert_eq!(ranked.first().map(String::as_str), Some("ctf00"));
+        assert_eq!(ranked.last().map(String::as_str), Some("ctf24"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serenity::model::id::GuildId;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// A lock per guild, created the first time a guild's lock is taken.
+///
+/// Holding one guild's lock never waits on another guild's.
+#[derive(Default)]
+pub struct GuildLocks {
+    locks: Mutex<HashMap<GuildId, Arc<Mutex<()>>>>,
+}
+
+impl GuildLocks {
+    pub fn new() -> Self {
+        GuildLocks::default()
+    }
+
+    /// Wait for, then hold, the lock of a guild until the returned guard is dropped.
+    pub async fn lock(&self, guild_id: GuildId) -> OwnedMutexGuard<()> {
+        // the map is only locked long enough to find the guild's lock
+        let guild_lock = self.locks.lock().await.entry(guild_id).or_default().clone();
+        guild_lock.lock_owned().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER_COUNT: usize = 10;
+
+    /// A queue updated under a guild's lock: read it, then (after other tasks had a chance to
+    /// run) save it with the player added, and form a pug once full. This only stands in for
+    /// the read then write of `join_helper`, it does not run it.
+    #[derive(Default)]
+    struct Queue {
+        players: Vec<u64>,
+        pugs: Vec<Vec<u64>>,
+    }
+
+    async fn join(locks: &GuildLocks, queue: &std::sync::Mutex<Queue>, player: u64) {
+        let _guild_lock = locks.lock(GuildId::new(1)).await;
+        let mut players = queue.lock().unwrap().players.clone();
+        tokio::task::yield_now().await;
+        players.push(player);
+        let mut queue = queue.lock().unwrap();
+        if players.len() == PLAYER_COUNT {
+            queue.pugs.push(players);
+            queue.players.clear();
+        } else {
+            queue.players = players;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn guild_lock_serializes_reading_then_writing_a_queue() {
+        let locks = Arc::new(GuildLocks::new());
+        let queue = Arc::new(std::sync::Mutex::new(Queue::default()));
+
+        let joins = (0..PLAYER_COUNT as u64 + 5)
+            .map(|player| {
+                let locks = locks.clone();
+                let queue = queue.clone();
+                tokio::spawn(async move { join(&locks, &queue, player).await })
+            })
+            .collect::<Vec<_>>();
+        for handle in joins {
+            handle.await.unwrap();
+        }
+
+        let queue = queue.lock().unwrap();
+        assert_eq!(queue.pugs.len(), 1);
+        assert_eq!(queue.pugs[0].len(), PLAYER_COUNT);
+        assert_eq!(queue.players.len(), 5);
+        let mut everyone = queue.pugs[0]
+            .iter()
+            .chain(queue.players.iter())
+            .copied()
+            .collect::<Vec<u64>>();
+        everyone.sort_unstable();
+        everyone.dedup();
+        assert_eq!(everyone.len(), PLAYER_COUNT + 5);
+    }
+
+    #[tokio::test]
+    async fn guilds_do_not_wait_on_each_other() {
+        let locks = GuildLocks::new();
+        let _first = locks.lock(GuildId::new(1)).await;
+        let second = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            locks.lock(GuildId::new(2)),
+        )
+        .await;
+        assert!(second.is_ok());
+    }
+}
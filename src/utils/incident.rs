@@ -0,0 +1,116 @@
+use chrono::{Duration, Utc};
+use nanoid::nanoid;
+use serenity::builder::{CreateActionRow, CreateButton, CreateInputText, CreateModal};
+use serenity::client::Context;
+use serenity::model::application::{ButtonStyle, InputTextStyle};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use tracing::error;
+
+use crate::db;
+use crate::db::model::Incident;
+use crate::utils::metrics;
+use crate::DbClientRef;
+
+/// Prefix of the custom id of the button (and the modal it opens) for adding a note to an incident.
+pub const NOTE_ID_PREFIX: &str = "incident_note";
+
+/// Custom id of the text input of the note modal.
+const NOTE_INPUT_ID: &str = "note";
+
+/// What was being handled when an error occurred.
+pub struct IncidentSource {
+    pub guild_id: Option<GuildId>,
+    /// Name of the command, or custom id of the component
+    pub command: String,
+    /// Options the command was used with, as `name: value`
+    pub options: Vec<String>,
+    pub user_id: UserId,
+    pub channel_id: ChannelId,
+}
+
+/// Log an error returned by a handler under a new incident id and, if it happened in a guild,
+/// save it to the guild's database.
+///
+/// Returns the incident id. Saving is best-effort, so an id is returned either way.
+pub async fn record(ctx: &Context, source: IncidentSource, err: &anyhow::Error) -> String {
+    let incident_id = nanoid!(6);
+    error!("Error Event [{}]\n{:#?}", incident_id, err);
+    let discord_errors = err
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<serenity::Error>())
+        .collect::<Vec<_>>();
+    for discord_err in discord_errors {
+        metrics::discord_error(ctx, discord_err).await;
+    }
+
+    let guild_id = match source.guild_id {
+        Some(guild_id) => guild_id,
+        None => return incident_id,
+    };
+    let client = {
+        let data = ctx.data.read().await;
+        match data.get::<DbClientRef>() {
+            Some(client) => client.clone(),
+            None => return incident_id,
+        }
+    };
+
+    let retention_days = crate::config::get(ctx).await.incidents.retention_days;
+    let now = Utc::now();
+    let incident = Incident {
+        incident_id: incident_id.clone(),
+        created: now,
+        expires: (now + Duration::days(retention_days)).into(),
+        command: source.command,
+        options: source.options,
+        user_id: source.user_id.get() as i64,
+        channel_id: source.channel_id.get() as i64,
+        error_chain: err.chain().map(|cause| cause.to_string()).collect(),
+        notes: Vec::default(),
+    };
+    if let Err(save_err) =
+        db::write::insert_incident(client.database(&guild_id.to_string()), &incident).await
+    {
+        error!("Failed to save incident [{}]: {:?}", incident_id, save_err);
+    }
+    incident_id
+}
+
+/// The reply to a user whose command failed.
+pub fn reply_text(incident_id: &str) -> String {
+    format!(
+        "Sorry, something went wrong and this incident has been logged.\nIncident ID: `{}`",
+        incident_id
+    )
+}
+
+/// A button which lets the user add a note to their incident.
+pub fn note_button(incident_id: &str) -> CreateActionRow {
+    let button = CreateButton::new(format!("{}|{}", NOTE_ID_PREFIX, incident_id))
+        .label("Add a note")
+        .style(ButtonStyle::Secondary);
+    CreateActionRow::Buttons(vec![button])
+}
+
+/// The form for adding a note to an incident, opened by its [`note_button`].
+pub fn note_modal(incident_id: &str) -> CreateModal {
+    let input = CreateInputText::new(
+        InputTextStyle::Paragraph,
+        "What were you trying to do?",
+        NOTE_INPUT_ID,
+    )
+    .max_length(1000)
+    .required(true);
+    CreateModal::new(
+        format!("{}|{}", NOTE_ID_PREFIX, incident_id),
+        format!("Incident {}", incident_id),
+    )
+    .components(vec![CreateActionRow::InputText(input)])
+}
+
+/// The incident id in the custom id of a note button or modal.
+pub fn incident_id_from_custom_id(custom_id: &str) -> Option<&str> {
+    custom_id
+        .strip_prefix(NOTE_ID_PREFIX)
+        .and_then(|rest| rest.strip_prefix('|'))
+}
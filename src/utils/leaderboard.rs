@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use mongodb::Database;
+use serenity::builder::{CreateActionRow, CreateButton};
+use serenity::client::Context;
+use serenity::model::id::GuildId;
+use serenity::prelude::{RwLock, TypeMapKey};
+
+use crate::db;
+use crate::db::model::LeaderboardEntry;
+use crate::utils::time::start_of_day;
+
+/// Number of entries shown on a single page of a leaderboard.
+pub const PAGE_SIZE: usize = 10;
+
+/// Minimum number of games with a recorded outcome a player needs
+/// in order to be ranked by win rate.
+pub const WIN_RATE_MIN_GAMES: i64 = 10;
+
+/// Prefix of the custom id of leaderboard page buttons.
+pub const BUTTON_ID_PREFIX: &str = "leaderboard";
+
+/// Discord rejects components with a custom id longer than this many characters
+const MAX_CUSTOM_ID_LENGTH: usize = 100;
+
+/// What players are ranked by on a leaderboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LeaderboardMetric {
+    GamesPlayed,
+    Wins,
+    WinRate,
+    TimesCaptained,
+}
+
+impl LeaderboardMetric {
+    /// Identifier used as the command option value and in button custom ids.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LeaderboardMetric::GamesPlayed => "games",
+            LeaderboardMetric::Wins => "wins",
+            LeaderboardMetric::WinRate => "winrate",
+            LeaderboardMetric::TimesCaptained => "captained",
+        }
+    }
+
+    pub fn from_value(value: &str) -> Option<Self> {
+        match value {
+            "games" => Some(LeaderboardMetric::GamesPlayed),
+            "wins" => Some(LeaderboardMetric::Wins),
+            "winrate" => Some(LeaderboardMetric::WinRate),
+            "captained" => Some(LeaderboardMetric::TimesCaptained),
+            _ => None,
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            LeaderboardMetric::GamesPlayed => "Games played",
+            LeaderboardMetric::Wins => "Wins",
+            LeaderboardMetric::WinRate => "Win rate",
+            LeaderboardMetric::TimesCaptained => "Times captained",
+        }
+    }
+
+    /// Render the value of this metric for a leaderboard entry.
+    pub fn format_value(&self, entry: &LeaderboardEntry) -> String {
+        match self {
+            LeaderboardMetric::GamesPlayed => entry.games_played.to_string(),
+            LeaderboardMetric::Wins => entry.wins.to_string(),
+            LeaderboardMetric::WinRate => format!(
+                "{:.1}% ({}/{})",
+                win_rate(entry) * 100.0,
+                entry.wins,
+                entry.games_decided
+            ),
+            LeaderboardMetric::TimesCaptained => entry.times_captained.to_string(),
+        }
+    }
+}
+
+fn win_rate(entry: &LeaderboardEntry) -> f64 {
+    if entry.games_decided == 0 {
+        return 0.0;
+    }
+    entry.wins as f64 / entry.games_decided as f64
+}
+
+/// Order leaderboard entries from best to worst by the provided metric,
+/// dropping entries that do not qualify to be ranked by it.
+pub fn rank(
+    mut entries: Vec<LeaderboardEntry>,
+    metric: LeaderboardMetric,
+) -> Vec<LeaderboardEntry> {
+    if metric == LeaderboardMetric::WinRate {
+        entries.retain(|e| e.games_decided >= WIN_RATE_MIN_GAMES);
+    }
+    entries.sort_by(|a, b| {
+        let ordering = match metric {
+            LeaderboardMetric::GamesPlayed => b.games_played.cmp(&a.games_played),
+            LeaderboardMetric::Wins => b.wins.cmp(&a.wins),
+            LeaderboardMetric::WinRate => win_rate(b).total_cmp(&win_rate(a)),
+            LeaderboardMetric::TimesCaptained => b.times_captained.cmp(&a.times_captained),
+        };
+        // break ties by the number of games played, then deterministically by user id
+        ordering
+            .then(b.games_played.cmp(&a.games_played))
+            .then(a.user_id.cmp(&b.user_id))
+    });
+    entries
+}
+
+/// Everything needed to render (and page through) a leaderboard.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LeaderboardQuery {
+    pub game_mode_label: String,
+    pub metric: LeaderboardMetric,
+    /// Zero-based page number
+    pub page: usize,
+    pub season: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl LeaderboardQuery {
+    /// Encode this query into a custom id for a page button, if it fits in Discord's limit.
+    /// A long season name and game mode label together can be too long.
+    ///
+    /// The game mode label goes last, since it is the only part which might contain the separator.
+    /// Season names are not allowed to contain it.
+    pub fn to_custom_id(&self, page: usize) -> Option<String> {
+        let format_date = |date: Option<NaiveDate>| match date {
+            Some(date) => date.format("%Y-%m-%d").to_string(),
+            None => String::new(),
+        };
+        let custom_id = format!(
+            "{}|{}|{}|{}|{}|{}|{}",
+            BUTTON_ID_PREFIX,
+            self.metric.as_str(),
+            page,
+            self.season.as_deref().unwrap_or_default(),
+            format_date(self.from),
+            format_date(self.to),
+            self.game_mode_label
+        );
+        match custom_id.chars().count() <= MAX_CUSTOM_ID_LENGTH {
+            true => Some(custom_id),
+            false => None,
+        }
+    }
+
+    /// Decode a query from the custom id of a page button.
+    pub fn from_custom_id(custom_id: &str) -> Option<Self> {
+        let parts = custom_id.splitn(7, '|').collect::<Vec<&str>>();
+        if parts.len() != 7 || parts[0] != BUTTON_ID_PREFIX {
+            return None;
+        }
+        let parse_date = |value: &str| match value {
+            "" => Ok(None),
+            value => NaiveDate::parse_from_str(value, "%Y-%m-%d").map(Some),
+        };
+        Some(LeaderboardQuery {
+            metric: LeaderboardMetric::from_value(parts[1])?,
+            page: parts[2].parse().ok()?,
+            season: match parts[3] {
+                "" => None,
+                season => Some(season.to_string()),
+            },
+            from: parse_date(parts[4]).ok()?,
+            to: parse_date(parts[5]).ok()?,
+            game_mode_label: parts[6].to_string(),
+        })
+    }
+}
+
+/// Key which identifies a set of cached leaderboard entries within a guild.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    game_mode_label: String,
+    season: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+/// Leaderboard entries which have already been aggregated, so paging through a
+/// leaderboard does not repeat the aggregation. Entries of a guild are invalidated
+/// whenever a [`crate::db::model::Pug`] is completed for it.
+pub struct LeaderboardCache;
+impl TypeMapKey for LeaderboardCache {
+    type Value = Arc<RwLock<HashMap<GuildId, HashMap<CacheKey, Vec<LeaderboardEntry>>>>>;
+}
+
+/// Get the entries of a leaderboard ranked by the query's metric,
+/// aggregating them only if they are not cached already.
+pub async fn get_ranked_entries(
+    ctx: &Context,
+    db: Database,
+    guild_id: GuildId,
+    query: &LeaderboardQuery,
+) -> Result<Vec<LeaderboardEntry>, mongodb::error::Error> {
+    let cache = {
+        let data = ctx.data.read().await;
+        data.get::<LeaderboardCache>()
+            .expect("Expected the leaderboard cache to be available for use")
+            .clone()
+    };
+    let key = CacheKey {
+        game_mode_label: query.game_mode_label.clone(),
+        season: query.season.clone(),
+        from: query.from,
+        to: query.to,
+    };
+
+    let cached = cache
+        .read()
+        .await
+        .get(&guild_id)
+        .and_then(|guild_cache| guild_cache.get(&key).cloned());
+
+    let entries = match cached {
+        Some(entries) => entries,
+        None => {
+            // the end date is inclusive, so the range extends to the start of the following day
+            let entries = db::read::get_leaderboard_entries(
+                db,
+                &query.game_mode_label,
+                query.season.as_deref(),
+                query.from.map(start_of_day),
+                query.to.and_then(|to| to.succ_opt()).map(start_of_day),
+            )
+            .await?;
+            cache
+                .write()
+                .await
+                .entry(guild_id)
+                .or_default()
+                .insert(key, entries.clone());
+            entries
+        }
+    };
+
+    Ok(rank(entries, query.metric))
+}
+
+/// Discard all cached leaderboards of a guild.
+pub async fn invalidate(ctx: &Context, guild_id: GuildId) {
+    let data = ctx.data.read().await;
+    if let Some(cache) = data.get::<LeaderboardCache>() {
+        cache.write().await.remove(&guild_id);
+    }
+}
+
+/// Build the previous/next page buttons for a leaderboard.
+///
+/// There are none if the query does not fit in their custom ids, in which case
+/// other pages can still be shown with the `page` option of /leaderboard.
+pub fn page_buttons(query: &LeaderboardQuery, page_count: usize) -> Vec<CreateActionRow> {
+    let (Some(previous_id), Some(next_id)) = (
+        query.to_custom_id(query.page.saturating_sub(1)),
+        query.to_custom_id(query.page + 1),
+    ) else {
+        return Vec::default();
+    };
+    let previous = CreateButton::new(previous_id)
+        .label("Previous")
+        .disabled(query.page == 0);
+    let next = CreateButton::new(next_id)
+        .label("Next")
+        .disabled(query.page + 1 >= page_count);
+    vec![CreateActionRow::Buttons(vec![previous, next])]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(game_mode_label: &str, season: Option<&str>) -> LeaderboardQuery {
+        LeaderboardQuery {
+            game_mode_label: game_mode_label.to_string(),
+            metric: LeaderboardMetric::TimesCaptained,
+            page: 0,
+            season: season.map(str::to_string),
+            from: NaiveDate::from_ymd_opt(2024, 1, 1),
+            to: NaiveDate::from_ymd_opt(2024, 12, 31),
+        }
+    }
+
+    #[test]
+    fn a_query_survives_its_custom_id() {
+        let query = query("ctf|2v2", Some("Spring 2024"));
+        let custom_id = query.to_custom_id(3).unwrap();
+        assert_eq!(
+            LeaderboardQuery::from_custom_id(&custom_id),
+            Some(LeaderboardQuery { page: 3, ..query })
+        );
+    }
+
+    #[test]
+    fn custom_ids_never_exceed_the_discord_limit() {
+        // the longest season name /season start accepts, with both dates set,
+        // leaves room for a label of 3 characters
+        let season = "s".repeat(50);
+        let fits = query(&"l".repeat(3), Some(&season));
+        let custom_id = fits.to_custom_id(9).unwrap();
+        assert_eq!(custom_id.chars().count(), MAX_CUSTOM_ID_LENGTH);
+
+        let too_long = query(&"l".repeat(4), Some(&season));
+        assert_eq!(too_long.to_custom_id(9), None);
+        // characters are counted, not bytes
+        assert!(query(&"é".repeat(3), Some(&season))
+            .to_custom_id(9)
+            .is_some());
+    }
+
+    #[test]
+    fn queries_too_long_for_a_custom_id_get_no_page_buttons() {
+        let season = "s".repeat(50);
+        assert!(page_buttons(&query(&"l".repeat(100), Some(&season)), 3).is_empty());
+        assert_eq!(page_buttons(&query("ctf", Some(&season)), 3).len(), 1);
+    }
+}
@@ -0,0 +1,400 @@
+//! Map votes: once the teams of a pug are picked, its players vote on the map in the pug's
+//! thread, choosing from the game mode's map pool (managed with /map).
+//!
+//! The maps of the game mode's most recent pugs are left out, as long as that leaves any.
+//! A vote ends after `pugs.map_vote_secs`, and ties (including a vote nobody voted in) are
+//! broken at random. Votes which were still running when the bot stopped are ended by a job.
+
+use std::collections::HashMap;
+
+use anyhow::Context as AnyhowContext;
+use chrono::{Duration, Utc};
+use itertools::Itertools;
+use mongodb::Database;
+use rand::seq::SliceRandom;
+use serenity::builder::{CreateActionRow, CreateButton, CreateMessage, EditMessage};
+use serenity::client::Context;
+use serenity::model::application::ButtonStyle;
+use serenity::model::id::{ChannelId, MessageId};
+use serenity::utils::MessageBuilder;
+use tracing::error;
+
+use crate::db;
+use crate::db::model::{MapVote, Pug};
+
+/// Prefix of the custom id of map vote buttons.
+pub const BUTTON_ID_PREFIX: &str = "map_vote";
+
+/// Discord allows up to 5 rows of 5 buttons, one for each map.
+const MAX_CANDIDATES: usize = 25;
+const BUTTONS_PER_ROW: usize = 5;
+
+/// Encode the position of a button's map among the candidates into its custom id.
+/// The pug is the one of the thread the button was posted in.
+pub fn to_custom_id(candidate_index: usize) -> String {
+    format!("{}|{}", BUTTON_ID_PREFIX, candidate_index)
+}
+
+/// Decode the position of a button's map among the candidates from its custom id.
+pub fn from_custom_id(custom_id: &str) -> Option<usize> {
+    match custom_id.split_once('|')? {
+        (BUTTON_ID_PREFIX, candidate_index) => candidate_index.parse().ok(),
+        _ => None,
+    }
+}
+
+/// The maps to vote on: the map pool without recently played maps (unless that leaves none),
+/// and no more than there can be buttons for.
+pub fn candidates(pool: &[String], recent: &[String]) -> Vec<String> {
+    let mut candidates = pool
+        .iter()
+        .filter(|map| !recent.iter().any(|played| played.eq_ignore_ascii_case(map)))
+        .cloned()
+        .collect::<Vec<String>>();
+    if candidates.is_empty() {
+        candidates = pool.to_vec();
+    }
+    if candidates.len() > MAX_CANDIDATES {
+        candidates.shuffle(&mut rand::thread_rng());
+        candidates.truncate(MAX_CANDIDATES);
+    }
+    candidates
+}
+
+/// How many votes each candidate got, in the order of the candidates.
+pub fn tally(vote: &MapVote) -> Vec<(&String, usize)> {
+    vote.candidates
+        .iter()
+        .map(|map| {
+            (
+                map,
+                vote.votes.values().filter(|voted| *voted == map).count(),
+            )
+        })
+        .collect()
+}
+
+/// The map with the most votes, with ties broken at random.
+pub fn winner(vote: &MapVote) -> Option<String> {
+    let tally = tally(vote);
+    let most_votes = tally.iter().map(|(_, votes)| *votes).max()?;
+    tally
+        .into_iter()
+        .filter(|(_, votes)| *votes == most_votes)
+        .map(|(map, _)| map)
+        .collect::<Vec<&String>>()
+        .choose(&mut rand::thread_rng())
+        .map(|map| map.to_string())
+}
+
+/// The message of a map vote: the votes so far and when voting ends, or the chosen map.
+pub fn render(game_mode: &str, vote: &MapVote, chosen: Option<&str>) -> String {
+    let mut message = MessageBuilder::default();
+    match chosen {
+        Some(map) => message
+            .push("Map vote for ")
+            .push_bold(game_mode)
+            .push(" ended: ")
+            .push_bold_line(map),
+        None => message
+            .push("Vote for the map of ")
+            .push_bold(game_mode)
+            .push_line(format!(", voting ends <t:{}:R>", vote.ends.timestamp())),
+    };
+    message.push(
+        tally(vote)
+            .into_iter()
+            .map(|(map, votes)| format!("{}: {}", map, votes))
+            .join(" :small_blue_diamond: "),
+    );
+    message.build()
+}
+
+fn buttons(vote: &MapVote) -> Vec<CreateActionRow> {
+    vote.candidates
+        .iter()
+        .enumerate()
+        .chunks(BUTTONS_PER_ROW)
+        .into_iter()
+        .map(|maps| {
+            CreateActionRow::Buttons(
+                maps.map(|(candidate_index, map)| {
+                    CreateButton::new(to_custom_id(candidate_index))
+                        .label(map)
+                        .style(ButtonStyle::Primary)
+                })
+                .collect(),
+            )
+        })
+        .collect()
+}
+
+/// Start a map vote in the thread of a completed pug, if its game mode has a map pool.
+/// With only one map to choose from, that map is chosen right away.
+pub async fn start(ctx: &Context, db: Database, pug: &Pug) -> anyhow::Result<()> {
+    let game_mode = match db::read::find_game_mode(db.clone(), &pug.game_mode)
+        .await
+        .context("Failed to read the game mode of a completed pug")?
+    {
+        Some(game_mode) if !game_mode.maps.is_empty() => game_mode,
+        _ => return Ok(()),
+    };
+
+    let config = crate::config::get(ctx).await;
+    // a limit of 0 would mean no limit
+    let recent = if config.pugs.recent_maps_excluded > 0 {
+        db::read::get_recent_maps(db.clone(), &pug.game_mode, config.pugs.recent_maps_excluded)
+            .await
+            .context("Failed to read recently played maps")?
+    } else {
+        Vec::default()
+    };
+    let candidates = candidates(&game_mode.maps, &recent);
+
+    let thread = ChannelId::from(pug.thread_channel_id as u64);
+    if let [map] = candidates.as_slice() {
+        if db::write::set_pug_map(db, pug.thread_channel_id, map)
+            .await
+            .context("Failed to save the map of a pug")?
+            .is_some()
+        {
+            announce(ctx, thread, map).await?;
+        }
+        return Ok(());
+    }
+
+    let vote = MapVote {
+        candidates,
+        votes: HashMap::default(),
+        ends: Utc::now() + Duration::seconds(config.pugs.map_vote_secs),
+        message_id: None,
+    };
+    if db::write::start_map_vote(db.clone(), pug.thread_channel_id, &vote)
+        .await
+        .context("Failed to save a map vote")?
+        .is_none()
+    {
+        return Ok(());
+    }
+
+    let message = thread
+        .send_message(
+            &ctx.http,
+            CreateMessage::new()
+                .content(render(&pug.game_mode, &vote, None))
+                .components(buttons(&vote)),
+        )
+        .await
+        .context("Failed to post a map vote")?;
+    db::write::set_map_vote_message(db.clone(), pug.thread_channel_id, message.id.get())
+        .await
+        .context("Failed to save the message of a map vote")?;
+
+    let ctx = ctx.clone();
+    let thread_channel_id = pug.thread_channel_id;
+    let vote_duration = std::time::Duration::from_secs(config.pugs.map_vote_secs as u64);
+    tokio::spawn(async move {
+        tokio::time::sleep(vote_duration).await;
+        if let Err(err) = end(&ctx, db, thread_channel_id).await {
+            error!(
+                "Failed to end the map vote of pug {}: {:?}",
+                thread_channel_id, err
+            );
+        }
+    });
+    Ok(())
+}
+
+/// End the map vote of a pug, if it is still running, and announce the chosen map.
+pub async fn end(ctx: &Context, db: Database, thread_channel_id: i64) -> anyhow::Result<()> {
+    let pug = db::read::find_pug(db.clone(), thread_channel_id)
+        .await
+        .context("Tried to fetch the pug of a map vote")?;
+    let (game_mode, vote) = match pug {
+        Some(Pug {
+            game_mode,
+            map_vote: Some(vote),
+            ..
+        }) => (game_mode, vote),
+        _ => return Ok(()),
+    };
+
+    let map = winner(&vote).context("A map vote was held without any maps")?;
+    // the vote may have been ended by the job catching votes left running
+    if db::write::set_pug_map(db, thread_channel_id, &map)
+        .await
+        .context("Failed to save the map chosen by vote")?
+        .is_none()
+    {
+        return Ok(());
+    }
+
+    let thread = ChannelId::from(thread_channel_id as u64);
+    announce(ctx, thread, &map).await?;
+    if let Some(message_id) = vote.message_id {
+        thread
+            .edit_message(
+                &ctx.http,
+                MessageId::new(message_id as u64),
+                EditMessage::new()
+                    .content(render(&game_mode, &vote, Some(&map)))
+                    .components(Vec::default()),
+            )
+            .await
+            .context("Failed to show the result of a map vote")?;
+    }
+    Ok(())
+}
+
+async fn announce(ctx: &Context, thread: ChannelId, map: &str) -> anyhow::Result<()> {
+    thread
+        .say(
+            &ctx.http,
+            MessageBuilder::default()
+                .push("Map: ")
+                .push_bold(map)
+                .build(),
+        )
+        .await
+        .context("Failed to announce the map of a pug")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn maps(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// A vote on `candidates`, with each voter's choice.
+    fn vote(candidates: &[&str], votes: &[(&str, &str)]) -> MapVote {
+        MapVote {
+            candidates: maps(candidates),
+            votes: votes
+                .iter()
+                .map(|(voter, map)| (voter.to_string(), map.to_string()))
+                .collect(),
+            ends: Utc::now(),
+            message_id: None,
+        }
+    }
+
+    #[test]
+    fn custom_ids_round_trip() {
+        for candidate_index in [0, 7, MAX_CANDIDATES - 1] {
+            assert_eq!(
+                from_custom_id(&to_custom_id(candidate_index)),
+                Some(candidate_index)
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_custom_ids_are_rejected() {
+        for custom_id in [
+            "",
+            "map_vote",
+            "map_vote|",
+            "map_vote|first",
+            "map_vote|-1",
+            "map_vote|1|2",
+            "result_report|1",
+            "MAP_VOTE|1",
+        ] {
+            assert_eq!(from_custom_id(custom_id), None, "{}", custom_id);
+        }
+    }
+
+    #[test]
+    fn recently_played_maps_are_left_out_ignoring_case() {
+        assert_eq!(
+            candidates(
+                &maps(&["Dust", "Inferno", "Nuke"]),
+                &maps(&["dust", "NUKE"])
+            ),
+            maps(&["Inferno"])
+        );
+    }
+
+    #[test]
+    fn whole_pool_when_every_map_was_played_recently() {
+        let pool = maps(&["Dust", "Inferno"]);
+        assert_eq!(candidates(&pool, &maps(&["inferno", "dust"])), pool);
+    }
+
+    #[test]
+    fn empty_pool_has_no_candidates() {
+        assert!(candidates(&[], &maps(&["Dust"])).is_empty());
+    }
+
+    #[test]
+    fn never_more_candidates_than_buttons() {
+        let pool = (0..40)
+            .map(|n| format!("map{}", n))
+            .collect::<Vec<String>>();
+        let chosen = candidates(&pool, &[]);
+        assert_eq!(chosen.len(), MAX_CANDIDATES);
+        assert!(chosen.iter().all(|map| pool.contains(map)));
+        assert!(chosen.iter().all_unique());
+    }
+
+    #[test]
+    fn tally_counts_votes_in_candidate_order() {
+        let vote = vote(
+            &["Dust", "Inferno", "Nuke"],
+            &[("1", "Nuke"), ("2", "Dust"), ("3", "Nuke")],
+        );
+        assert_eq!(
+            tally(&vote)
+                .into_iter()
+                .map(|(map, votes)| (map.as_str(), votes))
+                .collect::<Vec<(&str, usize)>>(),
+            vec![("Dust", 1), ("Inferno", 0), ("Nuke", 2)]
+        );
+    }
+
+    #[test]
+    fn most_votes_wins() {
+        let vote = vote(
+            &["Dust", "Inferno", "Nuke"],
+            &[("1", "Nuke"), ("2", "Dust"), ("3", "Nuke")],
+        );
+        assert_eq!(winner(&vote), Some("Nuke".to_string()));
+    }
+
+    #[test]
+    fn ties_are_broken_among_the_tied_maps_only() {
+        let vote = vote(
+            &["Dust", "Inferno", "Nuke"],
+            &[("1", "Nuke"), ("2", "Dust")],
+        );
+        for _ in 0..50 {
+            let map = winner(&vote).unwrap();
+            assert!(map == "Nuke" || map == "Dust", "{}", map);
+        }
+    }
+
+    #[test]
+    fn any_candidate_can_win_when_nobody_voted() {
+        let vote = vote(&["Dust", "Inferno"], &[]);
+        for _ in 0..50 {
+            assert!(vote.candidates.contains(&winner(&vote).unwrap()));
+        }
+    }
+
+    #[test]
+    fn votes_for_maps_which_are_not_candidates_are_not_counted() {
+        let vote = vote(
+            &["Dust", "Inferno"],
+            &[("1", "Nuke"), ("2", "Nuke"), ("3", "Dust")],
+        );
+        assert_eq!(winner(&vote), Some("Dust".to_string()));
+    }
+
+    #[test]
+    fn no_winner_without_candidates() {
+        assert_eq!(winner(&vote(&[], &[])), None);
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use serenity::client::Context;
+use serenity::model::id::GuildId;
+use serenity::prelude::TypeMapKey;
+use tracing::error;
+
+/// Prometheus metrics of the bot, served at `/metrics` by the [status server](super::status_server).
+pub struct Metrics {
+    registry: Registry,
+    commands: IntCounterVec,
+    command_duration: HistogramVec,
+    queue_size: IntGaugeVec,
+    pugs_formed: IntCounterVec,
+    job_runs: IntCounterVec,
+    discord_http_errors: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let commands = IntCounterVec::new(
+            Opts::new("rusty_bot_commands_total", "Commands handled"),
+            &["command", "outcome"],
+        )
+        .unwrap();
+        let command_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "rusty_bot_command_duration_seconds",
+                "Time taken to handle a command",
+            ),
+            &["command"],
+        )
+        .unwrap();
+        let queue_size = IntGaugeVec::new(
+            Opts::new(
+                "rusty_bot_queue_size",
+                "Players in the queue of a game mode",
+            ),
+            &["guild", "game_mode"],
+        )
+        .unwrap();
+        let pugs_formed = IntCounterVec::new(
+            Opts::new(
+                "rusty_bot_pugs_formed_total",
+                "Pugs started from a filled queue",
+            ),
+            &["guild", "game_mode"],
+        )
+        .unwrap();
+        let job_runs = IntCounterVec::new(
+            Opts::new("rusty_bot_job_runs_total", "Runs of background jobs"),
+            &["job", "outcome"],
+        )
+        .unwrap();
+        let discord_http_errors = IntCounterVec::new(
+            Opts::new(
+                "rusty_bot_discord_http_errors_total",
+                "Failed requests to the Discord HTTP API",
+            ),
+            &["status"],
+        )
+        .unwrap();
+
+        // registering can only fail on conflicting names, and the names above are distinct
+        let registry = Registry::new();
+        registry.register(Box::new(commands.clone())).unwrap();
+        registry
+            .register(Box::new(command_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(queue_size.clone())).unwrap();
+        registry.register(Box::new(pugs_formed.clone())).unwrap();
+        registry.register(Box::new(job_runs.clone())).unwrap();
+        registry
+            .register(Box::new(discord_http_errors.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            commands,
+            command_duration,
+            queue_size,
+            pugs_formed,
+            job_runs,
+            discord_http_errors,
+        }
+    }
+
+    /// Replace the queue sizes of a guild with the given `(game mode, players)` pairs.
+    ///
+    /// Sizes are taken from the database when metrics are scraped, rather than tracked on
+    /// every join and leave.
+    pub fn set_queue_sizes<'a, I>(&self, guild_id: GuildId, sizes: I)
+    where
+        I: IntoIterator<Item = (&'a str, usize)>,
+    {
+        let guild = guild_id.to_string();
+        for (game_mode, size) in sizes {
+            self.queue_size
+                .with_label_values(&[&guild, game_mode])
+                .set(size as i64);
+        }
+    }
+
+    /// Forget all queue sizes, so game modes or guilds which are gone are not reported.
+    pub fn clear_queue_sizes(&self) {
+        self.queue_size.reset();
+    }
+
+    /// All metrics, in the Prometheus text format.
+    pub fn render(&self) -> String {
+        match TextEncoder::new().encode_to_string(&self.registry.gather()) {
+            Ok(text) => text,
+            Err(err) => {
+                error!("Failed to encode metrics: {:?}", err);
+                String::default()
+            }
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct MetricsRef;
+impl TypeMapKey for MetricsRef {
+    type Value = Arc<Metrics>;
+}
+
+async fn get(ctx: &Context) -> Option<Arc<Metrics>> {
+    let data = ctx.data.read().await;
+    data.get::<MetricsRef>().cloned()
+}
+
+fn outcome(succeeded: bool) -> &'static str {
+    if succeeded {
+        "ok"
+    } else {
+        "error"
+    }
+}
+
+/// Count a handled command, and how long handling it took.
+pub async fn observe_command(ctx: &Context, command: &str, succeeded: bool, elapsed: Duration) {
+    if let Some(metrics) = get(ctx).await {
+        metrics
+            .commands
+            .with_label_values(&[command, outcome(succeeded)])
+            .inc();
+        metrics
+            .command_duration
+            .with_label_values(&[command])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+pub async fn pug_formed(ctx: &Context, guild_id: GuildId, game_mode: &str) {
+    if let Some(metrics) = get(ctx).await {
+        metrics
+            .pugs_formed
+            .with_label_values(&[&guild_id.to_string(), game_mode])
+            .inc();
+    }
+}
+
+pub async fn job_run(ctx: &Context, job: &str, succeeded: bool) {
+    if let Some(metrics) = get(ctx).await {
+        metrics
+            .job_runs
+            .with_label_values(&[job, outcome(succeeded)])
+            .inc();
+    }
+}
+
+/// Count `err` if it is an error response from (or failed request to) the Discord HTTP API.
+pub async fn discord_error(ctx: &Context, err: &serenity::Error) {
+    let status = match err {
+        serenity::Error::Http(http_err) => match http_err.status_code() {
+            Some(status) => status.as_u16().to_string(),
+            None => "none".to_string(),
+        },
+        _ => return,
+    };
+    if let Some(metrics) = get(ctx).await {
+        metrics
+            .discord_http_errors
+            .with_label_values(&[&status])
+            .inc();
+    }
+}
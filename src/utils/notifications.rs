@@ -0,0 +1,164 @@
+use anyhow::Context as AnyhowContext;
+use chrono::{Duration, Timelike, Utc};
+use mongodb::Database;
+use serenity::client::Context;
+use serenity::model::channel::GuildChannel;
+use serenity::model::id::UserId;
+use serenity::utils::MessageBuilder;
+use tracing::{instrument, warn};
+
+use crate::db;
+use crate::db::model::{GameMode, NotificationDelivery, QueueNotification};
+
+/// Minimum number of minutes between two alerts to the same subscriber for the same game mode.
+pub const NOTIFICATION_COOLDOWN_MINS: i64 = 30;
+
+/// Whether `hour` falls within the quiet hours window `[start, end)`.
+/// The window may wrap past midnight, e.g. 22 to 6.
+pub fn is_within_quiet_hours(start: i64, end: i64, hour: i64) -> bool {
+    if start == end {
+        return false;
+    }
+    if start < end {
+        start <= hour && hour < end
+    } else {
+        hour >= start || hour < end
+    }
+}
+
+/// Whether a subscriber should be alerted right now, honoring their quiet hours
+/// and the cooldown since their last alert.
+fn is_due(subscription: &QueueNotification) -> bool {
+    let now = Utc::now();
+
+    if let (Some(start), Some(end)) = (subscription.quiet_hours_start, subscription.quiet_hours_end)
+    {
+        if is_within_quiet_hours(start, end, now.hour() as i64) {
+            return false;
+        }
+    }
+
+    match subscription.last_notified {
+        Some(last_notified) => now - last_notified >= Duration::minutes(NOTIFICATION_COOLDOWN_MINS),
+        None => true,
+    }
+}
+
+/// Alert users subscribed to a game mode's queue reaching its current size, or any size
+/// it grew past since it held `previous_size` players.
+///
+/// Users who are already in the queue are skipped. Subscribers who prefer
+/// a mention are grouped into a single message in the pug channel.
+///
+/// Intended to be spawned into a new thread, not awaited, so a slow or failing
+/// alert never holds up a queue join.
+#[instrument(skip(ctx, db, pug_channel))]
+pub async fn notify_queue_subscribers(
+    ctx: Context,
+    db: Database,
+    pug_channel: GuildChannel,
+    game_mode: GameMode,
+    previous_size: usize,
+    queued_user_ids: Vec<i64>,
+) {
+    if let Err(err) = notify_queue_subscribers_helper(
+        &ctx,
+        db,
+        &pug_channel,
+        &game_mode,
+        previous_size,
+        &queued_user_ids,
+    )
+    .await
+    {
+        warn!(
+            "Failed to alert subscribers of the {} queue:\n{:?}",
+            game_mode.label, err
+        );
+    }
+}
+
+async fn notify_queue_subscribers_helper(
+    ctx: &Context,
+    db: Database,
+    pug_channel: &GuildChannel,
+    game_mode: &GameMode,
+    previous_size: usize,
+    queued_user_ids: &[i64],
+) -> anyhow::Result<()> {
+    let queue_size = queued_user_ids.len() as i64;
+    let subscriptions = db::read::get_queue_notification_subscribers(
+        db.clone(),
+        &game_mode.label,
+        previous_size as i64,
+        queue_size,
+    )
+    .await
+    .context("Failed to read queue notification subscriptions")?;
+
+    let due_subscriptions = subscriptions
+        .into_iter()
+        .filter(|s| !queued_user_ids.contains(&s.user_id))
+        .filter(is_due)
+        .collect::<Vec<QueueNotification>>();
+
+    if due_subscriptions.is_empty() {
+        return Ok(());
+    }
+
+    let alert = format!(
+        "**{}** is {}/{} - use /join in {} to get in",
+        game_mode.label, queue_size, game_mode.player_count, pug_channel.name
+    );
+
+    let mut notified: Vec<i64> = Vec::default();
+    let mut mentioned: Vec<i64> = Vec::default();
+    let mut mentions = MessageBuilder::default();
+    for subscription in &due_subscriptions {
+        let user_id = UserId::from(subscription.user_id as u64);
+        match subscription.delivery {
+            NotificationDelivery::DirectMessage => {
+                let sent = match user_id.create_dm_channel(&ctx.http).await {
+                    Ok(dm_channel) => dm_channel.say(&ctx.http, &alert).await.map(|_| ()),
+                    Err(err) => Err(err),
+                };
+                match sent {
+                    Ok(_) => notified.push(subscription.user_id),
+                    Err(err) => warn!("Failed to DM queue alert to {}: {}", user_id, err),
+                }
+            }
+            NotificationDelivery::ChannelMention => {
+                mentions.mention(&user_id).push(" ");
+                mentioned.push(subscription.user_id);
+            }
+        }
+    }
+
+    let mentions = mentions.build();
+    let mention_sent = match mentions.is_empty() {
+        true => Ok(()),
+        false => {
+            let announcement = MessageBuilder::new()
+                .push_line(format!(
+                    "**{}** is {}/{}",
+                    game_mode.label, queue_size, game_mode.player_count
+                ))
+                .push(mentions)
+                .build();
+            pug_channel.say(&ctx.http, announcement).await.map(|_| ())
+        }
+    };
+    if mention_sent.is_ok() {
+        notified.extend(mentioned);
+    }
+
+    // users who were sent a DM are marked even if mentioning the others failed,
+    // so they are not alerted again before the cooldown is over
+    if !notified.is_empty() {
+        db::write::mark_queue_notifications_sent(db, &game_mode.label, &notified)
+            .await
+            .context("Failed to record the time queue alerts were sent")?;
+    }
+
+    mention_sent.context("Failed to mention queue alert subscribers in the pug channel")
+}
@@ -1,35 +1,20 @@
 use futures::future::join_all;
-use itertools::Itertools;
 use mongodb::Client;
 use std::sync::Arc;
 
 use serenity::client::Context;
-use serenity::model::id::{CommandId, GuildId};
+use serenity::model::id::{ChannelId, GuildId};
 use tokio::task::JoinHandle;
-use tokio::time::{interval, Duration};
 use tracing::{error, info, instrument, warn};
 
-use crate::db::model::GuildCommand;
-use crate::db::write::clear_guild_commands;
-use crate::DbClientRef;
+use crate::utils::application_commands;
+use crate::utils::ops::{self, OpsEvent};
 
-/// For each guild, check for presence of guild application commands created by this bot.
-/// If there aren't suitable existing commands, create a `/help` command
+/// For each guild, bring its database up to date and sync its guild application commands.
 #[instrument(skip(ctx, guild_ids))]
 pub async fn inspect_guild_commands(ctx: Arc<Context>, guild_ids: Vec<GuildId>) {
-    let mut interval = interval(Duration::from_secs(5));
-
-    // loop/block until the Client is available in storage
-    let db_client = loop {
-        let data = ctx.data.read().await;
-        match data.get::<DbClientRef>() {
-            Some(c) => break c.clone(),
-            None => {
-                info!("Waiting for database client ready");
-                interval.tick().await;
-            }
-        }
-    };
+    // block until the Client is available in storage
+    let db_client = crate::db::readiness::client(&ctx).await;
 
     let mut join_handles: Vec<JoinHandle<Result<GuildId, crate::error::Error>>> = Vec::default();
     let mut ordered_guild_names: Vec<String> = Vec::default();
@@ -63,7 +48,11 @@ pub async fn inspect_guild_commands(ctx: Arc<Context>, guild_ids: Vec<GuildId>)
     info!("Inspections complete!");
 }
 
-/// Register /help command as necessary for guilds
+/// Bring the guild's database up to date, then sync its commands with its state
+///
+/// Migrations only log what they would change if `migration.dry_run` is set in the config,
+/// in which case a guild with pending migrations is left there, without syncing its commands
+/// or resuming its pug.
 ///
 /// TODO: ensure that guilds marked as disabled don't have/get any guild commands registered.
 pub async fn inspect_and_maybe_update_db(
@@ -73,60 +62,53 @@ pub async fn inspect_and_maybe_update_db(
 ) -> Result<GuildId, crate::error::Error> {
     let db = db_client.database(&guild_id.to_string());
 
-    let current_commands = guild_id.get_commands(&ctx.http).await?;
-    let mut saved_commands: Vec<GuildCommand> = crate::db::read::get_commands(db.clone()).await?;
-
-    // if there is a mismatch between the commands saved in the database vs the ones currently
-    // registered with discord, clear out the guild's commands
-    // We do this because it suggests the arrangement of registered commands in the database
-    // has grown apart from what the code expects.
-    // Thus the code is likely faulty and should not be allowed to quietly continue corrupting data
-    // !FIXME: the bot saves duplicate records of the same command for some reason
-
-    let commands_match = saved_commands.len() == current_commands.len()
-        && current_commands.iter().all(|current| {
-            saved_commands
-                .iter()
-                .any(|saved| saved.command_id as u64 == current.id.get())
-        });
+    let dry_run = crate::config::get(&ctx).await.migration.dry_run;
+    let schema_version = crate::db::migration::migrate(db.clone(), dry_run).await?;
+    info!(
+        "Database of {} is at schema version {}",
+        guild_id, schema_version
+    );
+    if dry_run {
+        // commands and pugs of an out of date database would be read with the current models
+        let current_version = crate::db::read::get_schema_version(db.clone())
+            .await?
+            .map_or(0, |schema_version| schema_version.version);
+        if current_version < schema_version {
+            return Err(crate::error::Error::PendingMigrations {
+                current: current_version,
+                pending: schema_version,
+            });
+        }
+    } else {
+        crate::db::index::ensure_indexes(db.clone()).await?;
+    }
 
-    if !commands_match {
-        let a_c = current_commands
-            .iter()
-            .format_with(", ", |cmd, f| f(&format_args!("{} {}", cmd.name, cmd.id)));
-        let s_c = saved_commands.iter().format_with(", ", |cmd, f| {
-            f(&format_args!(
-                "{} {}",
-                cmd.name,
-                CommandId::from(cmd.command_id as u64)
-            ))
-        });
-        let output = format!(
-            "Mismatch in command set for {:?}\n\
-            Current Guild Application Commands: {}\n\
-            Commands Saved in DB: {}\n\
-            Clearing all existing commands from guild and database...",
-            &guild_id, a_c, s_c
-        );
+    // new commands are expected (e.g. /help for a new guild), but registered commands should
+    // already match the guild's state, unless they were changed while the bot was not running
+    // (or by a bug), so other differences are worth reporting
+    let diff = application_commands::sync(&ctx, guild_id, db.clone())
+        .await
+        .map_err(crate::error::Error::CommandSync)?;
+    if !diff.updated.is_empty() || !diff.deleted.is_empty() {
+        let output = format!("Commands of {:?} were out of sync\n{}", &guild_id, diff);
         warn!("{}", output);
-        // clear guild commands
-        guild_id.set_commands(&ctx.http, Vec::new()).await?;
-        // clear db also
-        clear_guild_commands(db.clone()).await?;
-        // and empty the vec that might contain old results
-        // from the db which we just ^ cleared
-        saved_commands.clear();
+        ops::report(&ctx, OpsEvent::CommandReset, output).await;
     }
 
-    if saved_commands.is_empty() {
-        // create /help command
-
-        let help_cmd = guild_id
-            .create_command(&ctx.http, crate::command_builder::base::build_help())
-            .await?;
-
-        // save in db
-        crate::db::write::register_guild_command(db, &help_cmd).await?;
+    // resume the auto captain countdown of a pug which was waiting for captains when the bot stopped
+    if let Some(pug) = crate::db::read::get_current_picking_session(db.clone()).await? {
+        if !pug.has_both_captains() {
+            info!(
+                "Resuming auto captain countdown for the pug in thread {}",
+                pug.thread_channel_id
+            );
+            tokio::spawn(crate::utils::captain::autopick_countdown(
+                (*ctx).clone(),
+                db,
+                ChannelId::from(pug.thread_channel_id as u64),
+                guild_id,
+            ));
+        }
     }
 
     Ok(guild_id)
@@ -0,0 +1,113 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::client::Context;
+use tracing::error;
+
+use crate::config;
+use crate::utils::metrics;
+
+/// Kinds of events worth telling the people running the bot about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpsEvent {
+    /// The bot connected to Discord
+    Startup,
+    /// The bot was added to a guild
+    GuildJoin,
+    /// The bot was removed from a guild
+    GuildLeave,
+    /// A background job ran into errors
+    JobFailure,
+    /// Onboarding found a guild's commands out of sync with its state and synced them
+    CommandReset,
+    /// System resource load exceeded its configured limits
+    Load,
+}
+
+impl OpsEvent {
+    pub const ALL: [OpsEvent; 6] = [
+        OpsEvent::Startup,
+        OpsEvent::GuildJoin,
+        OpsEvent::GuildLeave,
+        OpsEvent::JobFailure,
+        OpsEvent::CommandReset,
+        OpsEvent::Load,
+    ];
+
+    /// Name of the event kind, as used in the config.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OpsEvent::Startup => "startup",
+            OpsEvent::GuildJoin => "guild_join",
+            OpsEvent::GuildLeave => "guild_leave",
+            OpsEvent::JobFailure => "job_failure",
+            OpsEvent::CommandReset => "command_reset",
+            OpsEvent::Load => "load",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            OpsEvent::Startup => "Started",
+            OpsEvent::GuildJoin => "Joined Guild",
+            OpsEvent::GuildLeave => "Left Guild",
+            OpsEvent::JobFailure => "Job Failure",
+            OpsEvent::CommandReset => "Guild Commands Resynced",
+            OpsEvent::Load => "System Resource Load",
+        }
+    }
+}
+
+impl FromStr for OpsEvent {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        OpsEvent::ALL
+            .into_iter()
+            .find(|event| event.as_str() == value)
+            .ok_or_else(|| format!("unknown ops event kind `{}`", value))
+    }
+}
+
+/// Send a report of an event to the ops channel and recipients, if reporting that kind
+/// of event is enabled. Failing to deliver a report is logged, never fatal.
+pub async fn report<S: Into<String>>(ctx: &Context, event: OpsEvent, details: S) {
+    let config = config::get(ctx).await;
+    let config = &config.ops;
+    if !config.events.contains(&event) {
+        return;
+    }
+
+    // embed descriptions are limited to 4096 characters
+    let details = details.into().chars().take(4096).collect::<String>();
+    let message = CreateMessage::new()
+        .add_embed(CreateEmbed::new().title(event.title()).description(details));
+
+    if let Some(channel_id) = config.channel_id {
+        if let Err(err) = channel_id.send_message(&ctx.http, message.clone()).await {
+            error!(
+                "Failed to post {} report in ops channel: {:?}",
+                event.as_str(),
+                err
+            );
+            metrics::discord_error(ctx, &err).await;
+        }
+    }
+    for user_id in config.recipients.iter() {
+        let sent = match user_id.create_dm_channel(&ctx.http).await {
+            Ok(dm_channel) => dm_channel.send_message(&ctx.http, message.clone()).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = sent {
+            error!(
+                "Failed to send {} report to {}: {:?}",
+                event.as_str(),
+                user_id,
+                err
+            );
+            metrics::discord_error(ctx, &err).await;
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Premade parties: players who queue together, and are placed on the same team.
+//!
+//! A party joins queues as a unit, and only when the whole party fits. When its queue fills,
+//! the party is recorded on the pug, so a picked player brings their party mates along,
+//! and captains start out with theirs.
+
+use anyhow::{bail, Context as AnyhowContext};
+use mongodb::Database;
+use serenity::client::Context;
+use serenity::model::id::GuildId;
+use tracing::error;
+
+use crate::db;
+use crate::db::model::{Party, Pug, Team};
+use crate::utils::{application_commands, map_vote, transform};
+
+/// The parties among the players of a filled queue, as far as each fits the game mode.
+///
+/// Players who are on their own in the pug are left out.
+pub fn parties_among(parties: &[Party], players: &[i64], party_size_limit: usize) -> Vec<Vec<i64>> {
+    parties
+        .iter()
+        .map(|party| {
+            party
+                .members
+                .iter()
+                .filter(|member| players.contains(member))
+                .take(party_size_limit)
+                .copied()
+                .collect::<Vec<i64>>()
+        })
+        .filter(|members| members.len() > 1)
+        .collect()
+}
+
+/// Group items (e.g. queued players) by party, keeping the order in which each group
+/// first appears. Players who are not in a party are in a group of their own.
+pub fn group_by_party<T, F>(items: Vec<T>, parties: &[Vec<i64>], user_id_of: F) -> Vec<Vec<T>>
+where
+    F: Fn(&T) -> i64,
+{
+    let mut groups: Vec<(Option<usize>, Vec<T>)> = Vec::default();
+    for item in items {
+        let party = parties
+            .iter()
+            .position(|party| party.contains(&user_id_of(&item)));
+        match groups
+            .iter_mut()
+            .find(|(group_party, _)| party.is_some() && *group_party == party)
+        {
+            Some((_, group)) => group.push(item),
+            None => groups.push((party, vec![item])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// Add players to the teams of a pug being picked. If that leaves nothing to pick, everyone
+/// left goes to the team with room, the pug is completed, its voice channels set up and
+/// its map vote started.
+///
+/// Returns the updated pug, or `None` if it was changed by someone else in the meantime.
+pub async fn place_picks(
+    ctx: &Context,
+    guild_id: GuildId,
+    db: Database,
+    pug: &Pug,
+    picks: &[(i64, Team)],
+) -> anyhow::Result<Option<Pug>> {
+    let final_picks = match pug.final_picks(picks) {
+        Some(final_picks) => final_picks,
+        None => {
+            return db::write::pick_players(db, pug, picks)
+                .await
+                .context("Failed to save the players picked for a team");
+        }
+    };
+
+    let season = db::read::get_active_season(db.clone())
+        .await
+        .context("Failed to check for an active season")?
+        .map(|season| season.name);
+    let completed_pug = match db::write::complete_picking(db.clone(), pug, &final_picks, season)
+        .await
+        .context("Failed to save the final picks of a pug")?
+    {
+        Some(pug) => pug,
+        None => return Ok(None),
+    };
+
+    // removes /pick, /teams and /reset
+    application_commands::sync(ctx, guild_id, db.clone())
+        .await
+        .context("Failed to clean up picking commands of a completed pug")?;
+
+    let mut created_channels = Vec::default();
+    let completed_pug = match transform::set_up_completed_pug(
+        ctx,
+        db.clone(),
+        completed_pug,
+        &mut created_channels,
+    )
+    .await
+    {
+        Ok(completed_pug) => completed_pug,
+        Err(err) => {
+            // channels which were not recorded on the pug would never be cleaned up
+            for channel in created_channels {
+                if let Err(err) = channel.delete(&ctx.http).await {
+                    error!("Failed to delete voice channel {}: {:?}", channel, err);
+                }
+            }
+            return Err(err.context("Failed to set up voice channels for a completed pug"));
+        }
+    };
+    if completed_pug.voice_chat.is_none() {
+        bail!("A completed pug was set up without voice channels");
+    }
+
+    // the teams are final either way, so picking does not fail along with the vote
+    if let Err(err) = map_vote::start(ctx, db, &completed_pug).await {
+        error!(
+            "Failed to start the map vote of pug {}: {:?}",
+            completed_pug.thread_channel_id, err
+        );
+    }
+    Ok(Some(completed_pug))
+}
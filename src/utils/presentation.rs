@@ -0,0 +1,616 @@
+//! How queues and pug rosters are shown.
+//!
+//! Everything is rendered twice: as embeds (with team colours) for guild channels, and as
+//! plain text for direct messages, where embeds are not used. Rendering only takes
+//! already resolved data (names rather than user ids), so its output is deterministic.
+
+use anyhow::Context as AnyhowContext;
+use itertools::Itertools;
+use serenity::builder::{CreateEmbed, EditInteractionResponse};
+use serenity::client::Context;
+use serenity::model::application::CommandInteraction;
+use serenity::model::id::{ChannelId, UserId};
+use serenity::model::mention::Mentionable;
+use serenity::model::Colour;
+use serenity::utils::MessageBuilder;
+
+use crate::db::model::{GameMode, GameModeJoin, Pug, Team};
+use crate::utils::party;
+use crate::utils::transform::{self, QueuedPlayerInfo};
+
+pub const RED_TEAM_COLOUR: Colour = Colour::RED;
+pub const BLUE_TEAM_COLOUR: Colour = Colour::BLUE;
+/// For anything which belongs to neither team
+pub const NEUTRAL_COLOUR: Colour = Colour::BLURPLE;
+
+/// Discord's limits on embeds.
+const MAX_FIELDS: usize = 25;
+const MAX_FIELD_VALUE_LENGTH: usize = 1024;
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+
+/// Something rendered both as embeds and as plain text.
+pub struct Presentation {
+    pub embeds: Vec<CreateEmbed>,
+    pub text: String,
+}
+
+/// Respond to a command with a presentation: as embeds in a guild, or as text in a DM.
+///
+/// Returns the text content of the response, which is what command handlers return.
+pub async fn respond(
+    ctx: &Context,
+    interaction: &CommandInteraction,
+    presentation: Presentation,
+) -> anyhow::Result<String> {
+    if interaction.guild_id.is_none() {
+        return Ok(presentation.text);
+    }
+    interaction
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new().embeds(presentation.embeds),
+        )
+        .await
+        .context("Failed to attach embeds to the response")?;
+    // replaces the initial "Working on it..."
+    Ok(String::new())
+}
+
+fn team_title(team: Team) -> &'static str {
+    match team {
+        Team::Red => "🔴 Red Team",
+        Team::Blue => "🔵 Blue Team",
+    }
+}
+
+fn team_emoji(team: Team) -> &'static str {
+    match team {
+        Team::Red => "🔴",
+        Team::Blue => "🔵",
+    }
+}
+
+fn team_colour(team: Team) -> Colour {
+    match team {
+        Team::Red => RED_TEAM_COLOUR,
+        Team::Blue => BLUE_TEAM_COLOUR,
+    }
+}
+
+fn truncate(mut value: String, max_length: usize) -> String {
+    if value.chars().count() > max_length {
+        value = value.chars().take(max_length - 1).collect();
+        value.push('…');
+    }
+    value
+}
+
+/// A game mode and the players in its queue.
+pub struct QueueView {
+    pub game_mode: GameMode,
+    /// Queued players, with the members of a party grouped together
+    pub players: Vec<Vec<QueuedPlayerInfo>>,
+}
+
+impl QueueView {
+    /// Gather the players of a queue, resolving their names and grouping them by party.
+    pub async fn load(
+        ctx: &Context,
+        game_mode: GameMode,
+        queue: &[GameModeJoin],
+        parties: &[Vec<i64>],
+    ) -> anyhow::Result<Self> {
+        let mut players = Vec::default();
+        for group in party::group_by_party(queue.iter().collect(), parties, |join_record| {
+            join_record.player_user_id
+        }) {
+            let mut infos = Vec::default();
+            for join_record in group {
+                infos.push(transform::join_record_to_player_info(ctx, join_record).await?);
+            }
+            players.push(infos);
+        }
+        Ok(QueueView { game_mode, players })
+    }
+
+    fn player_count(&self) -> usize {
+        self.players.iter().map(|group| group.len()).sum()
+    }
+}
+
+/// Render queues along with their players and how long they have waited.
+pub fn queues(queues: &[QueueView]) -> Presentation {
+    let mut embed = CreateEmbed::new().title("Queues").colour(NEUTRAL_COLOUR);
+    let mut text = MessageBuilder::default();
+    if queues.is_empty() {
+        embed = embed.description("No queues to show");
+        text.push_italic_line("No queues to show");
+    }
+    for queue in queues.iter().take(MAX_FIELDS) {
+        let name = format!(
+            "{} ({}/{})",
+            queue.game_mode.display_name(),
+            queue.player_count(),
+            queue.game_mode.player_count
+        );
+        let names = queue
+            .players
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|player_info| player_info.to_string())
+                    .join(" + ")
+            })
+            .join(" :small_blue_diamond: ");
+
+        let mut value = MessageBuilder::default();
+        if let Some(description) = &queue.game_mode.description {
+            value.push_italic_line(description);
+        }
+        value.push(if names.is_empty() {
+            "Nobody yet"
+        } else {
+            &names
+        });
+        embed = embed.field(
+            name.clone(),
+            truncate(value.build(), MAX_FIELD_VALUE_LENGTH),
+            false,
+        );
+
+        text.push_bold(&name);
+        if names.is_empty() {
+            text.push_line("");
+        } else {
+            text.push_line(format!(": {}", names));
+        }
+        if let Some(description) = &queue.game_mode.description {
+            text.push_italic_line(description);
+        }
+    }
+    Presentation {
+        embeds: vec![embed],
+        text: text.build(),
+    }
+}
+
+/// A team's captain and players, by name.
+pub struct TeamView {
+    pub captain: Option<String>,
+    /// In the order they were picked
+    pub players: Vec<String>,
+}
+
+/// Everything shown about a pug's teams while (and after) they are picked.
+pub struct RosterView {
+    pub game_mode: String,
+    pub red: TeamView,
+    pub blue: TeamView,
+    /// Players who are not on a team yet, with the members of a party grouped together
+    pub pickable: Vec<Vec<String>>,
+    /// Teams which are still to pick, in order
+    pub pick_order: Vec<Team>,
+    /// Voice channels of the red and blue teams, once picking is complete
+    pub voice_channels: Option<(ChannelId, ChannelId)>,
+}
+
+impl RosterView {
+    /// Gather the roster of a pug, resolving the names of its players.
+    pub async fn load(ctx: &Context, pug: &Pug) -> anyhow::Result<Self> {
+        let name_of = |user_id: i64| async move {
+            UserId::from(user_id as u64)
+                .to_user(ctx)
+                .await
+                .map(|user| user.name)
+                .context("An issue occurred when trying to convert `UserIds` to `User`s")
+        };
+        let mut teams = Vec::default();
+        for team in [Team::Red, Team::Blue] {
+            let captain = match pug.captain(team) {
+                Some(user_id) => Some(name_of(user_id).await?),
+                None => None,
+            };
+            let members = match team {
+                Team::Red => &pug.red_team,
+                Team::Blue => &pug.blue_team,
+            };
+            let mut players = Vec::default();
+            for user_id in members {
+                players.push(name_of(*user_id).await?);
+            }
+            teams.push(TeamView { captain, players });
+        }
+        let blue = teams.pop().expect("Both teams were gathered");
+        let red = teams.pop().expect("Both teams were gathered");
+
+        let mut pickable = Vec::default();
+        for group in party::group_by_party(pug.pickable_players(), &pug.parties, |user_id| *user_id)
+        {
+            let mut names = Vec::default();
+            for user_id in group {
+                names.push(name_of(user_id).await?);
+            }
+            pickable.push(names);
+        }
+        let pickable_count = pickable.iter().map(|group| group.len()).sum::<usize>();
+
+        // the first entry of the pick sequence is for choosing captains,
+        // and the last player left is assigned without a pick
+        let picks_made = pug.red_team.len() + pug.blue_team.len();
+        let pick_order = if pug.has_both_captains() {
+            pug.pick_sequence
+                .iter()
+                .skip(picks_made + 1)
+                .take(pickable_count.saturating_sub(1))
+                .copied()
+                .collect()
+        } else {
+            Vec::default()
+        };
+
+        let voice_channels = pug.voice_chat.as_ref().map(|voice_chat| {
+            (
+                ChannelId::from(voice_chat.red_channel.id as u64),
+                ChannelId::from(voice_chat.blue_channel.id as u64),
+            )
+        });
+
+        Ok(RosterView {
+            game_mode: pug.game_mode.clone(),
+            red,
+            blue,
+            pickable,
+            pick_order,
+            voice_channels,
+        })
+    }
+
+    fn team(&self, team: Team) -> &TeamView {
+        match team {
+            Team::Red => &self.red,
+            Team::Blue => &self.blue,
+        }
+    }
+
+    fn voice_channel(&self, team: Team) -> Option<ChannelId> {
+        self.voice_channels.map(|(red, blue)| match team {
+            Team::Red => red,
+            Team::Blue => blue,
+        })
+    }
+}
+
+/// Render the captains, picked players and voice channels of each team, and while picking,
+/// who is left to pick and the order the captains pick in.
+pub fn roster(roster: &RosterView) -> Presentation {
+    let mut embeds = Vec::default();
+    let mut text = MessageBuilder::default();
+    text.push_bold_line(&roster.game_mode);
+
+    for team in [Team::Red, Team::Blue] {
+        let team_view = roster.team(team);
+
+        let mut description = MessageBuilder::default();
+        match &team_view.captain {
+            Some(captain) => description.push("Captain: ").push_bold_line(captain),
+            None => description.push_italic_line("Waiting for a captain"),
+        };
+        for (position, player) in team_view.players.iter().enumerate() {
+            description.push_line(format!("{}. {}", position + 1, player));
+        }
+        let mut embed = CreateEmbed::new()
+            .title(format!("{} - {}", roster.game_mode, team_title(team)))
+            .description(description.build())
+            .colour(team_colour(team));
+        if let Some(voice_channel) = roster.voice_channel(team) {
+            embed = embed.field("Voice channel", voice_channel.mention().to_string(), false);
+        }
+        embeds.push(embed);
+
+        text.push(format!("{}: ", team_title(team)));
+        let mut members = Vec::default();
+        if let Some(captain) = &team_view.captain {
+            members.push(format!("**{}**", captain));
+        }
+        members.extend(team_view.players.iter().cloned());
+        text.push(members.join(", "));
+        if let Some(voice_channel) = roster.voice_channel(team) {
+            text.push(" - ").mention(&voice_channel);
+        }
+        text.push_line("");
+    }
+
+    if !roster.pickable.is_empty() {
+        // a party is picked as a block
+        let pickable = roster
+            .pickable
+            .iter()
+            .map(|group| group.join(" + "))
+            .join(", ");
+        let pick_order = roster
+            .pick_order
+            .iter()
+            .map(|team| team_emoji(*team))
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let mut embed = CreateEmbed::new()
+            .title("Left to pick")
+            .description(truncate(pickable.clone(), MAX_DESCRIPTION_LENGTH))
+            .colour(NEUTRAL_COLOUR);
+        text.push("Left to pick: ").push_line(pickable);
+        if let Some(next_team) = roster.pick_order.first() {
+            embed = embed.field("Pick order", pick_order.clone(), false);
+            text.push_line(format!(
+                "{} picks next. Pick order: {}",
+                team_title(*next_team),
+                pick_order
+            ));
+        }
+        embeds.push(embed);
+    }
+
+    Presentation {
+        embeds,
+        text: text.build(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    use super::*;
+
+    fn player(name: &str, minutes_queued: i64) -> QueuedPlayerInfo {
+        QueuedPlayerInfo {
+            name: name.to_string(),
+            joined: Utc::now() - Duration::minutes(minutes_queued),
+        }
+    }
+
+    fn embeds(presentation: &Presentation) -> serde_json::Value {
+        serde_json::to_value(&presentation.embeds).unwrap()
+    }
+
+    fn queue_views() -> Vec<QueueView> {
+        vec![
+            QueueView {
+                game_mode: GameMode {
+                    label: "ctf".to_string(),
+                    player_count: 4,
+                    description: Some("Capture the flag".to_string()),
+                    emoji: Some("🚩".to_string()),
+                    ..GameMode::default()
+                },
+                players: vec![
+                    vec![player("alice", 10), player("bob", 10)],
+                    vec![player("carol", 5)],
+                ],
+            },
+            QueueView {
+                game_mode: GameMode {
+                    label: "tdm".to_string(),
+                    player_count: 2,
+                    ..GameMode::default()
+                },
+                players: Vec::default(),
+            },
+        ]
+    }
+
+    #[test]
+    fn queues_as_embeds() {
+        assert_eq!(
+            embeds(&queues(&queue_views())),
+            json!([{
+                "title": "Queues",
+                "type": "rich",
+                "color": NEUTRAL_COLOUR.0,
+                "fields": [
+                    {
+                        "name": "🚩 ctf (3/4)",
+                        "value": "_Capture the flag_\nalice [10m] + bob [10m] :small_blue_diamond: carol [5m]",
+                        "inline": false,
+                    },
+                    {
+                        "name": "tdm (0/2)",
+                        "value": "Nobody yet",
+                        "inline": false,
+                    },
+                ],
+            }])
+        );
+    }
+
+    #[test]
+    fn queues_as_text() {
+        assert_eq!(
+            queues(&queue_views()).text,
+            "**🚩 ctf (3/4)**: alice [10m] + bob [10m] :small_blue_diamond: carol [5m]\n\
+            _Capture the flag_\n\
+            **tdm (0/2)**\n"
+        );
+    }
+
+    #[test]
+    fn no_queues() {
+        let presentation = queues(&[]);
+        assert_eq!(
+            embeds(&presentation),
+            json!([{
+                "title": "Queues",
+                "type": "rich",
+                "description": "No queues to show",
+                "color": NEUTRAL_COLOUR.0,
+            }])
+        );
+        assert_eq!(presentation.text, "_No queues to show_\n");
+    }
+
+    #[test]
+    fn long_queues_are_truncated_to_fit_a_field() {
+        let view = QueueView {
+            game_mode: GameMode {
+                label: "ctf".to_string(),
+                player_count: 200,
+                ..GameMode::default()
+            },
+            players: (0..100)
+                .map(|n| vec![player(&format!("player{}", n), 1)])
+                .collect(),
+        };
+        let presentation = queues(&[view]);
+        let value = embeds(&presentation)[0]["fields"][0]["value"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(value.chars().count(), MAX_FIELD_VALUE_LENGTH);
+        assert!(value.ends_with('…'));
+        // text has no length limit of its own
+        assert!(presentation.text.contains("player99 [1m]"));
+    }
+
+    fn mid_pick() -> RosterView {
+        RosterView {
+            game_mode: "ctf".to_string(),
+            red: TeamView {
+                captain: Some("alice".to_string()),
+                players: vec!["carol".to_string()],
+            },
+            blue: TeamView {
+                captain: Some("bob".to_string()),
+                players: Vec::default(),
+            },
+            pickable: vec![
+                vec!["dave".to_string(), "erin".to_string()],
+                vec!["frank".to_string()],
+            ],
+            pick_order: vec![Team::Blue, Team::Red],
+            voice_channels: None,
+        }
+    }
+
+    #[test]
+    fn roster_mid_pick_as_embeds() {
+        assert_eq!(
+            embeds(&roster(&mid_pick())),
+            json!([
+                {
+                    "title": "ctf - 🔴 Red Team",
+                    "type": "rich",
+                    "description": "Captain: **alice**\n1. carol\n",
+                    "color": RED_TEAM_COLOUR.0,
+                },
+                {
+                    "title": "ctf - 🔵 Blue Team",
+                    "type": "rich",
+                    "description": "Captain: **bob**\n",
+                    "color": BLUE_TEAM_COLOUR.0,
+                },
+                {
+                    "title": "Left to pick",
+                    "type": "rich",
+                    "description": "dave + erin, frank",
+                    "color": NEUTRAL_COLOUR.0,
+                    "fields": [{ "name": "Pick order", "value": "🔵 🔴", "inline": false }],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn roster_mid_pick_as_text() {
+        assert_eq!(
+            roster(&mid_pick()).text,
+            "**ctf**\n\
+            🔴 Red Team: **alice**, carol\n\
+            🔵 Blue Team: **bob**\n\
+            Left to pick: dave + erin, frank\n\
+            🔵 Blue Team picks next. Pick order: 🔵 🔴\n"
+        );
+    }
+
+    #[test]
+    fn roster_waiting_for_a_captain() {
+        let view = RosterView {
+            game_mode: "ctf".to_string(),
+            red: TeamView {
+                captain: Some("alice".to_string()),
+                players: Vec::default(),
+            },
+            blue: TeamView {
+                captain: None,
+                players: Vec::default(),
+            },
+            pickable: vec![vec!["bob".to_string()], vec!["carol".to_string()]],
+            pick_order: Vec::default(),
+            voice_channels: None,
+        };
+        let presentation = roster(&view);
+        assert_eq!(
+            embeds(&presentation)[1]["description"],
+            json!("_Waiting for a captain_\n")
+        );
+        // no pick order until both captains are set
+        assert_eq!(embeds(&presentation)[2].get("fields"), None);
+        assert_eq!(
+            presentation.text,
+            "**ctf**\n\
+            🔴 Red Team: **alice**\n\
+            🔵 Blue Team: \n\
+            Left to pick: bob, carol\n"
+        );
+    }
+
+    fn completed() -> RosterView {
+        RosterView {
+            game_mode: "ctf".to_string(),
+            red: TeamView {
+                captain: Some("alice".to_string()),
+                players: vec!["carol".to_string()],
+            },
+            blue: TeamView {
+                captain: Some("bob".to_string()),
+                players: vec!["dave".to_string()],
+            },
+            pickable: Vec::default(),
+            pick_order: Vec::default(),
+            voice_channels: Some((ChannelId::new(111), ChannelId::new(222))),
+        }
+    }
+
+    #[test]
+    fn completed_roster_as_embeds() {
+        assert_eq!(
+            embeds(&roster(&completed())),
+            json!([
+                {
+                    "title": "ctf - 🔴 Red Team",
+                    "type": "rich",
+                    "description": "Captain: **alice**\n1. carol\n",
+                    "color": RED_TEAM_COLOUR.0,
+                    "fields": [{ "name": "Voice channel", "value": "<#111>", "inline": false }],
+                },
+                {
+                    "title": "ctf - 🔵 Blue Team",
+                    "type": "rich",
+                    "description": "Captain: **bob**\n1. dave\n",
+                    "color": BLUE_TEAM_COLOUR.0,
+                    "fields": [{ "name": "Voice channel", "value": "<#222>", "inline": false }],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn completed_roster_as_text() {
+        assert_eq!(
+            roster(&completed()).text,
+            "**ctf**\n\
+            🔴 Red Team: **alice**, carol - <#111>\n\
+            🔵 Blue Team: **bob**, dave - <#222>\n"
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! A message in the pug channel showing every queue, kept up to date by the bot.
+//!
+//! Anything which changes a queue calls [`refresh`]. Refreshes are debounced per guild, and
+//! the board is only edited when what it shows has changed, to stay well within rate limits.
+//! If the board message is deleted, a new one is posted.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context as AnyhowContext;
+use itertools::Itertools;
+use mongodb::Database;
+use serenity::builder::{CreateActionRow, CreateButton, CreateMessage, EditMessage};
+use serenity::client::Context;
+use serenity::model::application::ButtonStyle;
+use serenity::model::id::{ChannelId, GuildId, MessageId};
+use serenity::prelude::{Mutex, TypeMapKey};
+use tracing::error;
+
+use crate::db;
+use crate::utils::presentation::{self, Presentation, QueueView};
+use crate::DbClientRef;
+
+/// Prefix of the custom id of queue board buttons.
+pub const BUTTON_ID_PREFIX: &str = "queue_board";
+
+/// How long to wait for more changes before updating a board.
+const DEBOUNCE: Duration = Duration::from_secs(3);
+
+/// Discord allows up to 5 rows of 5 buttons, so each row holds the buttons of two game modes.
+const MAX_BUTTON_ROWS: usize = 5;
+const GAME_MODES_PER_ROW: usize = 2;
+
+/// Longest game mode label which still fits in a button's label (80 characters)
+/// and custom id (100 characters).
+const MAX_LABEL_WITH_BUTTONS: usize = 74;
+
+#[derive(Default)]
+pub struct BoardState {
+    /// Whether an update has been scheduled, but not yet started
+    refresh_pending: bool,
+    /// Held while the board is updated, so two updates cannot both post a new board
+    update_lock: Arc<Mutex<()>>,
+    /// The id and text rendering of the board as it was last posted or edited
+    shown: Option<(u64, String)>,
+}
+
+pub struct QueueBoards;
+impl TypeMapKey for QueueBoards {
+    type Value = Arc<Mutex<HashMap<GuildId, BoardState>>>;
+}
+
+/// What a queue board button does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoardAction {
+    Join,
+    Leave,
+}
+
+impl BoardAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BoardAction::Join => "join",
+            BoardAction::Leave => "leave",
+        }
+    }
+}
+
+/// Encode a button's action and game mode into its custom id.
+///
+/// The game mode label goes last, since it might contain the separator.
+pub fn to_custom_id(action: BoardAction, game_mode_label: &str) -> String {
+    format!(
+        "{}|{}|{}",
+        BUTTON_ID_PREFIX,
+        action.as_str(),
+        game_mode_label
+    )
+}
+
+/// Decode a button's action and game mode from its custom id.
+pub fn from_custom_id(custom_id: &str) -> Option<(BoardAction, String)> {
+    let parts = custom_id.splitn(3, '|').collect::<Vec<&str>>();
+    if parts.len() != 3 || parts[0] != BUTTON_ID_PREFIX {
+        return None;
+    }
+    let action = match parts[1] {
+        "join" => BoardAction::Join,
+        "leave" => BoardAction::Leave,
+        _ => return None,
+    };
+    Some((action, parts[2].to_string()))
+}
+
+async fn get_boards(ctx: &Context) -> Arc<Mutex<HashMap<GuildId, BoardState>>> {
+    let data = ctx.data.read().await;
+    data.get::<QueueBoards>()
+        .expect("Expected the queue boards to be available for use")
+        .clone()
+}
+
+/// Schedule an update of a guild's queue board, unless one is already scheduled.
+pub async fn refresh(ctx: &Context, guild_id: GuildId) {
+    let boards = get_boards(ctx).await;
+    {
+        let mut boards = boards.lock().await;
+        let board = boards.entry(guild_id).or_default();
+        if board.refresh_pending {
+            return;
+        }
+        board.refresh_pending = true;
+    }
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(DEBOUNCE).await;
+        let update_lock = {
+            let mut boards = boards.lock().await;
+            let board = boards.entry(guild_id).or_default();
+            // changes from here on need another update
+            board.refresh_pending = false;
+            board.update_lock.clone()
+        };
+        let _update_lock = update_lock.lock().await;
+        if let Err(err) = update(&ctx, guild_id, &boards).await {
+            error!(
+                "Failed to update the queue board of {}: {:?}",
+                guild_id, err
+            );
+        }
+    });
+}
+
+/// Post a new board if the deleted message was the board of its guild.
+pub async fn handle_deleted_message(ctx: &Context, guild_id: GuildId, message_id: MessageId) {
+    let boards = get_boards(ctx).await;
+    {
+        let mut boards = boards.lock().await;
+        let board = match boards.get_mut(&guild_id) {
+            Some(board) => board,
+            None => return,
+        };
+        match &board.shown {
+            Some((shown_id, _)) if *shown_id == message_id.get() => board.shown = None,
+            _ => return,
+        }
+    }
+    refresh(ctx, guild_id).await;
+}
+
+/// Forget the board of a guild, so the next update posts a new one.
+pub async fn forget(ctx: &Context, guild_id: GuildId) {
+    if let Some(board) = get_boards(ctx).await.lock().await.get_mut(&guild_id) {
+        board.shown = None;
+    }
+}
+
+async fn update(
+    ctx: &Context,
+    guild_id: GuildId,
+    boards: &Mutex<HashMap<GuildId, BoardState>>,
+) -> anyhow::Result<()> {
+    let client = {
+        let data = ctx.data.read().await;
+        data.get::<DbClientRef>()
+            .expect("Expected MongoDB's `Client` to be available for use")
+            .clone()
+    };
+    let db = client.database(&guild_id.to_string());
+
+    let pug_channel = match db::read::get_pug_channel(db.clone())
+        .await
+        .context("Failed to read the pug channel")?
+    {
+        Some(pug_channel) => pug_channel,
+        None => return Ok(()),
+    };
+    let channel_id = ChannelId::new(pug_channel.channel_id as u64);
+
+    let (board, components) = render(ctx, db.clone()).await?;
+
+    if let Some(board_message_id) = pug_channel.board_message_id {
+        let board_message_id = board_message_id as u64;
+        let shown = boards
+            .lock()
+            .await
+            .get(&guild_id)
+            .and_then(|board| board.shown.clone());
+        // the text rendering changes exactly when the embeds do
+        if shown == Some((board_message_id, board.text.clone())) {
+            return Ok(());
+        }
+
+        let edit = EditMessage::new()
+            .content("")
+            .embeds(board.embeds.clone())
+            .components(components.clone());
+        match channel_id
+            .edit_message(&ctx.http, MessageId::new(board_message_id), edit)
+            .await
+        {
+            Ok(_) => {
+                remember(boards, guild_id, board_message_id, board.text).await;
+                return Ok(());
+            }
+            // the board was deleted, so a new one is posted below
+            Err(serenity::Error::Http(err))
+                if err.status_code().map(|status| status.as_u16()) == Some(404) => {}
+            Err(err) => {
+                crate::utils::metrics::discord_error(ctx, &err).await;
+                return Err(err).context("Failed to edit the queue board");
+            }
+        }
+    }
+
+    let message = channel_id
+        .send_message(
+            &ctx.http,
+            CreateMessage::new()
+                .embeds(board.embeds)
+                .components(components),
+        )
+        .await
+        .context("Failed to post the queue board")?;
+    db::write::set_queue_board_message(db, channel_id.get(), message.id.get())
+        .await
+        .context("Failed to save the id of the queue board")?;
+    remember(boards, guild_id, message.id.get(), board.text).await;
+    Ok(())
+}
+
+async fn remember(
+    boards: &Mutex<HashMap<GuildId, BoardState>>,
+    guild_id: GuildId,
+    message_id: u64,
+    text: String,
+) {
+    boards.lock().await.entry(guild_id).or_default().shown = Some((message_id, text));
+}
+
+/// Render the board: every game mode which can be joined, with its queued players and how
+/// long they have waited, and buttons to join or leave each of them.
+async fn render(
+    ctx: &Context,
+    db: Database,
+) -> anyhow::Result<(Presentation, Vec<CreateActionRow>)> {
+    let parties = db::read::get_parties(db.clone())
+        .await
+        .context("Tried to get parties for the queue board")?
+        .into_iter()
+        .map(|party| party.members)
+        .collect::<Vec<Vec<i64>>>();
+    let mut queue_views = Vec::default();
+    for (game_mode, queue) in db::read::get_all_queues(db)
+        .await
+        .context("Tried to get all queues for the queue board")?
+    {
+        if game_mode.disabled {
+            continue;
+        }
+        queue_views.push(QueueView::load(ctx, game_mode, &queue, &parties).await?);
+    }
+    queue_views.sort_by(|a, b| a.game_mode.label.cmp(&b.game_mode.label));
+    let board = presentation::queues(&queue_views);
+
+    // custom ids and button labels are limited in length, so very long labels get no buttons
+    let components = queue_views
+        .iter()
+        .map(|queue_view| &queue_view.game_mode)
+        .filter(|game_mode| game_mode.label.len() <= MAX_LABEL_WITH_BUTTONS)
+        .take(MAX_BUTTON_ROWS * GAME_MODES_PER_ROW)
+        .chunks(GAME_MODES_PER_ROW)
+        .into_iter()
+        .map(|game_modes| {
+            CreateActionRow::Buttons(
+                game_modes
+                    .flat_map(|game_mode| {
+                        [
+                            CreateButton::new(to_custom_id(BoardAction::Join, &game_mode.label))
+                                .label(format!("Join {}", game_mode.label))
+                                .style(ButtonStyle::Success),
+                            CreateButton::new(to_custom_id(BoardAction::Leave, &game_mode.label))
+                                .label(format!("Leave {}", game_mode.label))
+                                .style(ButtonStyle::Secondary),
+                        ]
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+
+    Ok((board, components))
+}
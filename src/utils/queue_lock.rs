@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use serenity::client::Context;
+use serenity::model::id::GuildId;
+use serenity::prelude::TypeMapKey;
+use tokio::sync::OwnedMutexGuard;
+
+use super::guild_lock::GuildLocks;
+
+/// One lock per guild, held while its queues are read and changed,
+/// so concurrent joins cannot both take the last slot of a queue.
+///
+/// Locks are per guild rather than per game mode because a filled pug removes its
+/// players from the queues of all game modes, so a fill in one game mode
+/// changes the queues of the others.
+pub struct QueueLocks;
+impl TypeMapKey for QueueLocks {
+    type Value = Arc<GuildLocks>;
+}
+
+/// Wait for, then hold, the queue lock of a guild until the returned guard is dropped.
+pub async fn lock(ctx: &Context, guild_id: GuildId) -> OwnedMutexGuard<()> {
+    let locks = {
+        let data = ctx.data.read().await;
+        data.get::<QueueLocks>()
+            .expect("Expected the queue locks to be available for use")
+            .clone()
+    };
+    locks.lock(guild_id).await
+}
@@ -0,0 +1,126 @@
+//! Match results reported by captains, confirmed by the other captain, and otherwise
+//! escalated for moderators to settle with /setresult.
+
+use anyhow::Context as AnyhowContext;
+use mongodb::Database;
+use serenity::builder::{CreateActionRow, CreateButton};
+use serenity::client::Context;
+use serenity::model::application::ButtonStyle;
+use serenity::model::id::{ChannelId, UserId};
+use serenity::utils::MessageBuilder;
+
+use crate::db;
+use crate::db::model::{MatchOutcome, Pug};
+
+/// Prefix of the custom id of the buttons for confirming or disputing a reported result.
+pub const BUTTON_ID_PREFIX: &str = "result_report";
+
+/// What a result report button does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportAction {
+    Confirm,
+    Dispute,
+}
+
+impl ReportAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReportAction::Confirm => "confirm",
+            ReportAction::Dispute => "dispute",
+        }
+    }
+}
+
+/// Encode a button's action into its custom id. The pug is the one of the thread the
+/// button was posted in.
+pub fn to_custom_id(action: ReportAction) -> String {
+    format!("{}|{}", BUTTON_ID_PREFIX, action.as_str())
+}
+
+/// Decode a button's action from its custom id.
+pub fn from_custom_id(custom_id: &str) -> Option<ReportAction> {
+    match custom_id.split_once('|')? {
+        (BUTTON_ID_PREFIX, "confirm") => Some(ReportAction::Confirm),
+        (BUTTON_ID_PREFIX, "dispute") => Some(ReportAction::Dispute),
+        _ => None,
+    }
+}
+
+/// The buttons with which the other captain confirms or disputes a reported result.
+pub fn buttons() -> CreateActionRow {
+    CreateActionRow::Buttons(vec![
+        CreateButton::new(to_custom_id(ReportAction::Confirm))
+            .label("Confirm")
+            .style(ButtonStyle::Success),
+        CreateButton::new(to_custom_id(ReportAction::Dispute))
+            .label("Dispute")
+            .style(ButtonStyle::Danger),
+    ])
+}
+
+/// Describe an outcome and score, e.g. "Red team won (5-3)".
+pub fn describe(outcome: MatchOutcome, score: Option<&str>) -> String {
+    let outcome = match outcome {
+        MatchOutcome::RedWin => "Red team won",
+        MatchOutcome::BlueWin => "Blue team won",
+        MatchOutcome::Draw => "Draw",
+    };
+    match score {
+        Some(score) => format!("{} ({})", outcome, score),
+        None => outcome.to_string(),
+    }
+}
+
+/// Leave a reported result for moderators to settle, and tell them (and the captains) why,
+/// in the pug's thread and the pug channel.
+///
+/// Returns `false` if the pug changed in the meantime, so nothing was escalated.
+pub async fn escalate(
+    ctx: &Context,
+    db: Database,
+    pug: &Pug,
+    reason: &str,
+) -> anyhow::Result<bool> {
+    let report = match &pug.result_report {
+        Some(report) => report,
+        None => return Ok(false),
+    };
+    if db::write::escalate_result_report(db.clone(), pug)
+        .await
+        .context("Failed to escalate a result report")?
+        .is_none()
+    {
+        return Ok(false);
+    }
+
+    let thread = ChannelId::from(pug.thread_channel_id as u64);
+    let mut notice = MessageBuilder::default();
+    notice
+        .push_bold(&pug.game_mode)
+        .push(" result needs a moderator: ")
+        .push_line(reason)
+        .push("Reported by ")
+        .mention(&UserId::from(report.reporter as u64))
+        .push_line(format!(
+            ": {}",
+            describe(report.outcome, report.score.as_deref())
+        ))
+        .push("Moderators, settle it with /setresult in ")
+        .mention(&thread);
+    let notice = notice.build();
+
+    thread
+        .say(&ctx.http, &notice)
+        .await
+        .context("Failed to post an escalated result in its pug thread")?;
+    if let Some(pug_channel) = db::read::get_pug_channel(db)
+        .await
+        .context("Failed to read the pug channel")?
+    {
+        ChannelId::from(pug_channel.channel_id as u64)
+            .say(&ctx.http, &notice)
+            .await
+            .context("Failed to post an escalated result in the pug channel")?;
+    }
+    Ok(true)
+}
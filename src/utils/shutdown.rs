@@ -0,0 +1,121 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serenity::client::Context;
+use serenity::prelude::TypeMapKey;
+use tokio::sync::{watch, Notify};
+use tokio::time::{timeout_at, Duration, Instant};
+
+/// The reply to interactions which arrive while the bot is shutting down.
+pub const RESTARTING_RESPONSE: &str =
+    "The bot is restarting, so this was not done. Please try again in a minute";
+
+/// Coordinates shutting down: once it begins, no new work is started, and work already
+/// in flight gets a chance to finish.
+pub struct Shutdown {
+    stopping: watch::Sender<bool>,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// Marks a unit of work (a command being handled, a job run) as in flight until dropped.
+pub struct InFlight(Arc<Shutdown>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            stopping: watch::channel(false).0,
+            in_flight: AtomicUsize::new(0),
+            idle: Notify::new(),
+        }
+    }
+
+    /// Start a unit of work, unless shutting down has begun.
+    pub fn track(self: &Arc<Self>) -> Option<InFlight> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        // checked after counting the work, so `drain` cannot miss it
+        if self.is_stopping() {
+            drop(InFlight(self.clone()));
+            return None;
+        }
+        Some(InFlight(self.clone()))
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        *self.stopping.borrow()
+    }
+
+    /// Resolves once shutting down has begun.
+    pub async fn stopping(&self) {
+        let mut stopping = self.stopping.subscribe();
+        // the sender lives as long as `self`, so this cannot fail
+        let _ = stopping.wait_for(|stopping| *stopping).await;
+    }
+
+    /// Begin shutting down, then wait for work in flight to finish.
+    ///
+    /// Returns false if the deadline passed before all of it finished.
+    pub async fn drain(&self, deadline: Duration) -> bool {
+        self.stopping.send_replace(true);
+        let deadline = Instant::now() + deadline;
+        loop {
+            // created before checking, so a notification in between is not missed
+            let idle = self.idle.notified();
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return true;
+            }
+            if timeout_at(deadline, idle).await.is_err() {
+                return false;
+            }
+        }
+    }
+
+    /// How much work is still in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct ShutdownRef;
+impl TypeMapKey for ShutdownRef {
+    type Value = Arc<Shutdown>;
+}
+
+pub async fn get(ctx: &Context) -> Arc<Shutdown> {
+    let data = ctx.data.read().await;
+    data.get::<ShutdownRef>()
+        .expect("Expected the shutdown coordinator to be available for use")
+        .clone()
+}
+
+/// Resolves on ctrl+c, or on SIGTERM (which is how Cloud Run stops the bot).
+pub async fn signalled() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm =
+            signal(SignalKind::terminate()).expect("Could not register SIGTERM handler");
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.expect("Could not register ctrl+c handler"),
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Could not register ctrl+c handler");
+}
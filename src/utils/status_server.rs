@@ -0,0 +1,123 @@
+//! An optional HTTP server for monitoring, enabled by setting `STATUS_SERVER_ADDR`.
+//!
+//! - `/healthz` responds `200 OK` when the bot is connected to Discord and the database is
+//!   reachable, and `503 Service Unavailable` otherwise
+//! - `/metrics` responds with the [metrics](super::metrics) in the Prometheus text format
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use serenity::cache::Cache;
+use serenity::gateway::ConnectionStage;
+use serenity::prelude::{RwLock, TypeMap};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use super::metrics::MetricsRef;
+use crate::{DbClientRef, ShardManagerContainer};
+
+#[derive(Clone)]
+struct StatusState {
+    data: Arc<RwLock<TypeMap>>,
+    cache: Arc<Cache>,
+}
+
+/// Serve `/healthz` and `/metrics` on `addr` until the process exits.
+pub async fn serve(addr: String, data: Arc<RwLock<TypeMap>>, cache: Arc<Cache>) {
+    let app = Router::new()
+        .route("/healthz", get(health))
+        .route("/metrics", get(metrics))
+        .with_state(StatusState { data, cache });
+
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to bind the status server to {}: {:?}", addr, err);
+            return;
+        }
+    };
+    info!("Status server listening on {}", addr);
+    if let Err(err) = axum::serve(listener, app).await {
+        error!("Status server stopped: {:?}", err);
+    }
+}
+
+async fn health(State(state): State<StatusState>) -> (StatusCode, String) {
+    let (shard_manager, db_client) = {
+        let data = state.data.read().await;
+        (
+            data.get::<ShardManagerContainer>().cloned(),
+            data.get::<DbClientRef>().cloned(),
+        )
+    };
+
+    let discord_connected = match shard_manager {
+        Some(shard_manager) => {
+            let runners = shard_manager.runners.lock().await;
+            !runners.is_empty()
+                && runners
+                    .values()
+                    .all(|runner| matches!(runner.stage, ConnectionStage::Connected))
+        }
+        None => false,
+    };
+    let db_reachable = match db_client {
+        Some(client) => crate::db::readiness::ping(&client).await.is_ok(),
+        None => false,
+    };
+
+    let status = if discord_connected && db_reachable {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = format!(
+        "discord: {}\ndatabase: {}\n",
+        if discord_connected {
+            "connected"
+        } else {
+            "disconnected"
+        },
+        if db_reachable {
+            "reachable"
+        } else {
+            "unreachable"
+        }
+    );
+    (status, body)
+}
+
+async fn metrics(State(state): State<StatusState>) -> (StatusCode, String) {
+    let (metrics, db_client) = {
+        let data = state.data.read().await;
+        (
+            data.get::<MetricsRef>().cloned(),
+            data.get::<DbClientRef>().cloned(),
+        )
+    };
+    let metrics = match metrics {
+        Some(metrics) => metrics,
+        None => return (StatusCode::SERVICE_UNAVAILABLE, String::default()),
+    };
+
+    if let Some(client) = db_client {
+        metrics.clear_queue_sizes();
+        for guild_id in state.cache.guilds() {
+            let db = client.database(&guild_id.to_string());
+            match crate::db::read::get_all_queues(db).await {
+                Ok(queues) => metrics.set_queue_sizes(
+                    guild_id,
+                    queues
+                        .iter()
+                        .map(|(game_mode, joins)| (game_mode.label.as_str(), joins.len())),
+                ),
+                Err(err) => error!("Failed to read the queues of {}: {:?}", guild_id, err),
+            }
+        }
+    }
+
+    (StatusCode::OK, metrics.render())
+}
@@ -3,7 +3,7 @@ use std::cmp::max;
 use std::fmt;
 use std::time::SystemTime;
 
-use chrono::{DateTime, Duration, TimeZone, Utc};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 
 pub trait Humanize {
     /// Emits `String` that represents current object in human friendly form
@@ -412,3 +412,10 @@ impl Humanize for SystemTime {
         HumanTime::from(*self).to_string()
     }
 }
+
+/// Midnight (UTC) at the start of `date`.
+pub fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0)
+        .expect("Expected midnight to be a valid time")
+        .and_utc()
+}